@@ -0,0 +1,391 @@
+//! Versioned ciphertext envelope format
+//!
+//! Every ciphertext produced by this crate starts with a self-describing header,
+//! so the format can evolve without breaking data already on the network.
+//!
+//! Header layout (all integers big-endian):
+//!
+//! | Offset | Size | Field                                              |
+//! |--------|------|----------------------------------------------------|
+//! | 0      | 4    | Magic `"CP2P"`                                     |
+//! | 4      | 1    | Format version (currently 1)                       |
+//! | 5      | 1    | Cipher suite id (1 = AES-256-GCM, 2 = XChaCha20-Poly1305) |
+//! | 6      | 1    | Flags (bit 0 = chunked, other bits must be 0)      |
+//! | 7      | 1    | Reserved (must be 0)                               |
+//! | 8      | 8    | Key id (see `EncryptionKey::key_id`)               |
+//! | 16     | 4    | Plaintext chunk size (0 for single blobs)          |
+//! | 20     | 16   | Stream id (chunked envelopes only)                 |
+//!
+//! A single blob is followed by `nonce || ciphertext || tag`, with the header
+//! (followed by any caller-supplied associated data) as associated data.
+//!
+//! A chunked file is followed by its chunk records back to back, each
+//! `nonce || ciphertext || tag`. Every record holds `chunk size` plaintext
+//! bytes except the last one, which may be shorter (but always exists, even
+//! for empty files). The associated data of chunk `i` is
+//! `header || i (u64) || final flag (u8)`, so chunks cannot be reordered,
+//! dropped or moved to another file. Chunk offsets and the original size are
+//! derived from the total length.
+
+use super::encryption::{CipherSuite, STREAM_ID_SIZE};
+use super::CryptoError;
+
+use std::io::Read;
+
+/// Magic bytes at the start of every envelope
+pub const MAGIC: [u8; 4] = *b"CP2P";
+
+/// Current envelope format version
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Size of the key identifier
+pub const KEY_ID_SIZE: usize = 8;
+
+/// Envelope flag: the payload is a chunked file
+pub const FLAG_CHUNKED: u8 = 0x01;
+
+/// Header size shared by all envelopes
+const BASE_HEADER_SIZE: usize = 20;
+
+/// Header size of a chunked envelope
+pub const CHUNKED_HEADER_SIZE: usize = BASE_HEADER_SIZE + STREAM_ID_SIZE;
+
+/// Parsed envelope header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnvelopeHeader {
+    /// Cipher suite of the payload
+    pub suite: CipherSuite,
+
+    /// Identifier of the key the payload was encrypted with
+    pub key_id: [u8; KEY_ID_SIZE],
+
+    /// Plaintext chunk size (0 for single blobs)
+    pub chunk_size: u32,
+
+    /// Stream id (present only for chunked files)
+    pub stream_id: Option<[u8; STREAM_ID_SIZE]>,
+}
+
+impl EnvelopeHeader {
+    /// Header for a single encrypted blob
+    pub fn blob(suite: CipherSuite, key_id: [u8; KEY_ID_SIZE]) -> Self {
+        Self {
+            suite,
+            key_id,
+            chunk_size: 0,
+            stream_id: None,
+        }
+    }
+
+    /// Header for a chunked file
+    pub fn chunked(
+        suite: CipherSuite,
+        key_id: [u8; KEY_ID_SIZE],
+        chunk_size: usize,
+        stream_id: [u8; STREAM_ID_SIZE],
+    ) -> Result<Self, CryptoError> {
+        let chunk_size = u32::try_from(chunk_size)
+            .ok()
+            .filter(|size| *size > 0)
+            .ok_or_else(|| CryptoError::InvalidEnvelope(format!("Invalid chunk size {}", chunk_size)))?;
+
+        Ok(Self {
+            suite,
+            key_id,
+            chunk_size,
+            stream_id: Some(stream_id),
+        })
+    }
+
+    /// Is this the header of a chunked file?
+    pub fn is_chunked(&self) -> bool {
+        self.stream_id.is_some()
+    }
+
+    /// Encoded header length
+    pub fn encoded_len(&self) -> usize {
+        if self.is_chunked() {
+            CHUNKED_HEADER_SIZE
+        } else {
+            BASE_HEADER_SIZE
+        }
+    }
+
+    /// Encode the header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.push(self.suite.id());
+        bytes.push(if self.is_chunked() { FLAG_CHUNKED } else { 0 });
+        bytes.push(0);
+        bytes.extend_from_slice(&self.key_id);
+        bytes.extend_from_slice(&self.chunk_size.to_be_bytes());
+        if let Some(stream_id) = &self.stream_id {
+            bytes.extend_from_slice(stream_id);
+        }
+        bytes
+    }
+
+    /// Parse a header from the start of `bytes`
+    /// Returns the header and the offset where the payload starts
+    pub fn parse(bytes: &[u8]) -> Result<(Self, usize), CryptoError> {
+        if bytes.len() < BASE_HEADER_SIZE {
+            return Err(CryptoError::InvalidEnvelope("Envelope too short".into()));
+        }
+
+        let base: &[u8; BASE_HEADER_SIZE] = bytes[..BASE_HEADER_SIZE].try_into().unwrap();
+        let chunked = Self::parse_base(base)?;
+
+        let mut header = Self::from_base(base);
+        if chunked {
+            let stream_id = bytes
+                .get(BASE_HEADER_SIZE..CHUNKED_HEADER_SIZE)
+                .ok_or_else(|| CryptoError::InvalidEnvelope("Envelope too short".into()))?;
+            header.stream_id = Some(stream_id.try_into().unwrap());
+        }
+
+        header.validate()?;
+        Ok((header, header.encoded_len()))
+    }
+
+    /// Read a header from the start of a stream
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, CryptoError> {
+        let mut base = [0u8; BASE_HEADER_SIZE];
+        reader
+            .read_exact(&mut base)
+            .map_err(|e| CryptoError::InvalidEnvelope(format!("Failed to read header: {}", e)))?;
+        let chunked = Self::parse_base(&base)?;
+
+        let mut header = Self::from_base(&base);
+        if chunked {
+            let mut stream_id = [0u8; STREAM_ID_SIZE];
+            reader
+                .read_exact(&mut stream_id)
+                .map_err(|e| CryptoError::InvalidEnvelope(format!("Failed to read header: {}", e)))?;
+            header.stream_id = Some(stream_id);
+        }
+
+        header.validate()?;
+        Ok(header)
+    }
+
+    /// Check magic, version, suite and flags; returns the chunked flag
+    fn parse_base(base: &[u8; BASE_HEADER_SIZE]) -> Result<bool, CryptoError> {
+        if base[..4] != MAGIC {
+            return Err(CryptoError::InvalidEnvelope("Bad magic bytes".into()));
+        }
+        if base[4] != FORMAT_VERSION {
+            return Err(CryptoError::UnsupportedVersion(base[4]));
+        }
+        if CipherSuite::from_id(base[5]).is_none() {
+            return Err(CryptoError::InvalidEnvelope(format!("Unknown cipher suite id {}", base[5])));
+        }
+        if base[6] & !FLAG_CHUNKED != 0 || base[7] != 0 {
+            return Err(CryptoError::InvalidEnvelope(format!("Unknown flags {:#04x}", base[6])));
+        }
+        Ok(base[6] & FLAG_CHUNKED != 0)
+    }
+
+    /// Build a header from an already checked base header
+    fn from_base(base: &[u8; BASE_HEADER_SIZE]) -> Self {
+        Self {
+            suite: CipherSuite::from_id(base[5]).unwrap_or_default(),
+            key_id: base[8..16].try_into().unwrap(),
+            chunk_size: u32::from_be_bytes(base[16..20].try_into().unwrap()),
+            stream_id: None,
+        }
+    }
+
+    /// Chunked files need a chunk size, blobs must not have one
+    fn validate(&self) -> Result<(), CryptoError> {
+        if self.is_chunked() == (self.chunk_size == 0) {
+            return Err(CryptoError::InvalidEnvelope(format!(
+                "Invalid chunk size {}",
+                self.chunk_size
+            )));
+        }
+        Ok(())
+    }
+
+    /// Size of one full encrypted chunk record
+    pub fn record_size(&self) -> usize {
+        self.chunk_size as usize + self.suite.overhead()
+    }
+
+    /// Derive chunk offsets and original size from the length of the chunk data
+    pub fn chunk_layout(&self, data_len: usize) -> Result<(Vec<usize>, usize), CryptoError> {
+        let record_size = self.record_size();
+        let overhead = self.suite.overhead();
+
+        if data_len == 0 {
+            return Err(CryptoError::TamperedChunkLayout("Missing final chunk".into()));
+        }
+
+        let chunk_count = data_len.div_ceil(record_size);
+        let last_record = data_len - (chunk_count - 1) * record_size;
+        if last_record < overhead {
+            return Err(CryptoError::InvalidEnvelope("Truncated chunk record".into()));
+        }
+
+        let chunk_offsets = (0..chunk_count).map(|i| i * record_size).collect();
+        let original_size = (chunk_count - 1) * self.chunk_size as usize + last_record - overhead;
+
+        Ok((chunk_offsets, original_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{EncryptedFile, EncryptionKey, FileEncryptor};
+
+    /// Fixed key used by the golden vectors
+    fn golden_key() -> EncryptionKey {
+        EncryptionKey::new([0x42; 32])
+    }
+
+    #[test]
+    fn test_header_layout() {
+        let header =
+            EnvelopeHeader::chunked(CipherSuite::XChaCha20Poly1305, [0xAB; 8], 65536, [0x11; 16]).unwrap();
+        let bytes = header.to_bytes();
+
+        assert_eq!(
+            hex::encode(&bytes),
+            "4350325001020100abababababababab0001000011111111111111111111111111111111"
+        );
+        assert_eq!(EnvelopeHeader::parse(&bytes).unwrap(), (header, CHUNKED_HEADER_SIZE));
+
+        let blob = EnvelopeHeader::blob(CipherSuite::Aes256Gcm, [0xAB; 8]);
+        assert_eq!(hex::encode(blob.to_bytes()), "4350325001010000abababababababab00000000");
+    }
+
+    #[test]
+    fn test_unknown_version_rejected() {
+        let mut bytes = EnvelopeHeader::blob(CipherSuite::Aes256Gcm, [0; 8]).to_bytes();
+        bytes[4] = 9;
+
+        assert!(matches!(
+            EnvelopeHeader::parse(&bytes),
+            Err(CryptoError::UnsupportedVersion(9))
+        ));
+    }
+
+    #[test]
+    fn test_malformed_headers_rejected() {
+        let good = EnvelopeHeader::blob(CipherSuite::Aes256Gcm, [0; 8]).to_bytes();
+
+        let mut bad_magic = good.clone();
+        bad_magic[0] = b'X';
+        let mut bad_suite = good.clone();
+        bad_suite[5] = 0x7F;
+        let mut bad_flags = good.clone();
+        bad_flags[6] = 0x80;
+        let mut chunked_without_stream_id = good.clone();
+        chunked_without_stream_id[6] = FLAG_CHUNKED;
+
+        for bytes in [&bad_magic, &bad_suite, &bad_flags, &chunked_without_stream_id, &good[..10].to_vec()] {
+            assert!(matches!(
+                EnvelopeHeader::parse(bytes),
+                Err(CryptoError::InvalidEnvelope(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_golden_blob() {
+        let key = golden_key();
+        let header = EnvelopeHeader::blob(CipherSuite::Aes256Gcm, key.key_id());
+        let envelope = key
+            .encrypt_envelope_with_nonce(&header, &[0x24; 12], b"LibreDrive", &[])
+            .unwrap();
+
+        assert_eq!(hex::encode(&envelope), GOLDEN_BLOB);
+        assert_eq!(
+            key.decrypt(&hex::decode(GOLDEN_BLOB).unwrap()).unwrap(),
+            b"LibreDrive"
+        );
+    }
+
+    #[test]
+    fn test_golden_chunked_file() {
+        let key = golden_key();
+        let data = hex::decode(GOLDEN_CHUNKED_FILE).unwrap();
+
+        let encrypted = EncryptedFile::from_bytes(&data).unwrap();
+        assert_eq!(encrypted.chunk_size, 16);
+        assert_eq!(encrypted.chunk_count(), 3);
+        assert_eq!(encrypted.original_size, 40);
+        assert_eq!(encrypted.suite, CipherSuite::XChaCha20Poly1305);
+        assert_eq!(encrypted.to_bytes().unwrap(), data);
+
+        let plaintext = FileEncryptor::new(key).decrypt_file(&encrypted).unwrap();
+        assert_eq!(plaintext, b"The quick brown fox jumps over the lazy!");
+    }
+
+    #[test]
+    fn test_legacy_blob() {
+        let key = golden_key();
+        assert_eq!(
+            key.decrypt(&hex::decode(LEGACY_BLOB).unwrap()).unwrap(),
+            b"LibreDrive"
+        );
+        assert!(EncryptionKey::generate().decrypt(&hex::decode(LEGACY_BLOB).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_legacy_chunked_file() {
+        let data = hex::decode(LEGACY_CHUNKED_FILE).unwrap();
+
+        let encrypted = EncryptedFile::from_bytes(&data).unwrap();
+        assert!(encrypted.is_legacy());
+        assert_eq!(encrypted.chunk_count(), 3);
+        assert_eq!(encrypted.original_size, 40);
+        assert_eq!(encrypted.to_bytes().unwrap(), data);
+
+        let encryptor = FileEncryptor::new(golden_key());
+        assert_eq!(
+            encryptor.decrypt_file(&encrypted).unwrap(),
+            b"The quick brown fox jumps over the lazy!"
+        );
+        assert_eq!(encryptor.decrypt_chunk(&encrypted, 1).unwrap(), b"fox jumps over t");
+        assert!(FileEncryptor::new(EncryptionKey::generate()).decrypt_file(&encrypted).is_err());
+    }
+
+    /// "LibreDrive" sealed with the golden key, AES-256-GCM and nonce [0x24; 12]
+    const GOLDEN_BLOB: &str = concat!(
+        "43503250010100001beed4b8942815a500000000242424242424242424242424",
+        "59f8a6338c82b457508d5b94224a5ed65d5039de92cdf497d9c7",
+    );
+
+    /// 40-byte file in 16-byte XChaCha20-Poly1305 chunks, stream id [0x11; 16],
+    /// chunk i sealed with nonce [i + 1; 24]
+    const GOLDEN_CHUNKED_FILE: &str = concat!(
+        "43503250010201001beed4b8942815a500000010111111111111111111111111",
+        "1111111101010101010101010101010101010101010101010101010133804196",
+        "117553f3490f926d0ea9290248b3afd36d1a56ff68cbd92fa993e33602020202",
+        "0202020202020202020202020202020202020202ead3392466aa545f6981afa3",
+        "5a52e2d1c32333f4e10232045041e0d5ded9347b030303030303030303030303",
+        "0303030303030303030303031da3cbe73bdbb2ad2449291044b90b47a545e6d6",
+        "fc642f31",
+    );
+
+    /// "LibreDrive" as encrypted before the envelope format: AES-256-GCM
+    /// `nonce || ciphertext || tag` with the golden key and nonce [0x24; 12]
+    const LEGACY_BLOB: &str = concat!(
+        "24242424242424242424242459f8a6338c82b457508d23aec4ae1a9feb4e7cfe",
+        "4fd8a80081bb",
+    );
+
+    /// Bincode `EncryptedFile` from before the envelope format: the same 40-byte
+    /// file in 16-byte chunks, chunk i a legacy blob with nonce [i + 1; 12]
+    const LEGACY_CHUNKED_FILE: &str = concat!(
+        "7c0000000000000001010101010101010101010179c1d327e521e8aeb4768bbe",
+        "8860d5eb00eac60a2931dac484af821a54ae0e0d020202020202020202020202",
+        "948a7e22b6893af3de8afc47af94938b65ffc0cce4423c16c076b8abf4fe0086",
+        "030303030303030303030303b909127cbd704a9bce8efdc5af0dd9a92d90e74a",
+        "cd1a5013030000000000000000000000000000002c0000000000000058000000",
+        "0000000028000000000000001000000000000000",
+    );
+}
@@ -17,6 +17,11 @@ impl ContentHash {
         Self(*hash.as_bytes())
     }
 
+    /// Keyed hash (a MAC): equal data only gives equal hashes under the same key
+    pub fn keyed_hash(key: &[u8; 32], data: &[u8]) -> Self {
+        Self(*blake3::keyed_hash(key, data).as_bytes())
+    }
+
     /// Create from raw bytes
     pub fn from_bytes(bytes: [u8; 32]) -> Self {
         Self(bytes)
@@ -131,7 +136,19 @@ pub fn hash_file_chunked<R: std::io::Read>(reader: &mut R, chunk_size: usize) ->
     Ok(hasher.finalize())
 }
 
+/// Domain prefix of Merkle leaf hashes
+const LEAF_PREFIX: u8 = 0x00;
+
+/// Domain prefix of Merkle inner nodes with two children
+const NODE_PREFIX: u8 = 0x01;
+
+/// Domain prefix of Merkle inner nodes with a single (odd) child
+const LONE_NODE_PREFIX: u8 = 0x02;
+
 /// Merkle tree node for verifying file chunks
+///
+/// Leaves, inner nodes and lone odd nodes are hashed in separate domains,
+/// so a leaf can never be passed off as an inner node (or the other way round).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MerkleTree {
     /// Root hash of the tree
@@ -141,10 +158,21 @@ pub struct MerkleTree {
     pub leaves: Vec<ContentHash>,
 }
 
+/// Audit path proving that a chunk belongs to a Merkle tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Number of leaves in the tree
+    pub leaf_count: usize,
+
+    /// Sibling hashes from the leaf level up to the root
+    /// (levels where the node has no sibling are skipped)
+    pub path: Vec<ContentHash>,
+}
+
 impl MerkleTree {
     /// Build a Merkle tree from chunks
     pub fn build(chunks: &[&[u8]]) -> Self {
-        let leaves: Vec<ContentHash> = chunks.iter().map(|c| ContentHash::hash(c)).collect();
+        let leaves: Vec<ContentHash> = chunks.iter().map(|c| hash_leaf(c)).collect();
         let root = Self::compute_root(&leaves);
 
         Self { root, leaves }
@@ -155,29 +183,11 @@ impl MerkleTree {
         if leaves.is_empty() {
             return ContentHash::hash(&[]);
         }
-        if leaves.len() == 1 {
-            return leaves[0];
-        }
 
         let mut current_level = leaves.to_vec();
 
         while current_level.len() > 1 {
-            let mut next_level = Vec::new();
-
-            for pair in current_level.chunks(2) {
-                let combined = if pair.len() == 2 {
-                    let mut data = Vec::with_capacity(64);
-                    data.extend_from_slice(pair[0].as_bytes());
-                    data.extend_from_slice(pair[1].as_bytes());
-                    ContentHash::hash(&data)
-                } else {
-                    // Odd number of nodes, promote the last one
-                    pair[0]
-                };
-                next_level.push(combined);
-            }
-
-            current_level = next_level;
+            current_level = next_level(&current_level);
         }
 
         current_level[0]
@@ -189,8 +199,33 @@ impl MerkleTree {
             return false;
         }
 
-        let chunk_hash = ContentHash::hash(chunk);
-        chunk_hash == self.leaves[index]
+        hash_leaf(chunk) == self.leaves[index]
+    }
+
+    /// Build the audit path for the chunk at `index`
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.leaves.len() {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        let mut current_level = self.leaves.clone();
+        let mut position = index;
+
+        while current_level.len() > 1 {
+            let sibling = position ^ 1;
+            if sibling < current_level.len() {
+                path.push(current_level[sibling]);
+            }
+
+            current_level = next_level(&current_level);
+            position /= 2;
+        }
+
+        Some(MerkleProof {
+            leaf_count: self.leaves.len(),
+            path,
+        })
     }
 
     /// Get the number of leaves
@@ -199,6 +234,78 @@ impl MerkleTree {
     }
 }
 
+/// Verify that `leaf_data` is the chunk at `index` of the tree with the given root
+/// Only the root is needed, not the full tree
+pub fn verify_proof(root: &ContentHash, index: usize, leaf_data: &[u8], proof: &MerkleProof) -> bool {
+    if index >= proof.leaf_count {
+        return false;
+    }
+
+    let mut hash = hash_leaf(leaf_data);
+    let mut siblings = proof.path.iter();
+    let mut position = index;
+    let mut level_len = proof.leaf_count;
+
+    while level_len > 1 {
+        hash = if position ^ 1 < level_len {
+            let sibling = match siblings.next() {
+                Some(sibling) => sibling,
+                None => return false,
+            };
+            if position.is_multiple_of(2) {
+                hash_node(&hash, sibling)
+            } else {
+                hash_node(sibling, &hash)
+            }
+        } else {
+            hash_lone_node(&hash)
+        };
+
+        position /= 2;
+        level_len = level_len.div_ceil(2);
+    }
+
+    // A valid proof uses every sibling exactly once
+    siblings.next().is_none() && hash == *root
+}
+
+/// Hash the next level of a Merkle tree
+fn next_level(level: &[ContentHash]) -> Vec<ContentHash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [lone] => hash_lone_node(lone),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Hash a chunk into a Merkle leaf
+fn hash_leaf(chunk: &[u8]) -> ContentHash {
+    let mut hasher = Hasher::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(chunk);
+    ContentHash(*hasher.finalize().as_bytes())
+}
+
+/// Hash two children into an inner node
+fn hash_node(left: &ContentHash, right: &ContentHash) -> ContentHash {
+    let mut hasher = Hasher::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    ContentHash(*hasher.finalize().as_bytes())
+}
+
+/// Hash an odd node that has no sibling on its level
+fn hash_lone_node(node: &ContentHash) -> ContentHash {
+    let mut hasher = Hasher::new();
+    hasher.update(&[LONE_NODE_PREFIX]);
+    hasher.update(node.as_bytes());
+    ContentHash(*hasher.finalize().as_bytes())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -272,4 +379,58 @@ mod tests {
         assert_eq!(tree.leaf_count(), 3);
         assert!(tree.verify_chunk(b"chunk 3", 2));
     }
+
+    #[test]
+    fn test_merkle_proofs() {
+        let data: Vec<Vec<u8>> = (0..7).map(|i| format!("chunk {}", i).into_bytes()).collect();
+
+        // Check every leaf of trees with even and odd levels
+        for count in 1..=data.len() {
+            let chunks: Vec<&[u8]> = data[..count].iter().map(|c| c.as_slice()).collect();
+            let tree = MerkleTree::build(&chunks);
+
+            for (index, chunk) in chunks.iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert!(verify_proof(&tree.root, index, chunk, &proof));
+                assert!(!verify_proof(&tree.root, index, b"wrong chunk", &proof));
+            }
+            assert!(tree.proof(count).is_none());
+        }
+    }
+
+    #[test]
+    fn test_merkle_proof_rejects_wrong_position() {
+        let chunks: Vec<&[u8]> = vec![b"chunk 1", b"chunk 2", b"chunk 3", b"chunk 4", b"chunk 5"];
+        let tree = MerkleTree::build(&chunks);
+        let proof = tree.proof(1).unwrap();
+
+        assert!(!verify_proof(&tree.root, 0, b"chunk 2", &proof));
+        assert!(!verify_proof(&tree.root, 7, b"chunk 2", &proof));
+
+        // Extra or missing path entries are rejected
+        let mut long = proof.clone();
+        long.path.push(tree.root);
+        assert!(!verify_proof(&tree.root, 1, b"chunk 2", &long));
+
+        let mut short = proof.clone();
+        short.path.pop();
+        assert!(!verify_proof(&tree.root, 1, b"chunk 2", &short));
+    }
+
+    #[test]
+    fn test_merkle_domain_separation() {
+        let chunks: Vec<&[u8]> = vec![b"chunk 1", b"chunk 2"];
+        let tree = MerkleTree::build(&chunks);
+
+        // An inner node presented as a leaf must not verify against the root
+        let mut inner = Vec::new();
+        inner.extend_from_slice(tree.leaves[0].as_bytes());
+        inner.extend_from_slice(tree.leaves[1].as_bytes());
+        let single = MerkleProof { leaf_count: 1, path: vec![] };
+        assert!(!verify_proof(&tree.root, 0, &inner, &single));
+
+        // A single chunk's root differs from its plain content hash
+        let single_tree = MerkleTree::build(&[b"chunk 1"]);
+        assert_ne!(single_tree.root, ContentHash::hash(b"chunk 1"));
+    }
 }
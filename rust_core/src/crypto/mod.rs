@@ -1,15 +1,27 @@
 //! Cryptography Module - End-to-end encryption for CloudP2P
 //!
-//! Provides AES-256-GCM encryption, Ed25519 signatures, and secure key derivation.
+//! Provides AES-256-GCM / XChaCha20-Poly1305 encryption, Ed25519 signatures
+//! and signed envelopes, X25519 sealed boxes, and secure key derivation.
 
 pub mod encryption;
+pub mod envelope;
 mod hashing;
+pub mod sealed_box;
+pub mod signed;
+mod stream;
 
-pub use encryption::{EncryptionKey, FileEncryptor, EncryptedFile};
-pub use hashing::ContentHash;
+pub use encryption::{CipherSuite, EncryptionKey, FileEncryptor, EncryptedFile};
+pub use envelope::EnvelopeHeader;
+pub use hashing::{verify_proof, ContentHash, MerkleProof, MerkleTree};
+pub use sealed_box::AgreementKeyPair;
+pub use signed::{Signed, SignedPayload};
+pub use stream::{ChunkTable, DecryptingReader};
 
 use ed25519_dalek::{SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use thiserror::Error;
+use zeroize::Zeroizing;
 
 #[derive(Error, Debug)]
 pub enum CryptoError {
@@ -27,10 +39,22 @@ pub enum CryptoError {
 
     #[error("Signature verification failed")]
     SignatureVerificationFailed,
+
+    #[error("Chunk layout tampered: {0}")]
+    TamperedChunkLayout(String),
+
+    #[error("IO error: {0}")]
+    Io(String),
+
+    #[error("Invalid envelope: {0}")]
+    InvalidEnvelope(String),
+
+    #[error("Unsupported envelope version: {0}")]
+    UnsupportedVersion(u8),
 }
 
 /// Signing key pair (Ed25519)
-#[derive(Clone)]
+/// The signing key is wiped from memory on drop
 pub struct SigningKeyPair {
     pub signing_key: SigningKey,
     pub verifying_key: VerifyingKey,
@@ -60,6 +84,14 @@ impl SigningKeyPair {
     }
 }
 
+impl fmt::Debug for SigningKeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKeyPair")
+            .field("verifying_key", &hex::encode(self.verifying_key.as_bytes()))
+            .finish_non_exhaustive()
+    }
+}
+
 /// Secure random bytes generator
 pub fn random_bytes(len: usize) -> Vec<u8> {
     use rand::RngCore;
@@ -76,28 +108,51 @@ pub fn random_32_bytes() -> [u8; 32] {
     bytes
 }
 
+/// Argon2id cost parameters for password-based key derivation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+
+    /// Number of passes over memory
+    pub iterations: u32,
+
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    /// Argon2id recommended parameters (19 MiB, 2 passes, 1 lane)
+    fn default() -> Self {
+        Self {
+            memory_kib: argon2::Params::DEFAULT_M_COST,
+            iterations: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
 /// Derive a key from password using Argon2id
-pub fn derive_key_from_password(password: &[u8], salt: &[u8]) -> Result<[u8; 32], CryptoError> {
-    use argon2::{Argon2, PasswordHasher};
-    use argon2::password_hash::SaltString;
+pub fn derive_key_from_password(password: &[u8], salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, CryptoError> {
+    derive_key_from_password_with_params(password, salt, &KdfParams::default())
+}
 
-    // Argon2id with recommended parameters
-    let argon2 = Argon2::default();
+/// Derive a key from password using Argon2id with the given cost parameters
+pub fn derive_key_from_password_with_params(
+    password: &[u8],
+    salt: &[u8],
+    params: &KdfParams,
+) -> Result<Zeroizing<[u8; 32]>, CryptoError> {
+    use argon2::{Algorithm, Argon2, Params, Version};
 
-    // Create salt string (needs to be valid base64)
-    let salt_b64 = base64::Engine::encode(&base64::engine::general_purpose::STANDARD_NO_PAD, salt);
-    let salt_str = SaltString::from_b64(&salt_b64)
+    let params = Params::new(params.memory_kib, params.iterations, params.parallelism, Some(32))
         .map_err(|e| CryptoError::InvalidKey(e.to_string()))?;
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
 
-    let hash = argon2
-        .hash_password(password, &salt_str)
+    let mut key = Zeroizing::new([0u8; 32]);
+    argon2
+        .hash_password_into(password, salt, key.as_mut())
         .map_err(|e| CryptoError::InvalidKey(e.to_string()))?;
-
-    let hash_bytes = hash.hash.ok_or(CryptoError::InvalidKey("No hash output".into()))?;
-    let bytes = hash_bytes.as_bytes();
-
-    let mut key = [0u8; 32];
-    key.copy_from_slice(&bytes[..32]);
     Ok(key)
 }
 
@@ -131,4 +186,22 @@ mod tests {
         assert!(keypair.verify(message, &signature).is_ok());
         assert!(keypair.verify(b"Wrong message", &signature).is_err());
     }
+
+    #[test]
+    fn test_derive_key_from_password_with_params() {
+        let params = KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let salt = b"0123456789abcdef";
+
+        let key1 = derive_key_from_password_with_params(b"password", salt, &params).unwrap();
+        let key2 = derive_key_from_password_with_params(b"password", salt, &params).unwrap();
+        assert_eq!(key1, key2);
+
+        let other_cost = KdfParams { iterations: 2, ..params };
+        assert_ne!(key1, derive_key_from_password_with_params(b"password", salt, &other_cost).unwrap());
+        assert_ne!(key1, derive_key_from_password_with_params(b"passw0rd", salt, &params).unwrap());
+    }
 }
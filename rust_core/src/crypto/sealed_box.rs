@@ -0,0 +1,148 @@
+//! Anonymous sealed boxes
+//!
+//! Encrypts a message to a recipient's X25519 public key: a fresh ephemeral key
+//! agrees a shared secret with the recipient, HKDF turns it into an AEAD key,
+//! and the ephemeral public key travels in front of the ciphertext. Only the
+//! recipient can open the box, and nothing in it identifies the sender.
+//!
+//! Layout: ephemeral public key (32 bytes) || single-blob envelope. The AEAD
+//! also authenticates both public keys.
+
+use super::{CipherSuite, CryptoError, EncryptionKey};
+
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::fmt;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// Size of an X25519 public key
+pub const PUBLIC_KEY_SIZE: usize = 32;
+
+/// HKDF label for sealed box keys
+const SEALED_BOX_INFO: &[u8] = b"cloudp2p sealed box v1";
+
+/// X25519 key-agreement key pair
+/// The secret key is wiped from memory on drop
+pub struct AgreementKeyPair {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl AgreementKeyPair {
+    /// Build a key pair from 32 secret bytes
+    pub fn from_secret_bytes(bytes: [u8; 32]) -> Self {
+        let secret = StaticSecret::from(bytes);
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    /// Get the public key
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public
+    }
+
+    /// Open a sealed box addressed to this key pair
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if sealed.len() < PUBLIC_KEY_SIZE {
+            return Err(CryptoError::InvalidData("Sealed box too short".into()));
+        }
+        let (ephemeral, ciphertext) = sealed.split_at(PUBLIC_KEY_SIZE);
+        let ephemeral: [u8; PUBLIC_KEY_SIZE] = ephemeral.try_into().unwrap();
+        let ephemeral = PublicKey::from(ephemeral);
+
+        let shared = self.secret.diffie_hellman(&ephemeral);
+        if !shared.was_contributory() {
+            return Err(CryptoError::InvalidKey("Low-order ephemeral key".into()));
+        }
+
+        let key = box_key(shared.as_bytes(), &ephemeral, &self.public)?;
+        key.decrypt_with_aad(ciphertext, &box_aad(&ephemeral, &self.public))
+    }
+}
+
+impl fmt::Debug for AgreementKeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgreementKeyPair")
+            .field("public_key", &hex::encode(self.public.as_bytes()))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Seal a message (e.g. a file key) to a recipient's public key
+pub fn seal(recipient: &PublicKey, plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let ephemeral_secret = StaticSecret::random_from_rng(rand::thread_rng());
+    let ephemeral = PublicKey::from(&ephemeral_secret);
+
+    let shared = ephemeral_secret.diffie_hellman(recipient);
+    if !shared.was_contributory() {
+        return Err(CryptoError::InvalidKey("Low-order recipient key".into()));
+    }
+
+    let key = box_key(shared.as_bytes(), &ephemeral, recipient)?;
+    let ciphertext =
+        key.encrypt_envelope_with_aad(CipherSuite::XChaCha20Poly1305, plaintext, &box_aad(&ephemeral, recipient))?;
+
+    let mut sealed = Vec::with_capacity(PUBLIC_KEY_SIZE + ciphertext.len());
+    sealed.extend_from_slice(ephemeral.as_bytes());
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Derive the box key from the shared secret, bound to both public keys
+fn box_key(shared: &[u8; 32], ephemeral: &PublicKey, recipient: &PublicKey) -> Result<EncryptionKey, CryptoError> {
+    let hk = Hkdf::<Sha256>::new(Some(&box_aad(ephemeral, recipient)), shared);
+    let mut key = Zeroizing::new([0u8; 32]);
+    hk.expand(SEALED_BOX_INFO, key.as_mut())
+        .map_err(|e| CryptoError::InvalidKey(e.to_string()))?;
+    Ok(EncryptionKey::new(*key))
+}
+
+/// Ephemeral public key || recipient public key
+fn box_aad(ephemeral: &PublicKey, recipient: &PublicKey) -> Vec<u8> {
+    [ephemeral.as_bytes().as_slice(), recipient.as_bytes()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::random_32_bytes;
+
+    #[test]
+    fn test_seal_open_roundtrip() {
+        let recipient = AgreementKeyPair::from_secret_bytes(random_32_bytes());
+        let file_key = random_32_bytes();
+
+        let sealed = seal(recipient.public_key(), &file_key).unwrap();
+        assert_eq!(recipient.open(&sealed).unwrap(), file_key);
+
+        // Each box uses a fresh ephemeral key
+        assert_ne!(sealed, seal(recipient.public_key(), &file_key).unwrap());
+    }
+
+    #[test]
+    fn test_wrong_recipient_or_tampering_rejected() {
+        let recipient = AgreementKeyPair::from_secret_bytes(random_32_bytes());
+        let other = AgreementKeyPair::from_secret_bytes(random_32_bytes());
+        let sealed = seal(recipient.public_key(), b"file key").unwrap();
+
+        assert!(other.open(&sealed).is_err());
+
+        let mut swapped = sealed.clone();
+        swapped[..PUBLIC_KEY_SIZE].copy_from_slice(other.public_key().as_bytes());
+        assert!(recipient.open(&swapped).is_err());
+
+        let mut corrupted = sealed;
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        assert!(recipient.open(&corrupted).is_err());
+
+        assert!(recipient.open(&[0u8; 10]).is_err());
+    }
+
+    #[test]
+    fn test_low_order_key_rejected() {
+        let low_order = PublicKey::from([0u8; 32]);
+        assert!(seal(&low_order, b"file key").is_err());
+    }
+}
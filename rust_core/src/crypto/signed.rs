@@ -0,0 +1,209 @@
+//! Typed signed envelopes
+//!
+//! Every signed structure is encoded the same way: a fixed prefix, the
+//! payload type's context string, then the bincode encoding of the signed
+//! fields. Bincode length-prefixes strings and byte vectors, so fields can
+//! never run into each other, and the context keeps a signature made for one
+//! type from being accepted as another.
+
+use super::{CryptoError, SigningKeyPair};
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Prefix of all canonical signing bytes
+const SIGNED_PREFIX: &[u8] = b"cloudp2p signed v1\0";
+
+/// Default allowance for envelopes dated ahead of the local clock (seconds)
+pub const DEFAULT_MAX_FUTURE_SKEW: i64 = 5 * 60;
+
+/// A type that can be signed
+pub trait SignedPayload: Serialize {
+    /// Context string unique to the type (e.g. "heartbeat")
+    const CONTEXT: &'static str;
+}
+
+/// Short identifier of a signing key (first 8 bytes of its SHA-256)
+pub type KeyId = [u8; 8];
+
+/// Get the key ID of an Ed25519 public key
+pub fn key_id(key: &VerifyingKey) -> KeyId {
+    let digest = Sha256::digest(key.as_bytes());
+    digest[..8].try_into().expect("digest is 32 bytes")
+}
+
+/// Canonical bytes to sign for a value of a signed type
+/// For types that carry their own signature field (which must be cleared in `value`)
+pub fn canonical_bytes<T: SignedPayload>(value: &T) -> Result<Vec<u8>, CryptoError> {
+    encode(T::CONTEXT, value)
+}
+
+/// A payload together with its signer, signing time and signature
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signed<T> {
+    /// Signed value
+    pub payload: T,
+
+    /// Key ID of the signer
+    pub key_id: KeyId,
+
+    /// Signing timestamp (Unix)
+    pub signed_at: i64,
+
+    /// Ed25519 signature over the canonical encoding of the fields above
+    pub signature: Vec<u8>,
+}
+
+impl<T: SignedPayload> Signed<T> {
+    /// Sign a payload now
+    pub fn sign(payload: T, keys: &SigningKeyPair) -> Result<Self, CryptoError> {
+        Self::sign_at(payload, keys, chrono::Utc::now().timestamp())
+    }
+
+    /// Sign a payload with an explicit timestamp
+    pub fn sign_at(payload: T, keys: &SigningKeyPair, signed_at: i64) -> Result<Self, CryptoError> {
+        let mut signed = Self {
+            payload,
+            key_id: key_id(&keys.verifying_key),
+            signed_at,
+            signature: vec![],
+        };
+        signed.signature = keys.sign(&signed.signing_bytes()?);
+        Ok(signed)
+    }
+
+    /// Check that `key` signed this envelope and return the payload
+    pub fn verify(&self, key: &VerifyingKey) -> Result<&T, CryptoError> {
+        if self.key_id != key_id(key) {
+            return Err(CryptoError::SignatureVerificationFailed);
+        }
+        let signature =
+            Signature::from_slice(&self.signature).map_err(|_| CryptoError::SignatureVerificationFailed)?;
+        key.verify(&self.signing_bytes()?, &signature)
+            .map_err(|_| CryptoError::SignatureVerificationFailed)?;
+        Ok(&self.payload)
+    }
+
+    /// Canonical bytes covered by the signature
+    pub fn signing_bytes(&self) -> Result<Vec<u8>, CryptoError> {
+        encode(T::CONTEXT, &(&self.payload, &self.key_id, self.signed_at))
+    }
+
+    /// Check if the envelope was signed less than `max_age_seconds` ago
+    /// Envelopes dated more than `DEFAULT_MAX_FUTURE_SKEW` ahead of now are
+    /// not recent: a future date would otherwise keep them fresh for longer
+    pub fn is_recent(&self, max_age_seconds: i64) -> bool {
+        self.is_recent_with_skew(max_age_seconds, DEFAULT_MAX_FUTURE_SKEW)
+    }
+
+    /// Like `is_recent`, allowing `max_future_skew` seconds ahead of now
+    pub fn is_recent_with_skew(&self, max_age_seconds: i64, max_future_skew: i64) -> bool {
+        let now = chrono::Utc::now().timestamp();
+        !self.is_future_dated(max_future_skew) && now.saturating_sub(self.signed_at) < max_age_seconds
+    }
+
+    /// Check if the envelope is dated more than `max_skew_seconds` ahead of now
+    pub fn is_future_dated(&self, max_skew_seconds: i64) -> bool {
+        self.signed_at > chrono::Utc::now().timestamp().saturating_add(max_skew_seconds)
+    }
+}
+
+/// Prefix, context and bincode of the signed fields
+fn encode<V: Serialize + ?Sized>(context: &str, value: &V) -> Result<Vec<u8>, CryptoError> {
+    let encoded = bincode::serialize(value).map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    Ok([SIGNED_PREFIX, context.as_bytes(), b"\0", &encoded].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct Note(String);
+
+    impl SignedPayload for Note {
+        const CONTEXT: &'static str = "note";
+    }
+
+    #[derive(Serialize)]
+    struct Pair(String, String);
+
+    impl SignedPayload for Pair {
+        const CONTEXT: &'static str = "pair";
+    }
+
+    #[derive(Serialize)]
+    struct Memo(String);
+
+    impl SignedPayload for Memo {
+        const CONTEXT: &'static str = "memo";
+    }
+
+    fn keys(seed: u8) -> SigningKeyPair {
+        let signing_key = SigningKey::from_bytes(&[seed; 32]);
+        SigningKeyPair {
+            verifying_key: signing_key.verifying_key(),
+            signing_key,
+        }
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let alice = keys(1);
+        let signed = Signed::sign(Note("a:b".into()), &alice).unwrap();
+
+        assert_eq!(signed.verify(&alice.verifying_key).unwrap(), &Note("a:b".into()));
+        assert!(signed.is_recent(60));
+        assert!(signed.verify(&keys(2).verifying_key).is_err());
+
+        let mut tampered = signed.clone();
+        tampered.payload = Note("a".into());
+        assert!(tampered.verify(&alice.verifying_key).is_err());
+
+        let mut backdated = signed;
+        backdated.signed_at -= 1;
+        assert!(backdated.verify(&alice.verifying_key).is_err());
+    }
+
+    #[test]
+    fn test_freshness_is_asymmetric() {
+        let alice = keys(1);
+        let now = chrono::Utc::now().timestamp();
+        let signed_at = |offset| Signed::sign_at(Note("a".into()), &alice, now + offset).unwrap();
+
+        assert!(signed_at(-30).is_recent(60));
+        assert!(!signed_at(-120).is_recent(60));
+
+        // Small clock differences are tolerated, dates far ahead are not
+        assert!(signed_at(30).is_recent(60));
+        assert!(!signed_at(3600).is_recent(60));
+        assert!(!signed_at(3600).is_recent(i64::MAX));
+        assert!(signed_at(3600).is_recent_with_skew(60, 7200));
+        assert!(signed_at(3600).is_future_dated(DEFAULT_MAX_FUTURE_SKEW));
+        assert!(!signed_at(0).is_future_dated(0));
+
+        // Extreme peer-chosen dates and skews do not overflow
+        let ancient = Signed::sign_at(Note("a".into()), &alice, i64::MIN).unwrap();
+        assert!(!ancient.is_recent(60));
+        assert!(!ancient.is_future_dated(i64::MAX));
+        let distant = Signed::sign_at(Note("a".into()), &alice, i64::MAX).unwrap();
+        assert!(!distant.is_recent(i64::MAX));
+        assert!(distant.is_future_dated(DEFAULT_MAX_FUTURE_SKEW));
+    }
+
+    #[test]
+    fn test_encoding_is_unambiguous() {
+        // Fields that would colon-join to the same string encode differently
+        let a = canonical_bytes(&Pair("a:".into(), "b".into())).unwrap();
+        let b = canonical_bytes(&Pair("a".into(), ":b".into())).unwrap();
+        assert_ne!(a, b);
+
+        // Identically encoded types are told apart by their context
+        let note = canonical_bytes(&Note("a".into())).unwrap();
+        let memo = canonical_bytes(&Memo("a".into())).unwrap();
+        assert_ne!(note, memo);
+        assert!(note.starts_with(b"cloudp2p signed v1\0note\0"));
+    }
+}
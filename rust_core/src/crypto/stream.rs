@@ -0,0 +1,598 @@
+//! Streaming file encryption
+//!
+//! Encrypts and decrypts files chunk by chunk over `Read`/`Write` (and their
+//! tokio counterparts) so large files never have to fit in memory. Streams use
+//! the chunked envelope format (see `crypto::envelope`), so they can be decrypted
+//! without any side metadata.
+
+use super::encryption::{
+    open_chunk, ChunkLayout, CipherSuite, EncryptedFile, FileEncryptor, STREAM_ID_SIZE,
+};
+use super::envelope::{EnvelopeHeader, CHUNKED_HEADER_SIZE, KEY_ID_SIZE};
+use super::{CryptoError, EncryptionKey};
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Chunk layout of an encrypted stream (everything in `EncryptedFile` but the data)
+/// Offsets are relative to the end of the envelope header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkTable {
+    /// Offsets of each encrypted chunk
+    pub chunk_offsets: Vec<usize>,
+
+    /// Original file size
+    pub original_size: usize,
+
+    /// Chunk size used for encryption
+    pub chunk_size: usize,
+
+    /// Total size of the encrypted chunks (without the envelope header)
+    pub encrypted_size: usize,
+
+    /// Random per-file stream ID bound into every chunk
+    pub stream_id: [u8; STREAM_ID_SIZE],
+
+    /// Cipher suite the chunks were encrypted with
+    #[serde(default)]
+    pub suite: CipherSuite,
+
+    /// Identifier of the file key
+    #[serde(default)]
+    pub key_id: [u8; KEY_ID_SIZE],
+}
+
+impl ChunkTable {
+    fn new(header: &EnvelopeHeader) -> Self {
+        Self {
+            chunk_offsets: Vec::new(),
+            original_size: 0,
+            chunk_size: header.chunk_size as usize,
+            encrypted_size: 0,
+            stream_id: header.stream_id.unwrap_or_default(),
+            suite: header.suite,
+            key_id: header.key_id,
+        }
+    }
+
+    /// Envelope header of the stream
+    pub fn header(&self) -> Result<EnvelopeHeader, CryptoError> {
+        EnvelopeHeader::chunked(self.suite, self.key_id, self.chunk_size, self.stream_id)
+    }
+
+    fn layout(&self) -> Result<ChunkLayout<'_>, CryptoError> {
+        Ok(ChunkLayout {
+            chunk_offsets: &self.chunk_offsets,
+            encrypted_size: self.encrypted_size,
+            header: self.header()?,
+        })
+    }
+
+    /// Record an encrypted chunk appended to the stream
+    fn push(&mut self, plaintext_len: usize, encrypted_len: usize) {
+        self.chunk_offsets.push(self.encrypted_size);
+        self.original_size += plaintext_len;
+        self.encrypted_size += encrypted_len;
+    }
+
+    /// Get the number of chunks
+    pub fn chunk_count(&self) -> usize {
+        self.chunk_offsets.len()
+    }
+
+    /// Byte range of a chunk, relative to the end of the envelope header
+    pub fn chunk_range(&self, chunk_index: usize) -> Result<(usize, usize), CryptoError> {
+        self.layout()?.chunk_range(chunk_index)
+    }
+}
+
+impl EncryptedFile {
+    /// Get the chunk layout of this file
+    pub fn chunk_table(&self) -> ChunkTable {
+        ChunkTable {
+            chunk_offsets: self.chunk_offsets.clone(),
+            original_size: self.original_size,
+            chunk_size: self.chunk_size,
+            encrypted_size: self.data.len(),
+            stream_id: self.stream_id,
+            suite: self.suite,
+            key_id: self.key_id,
+        }
+    }
+}
+
+impl FileEncryptor {
+    /// Encrypt everything from `reader` into `writer` as a chunked envelope
+    /// Only two chunks are held in memory (the next one is read ahead to
+    /// know which chunk is final); returns the chunk layout
+    pub fn encrypt_stream<R: Read, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<ChunkTable, CryptoError> {
+        let header = self.new_header()?;
+        writer.write_all(&header.to_bytes()).map_err(io_error)?;
+
+        let mut table = ChunkTable::new(&header);
+        let mut current = vec![0u8; self.chunk_size()];
+        let mut next = vec![0u8; self.chunk_size()];
+
+        let mut len = read_full(reader, &mut current).map_err(io_error)?;
+        loop {
+            // A short read means EOF, otherwise peek at the next chunk
+            let next_len = if len < current.len() {
+                0
+            } else {
+                read_full(reader, &mut next).map_err(io_error)?
+            };
+            let is_final = next_len == 0;
+
+            let index = table.chunk_count();
+            let encrypted_chunk = self.seal_chunk(&header, index, is_final, &current[..len])?;
+            writer.write_all(&encrypted_chunk).map_err(io_error)?;
+            table.push(len, encrypted_chunk.len());
+
+            if is_final {
+                break;
+            }
+            std::mem::swap(&mut current, &mut next);
+            len = next_len;
+        }
+
+        writer.flush().map_err(io_error)?;
+        Ok(table)
+    }
+
+    /// Decrypt a chunked envelope from `reader` into `writer`
+    /// The layout is read from the envelope itself; returns the chunk layout
+    pub fn decrypt_stream<R: Read, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<ChunkTable, CryptoError> {
+        let header = EnvelopeHeader::read_from(reader)?;
+        self.check_header(&header)?;
+
+        let mut table = ChunkTable::new(&header);
+        let mut current = vec![0u8; header.record_size()];
+        let mut next = vec![0u8; header.record_size()];
+
+        let mut len = read_full(reader, &mut current).map_err(io_error)?;
+        loop {
+            let next_len = if len < current.len() {
+                0
+            } else {
+                read_full(reader, &mut next).map_err(io_error)?
+            };
+            let is_final = next_len == 0;
+
+            let index = table.chunk_count();
+            let chunk = open_record(self.key(), &header, index, is_final, &current[..len])?;
+            writer.write_all(&chunk).map_err(io_error)?;
+            table.push(chunk.len(), len);
+
+            if is_final {
+                break;
+            }
+            std::mem::swap(&mut current, &mut next);
+            len = next_len;
+        }
+
+        writer.flush().map_err(io_error)?;
+        Ok(table)
+    }
+
+    /// Async version of `encrypt_stream`
+    pub async fn encrypt_stream_async<R, W>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<ChunkTable, CryptoError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let header = self.new_header()?;
+        writer.write_all(&header.to_bytes()).await.map_err(io_error)?;
+
+        let mut table = ChunkTable::new(&header);
+        let mut current = vec![0u8; self.chunk_size()];
+        let mut next = vec![0u8; self.chunk_size()];
+
+        let mut len = read_full_async(reader, &mut current).await.map_err(io_error)?;
+        loop {
+            let next_len = if len < current.len() {
+                0
+            } else {
+                read_full_async(reader, &mut next).await.map_err(io_error)?
+            };
+            let is_final = next_len == 0;
+
+            let index = table.chunk_count();
+            let encrypted_chunk = self.seal_chunk(&header, index, is_final, &current[..len])?;
+            writer.write_all(&encrypted_chunk).await.map_err(io_error)?;
+            table.push(len, encrypted_chunk.len());
+
+            if is_final {
+                break;
+            }
+            std::mem::swap(&mut current, &mut next);
+            len = next_len;
+        }
+
+        writer.flush().await.map_err(io_error)?;
+        Ok(table)
+    }
+
+    /// Async version of `decrypt_stream`
+    pub async fn decrypt_stream_async<R, W>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<ChunkTable, CryptoError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        // Streams are always chunked, so the header has a fixed size
+        let mut header_bytes = [0u8; CHUNKED_HEADER_SIZE];
+        let header_len = read_full_async(reader, &mut header_bytes).await.map_err(io_error)?;
+        let (header, _) = EnvelopeHeader::parse(&header_bytes[..header_len])?;
+        self.check_header(&header)?;
+
+        let mut table = ChunkTable::new(&header);
+        let mut current = vec![0u8; header.record_size()];
+        let mut next = vec![0u8; header.record_size()];
+
+        let mut len = read_full_async(reader, &mut current).await.map_err(io_error)?;
+        loop {
+            let next_len = if len < current.len() {
+                0
+            } else {
+                read_full_async(reader, &mut next).await.map_err(io_error)?
+            };
+            let is_final = next_len == 0;
+
+            let index = table.chunk_count();
+            let chunk = open_record(self.key(), &header, index, is_final, &current[..len])?;
+            writer.write_all(&chunk).await.map_err(io_error)?;
+            table.push(chunk.len(), len);
+
+            if is_final {
+                break;
+            }
+            std::mem::swap(&mut current, &mut next);
+            len = next_len;
+        }
+
+        writer.flush().await.map_err(io_error)?;
+        Ok(table)
+    }
+
+    /// Open a seekable plaintext reader over a chunked envelope
+    /// The envelope must start at the beginning of `inner`
+    pub fn reader<R: Read + Seek>(&self, mut inner: R) -> Result<DecryptingReader<R>, CryptoError> {
+        inner.seek(SeekFrom::Start(0)).map_err(io_error)?;
+        let header = EnvelopeHeader::read_from(&mut inner)?;
+        self.check_header(&header)?;
+
+        let total_size = inner.seek(SeekFrom::End(0)).map_err(io_error)? as usize;
+        let data_len = total_size - header.encoded_len();
+        let (chunk_offsets, original_size) = header.chunk_layout(data_len)?;
+
+        let table = ChunkTable {
+            chunk_offsets,
+            original_size,
+            encrypted_size: data_len,
+            ..ChunkTable::new(&header)
+        };
+        Ok(DecryptingReader::new(self.key().clone(), inner, table))
+    }
+
+    /// Streams must be chunked envelopes encrypted with our key
+    fn check_header(&self, header: &EnvelopeHeader) -> Result<(), CryptoError> {
+        if !header.is_chunked() {
+            return Err(CryptoError::InvalidEnvelope("Expected a chunked file, found a single blob".into()));
+        }
+        self.key().check_key_id(header)
+    }
+}
+
+/// Seekable reader that decrypts chunks on demand
+///
+/// Implements `Read` and `Seek` over the plaintext; only the chunk under
+/// the current position is decrypted and kept in memory.
+pub struct DecryptingReader<R> {
+    key: EncryptionKey,
+    inner: R,
+    table: ChunkTable,
+
+    /// Current plaintext position
+    position: u64,
+
+    /// Most recently decrypted chunk (index, plaintext)
+    current: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> DecryptingReader<R> {
+    fn new(key: EncryptionKey, inner: R, table: ChunkTable) -> Self {
+        Self {
+            key,
+            inner,
+            table,
+            position: 0,
+            current: None,
+        }
+    }
+
+    /// Decrypt chunk N
+    pub fn decrypt_chunk(&mut self, chunk_index: usize) -> Result<Vec<u8>, CryptoError> {
+        let layout = self.table.layout()?;
+        let (start, end) = layout.chunk_range(chunk_index)?;
+
+        let mut buffer = vec![0u8; end - start];
+        self.inner
+            .seek(SeekFrom::Start((CHUNKED_HEADER_SIZE + start) as u64))
+            .map_err(io_error)?;
+        self.inner.read_exact(&mut buffer).map_err(io_error)?;
+
+        layout.open(&self.key, chunk_index, &buffer)
+    }
+
+    /// Get the chunk layout
+    pub fn table(&self) -> &ChunkTable {
+        &self.table
+    }
+
+    /// Get the plaintext length
+    pub fn len(&self) -> u64 {
+        self.table.original_size as u64
+    }
+
+    /// Check if the plaintext is empty
+    pub fn is_empty(&self) -> bool {
+        self.table.original_size == 0
+    }
+
+    /// Get the inner reader back
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Seek> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.len() {
+            return Ok(0);
+        }
+
+        let chunk_size = self.table.chunk_size as u64;
+        let chunk_index = (self.position / chunk_size) as usize;
+        let offset = (self.position % chunk_size) as usize;
+
+        let cached = matches!(self.current, Some((index, _)) if index == chunk_index);
+        if !cached {
+            let chunk = self
+                .decrypt_chunk(chunk_index)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.current = Some((chunk_index, chunk));
+        }
+
+        let chunk = match &self.current {
+            Some((_, chunk)) => chunk,
+            None => return Ok(0),
+        };
+        if offset >= chunk.len() {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(chunk.len() - offset);
+        buf[..len].copy_from_slice(&chunk[offset..offset + len]);
+        self.position += len as u64;
+
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for DecryptingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        match target {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+/// Read until the buffer is full or EOF, returning the bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Async version of `read_full`
+async fn read_full_async<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Decrypt one chunk record read from a stream
+/// An empty record means the stream ended before its final chunk
+fn open_record(
+    key: &EncryptionKey,
+    header: &EnvelopeHeader,
+    index: usize,
+    is_final: bool,
+    record: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    if record.is_empty() {
+        return Err(CryptoError::TamperedChunkLayout("Missing final chunk".into()));
+    }
+    open_chunk(key, header, index, is_final, record)
+}
+
+fn io_error(e: io::Error) -> CryptoError {
+    CryptoError::Io(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        let encryptor = FileEncryptor::new(EncryptionKey::generate()).with_chunk_size(1024);
+        let data = test_data(5000);
+
+        let mut encrypted = Vec::new();
+        let table = encryptor
+            .encrypt_stream(&mut Cursor::new(&data), &mut encrypted)
+            .unwrap();
+
+        assert_eq!(table.chunk_count(), 5);
+        assert_eq!(table.original_size, data.len());
+        assert_eq!(CHUNKED_HEADER_SIZE + table.encrypted_size, encrypted.len());
+
+        let mut decrypted = Vec::new();
+        let decrypted_table = encryptor
+            .decrypt_stream(&mut Cursor::new(&encrypted), &mut decrypted)
+            .unwrap();
+
+        assert_eq!(data, decrypted);
+        assert_eq!(decrypted_table, table);
+    }
+
+    #[test]
+    fn test_stream_matches_encrypted_file() {
+        let encryptor = FileEncryptor::new(EncryptionKey::generate()).with_chunk_size(100);
+        let data = test_data(1000);
+
+        let mut encrypted = Vec::new();
+        let table = encryptor
+            .encrypt_stream(&mut Cursor::new(&data), &mut encrypted)
+            .unwrap();
+
+        // The stream can be loaded as a regular EncryptedFile
+        let file = EncryptedFile::from_bytes(&encrypted).unwrap();
+        assert_eq!(file.chunk_table(), table);
+        assert_eq!(file.to_bytes().unwrap(), encrypted);
+        assert_eq!(encryptor.decrypt_file(&file).unwrap(), data);
+    }
+
+    #[test]
+    fn test_stream_exact_multiple_of_chunk_size() {
+        let encryptor = FileEncryptor::new(EncryptionKey::generate()).with_chunk_size(100);
+        let data = test_data(300);
+
+        let mut encrypted = Vec::new();
+        let table = encryptor
+            .encrypt_stream(&mut Cursor::new(&data), &mut encrypted)
+            .unwrap();
+        assert_eq!(table.chunk_count(), 3);
+
+        let mut decrypted = Vec::new();
+        encryptor
+            .decrypt_stream(&mut Cursor::new(&encrypted), &mut decrypted)
+            .unwrap();
+        assert_eq!(data, decrypted);
+    }
+
+    #[test]
+    fn test_truncated_stream_rejected() {
+        let encryptor = FileEncryptor::new(EncryptionKey::generate()).with_chunk_size(100);
+        let data = test_data(450);
+
+        let mut encrypted = Vec::new();
+        let table = encryptor
+            .encrypt_stream(&mut Cursor::new(&data), &mut encrypted)
+            .unwrap();
+
+        // Drop the final chunk
+        encrypted.truncate(CHUNKED_HEADER_SIZE + table.chunk_offsets[4]);
+        let result = encryptor.decrypt_stream(&mut Cursor::new(&encrypted), &mut Vec::new());
+        assert!(matches!(result, Err(CryptoError::TamperedChunkLayout(_))));
+
+        // Drop every chunk, leaving only the header
+        encrypted.truncate(CHUNKED_HEADER_SIZE);
+        let result = encryptor.decrypt_stream(&mut Cursor::new(&encrypted), &mut Vec::new());
+        assert!(matches!(result, Err(CryptoError::TamperedChunkLayout(_))));
+    }
+
+    #[tokio::test]
+    async fn test_async_stream_roundtrip() {
+        let encryptor = FileEncryptor::new(EncryptionKey::generate()).with_chunk_size(512);
+        let data = test_data(3000);
+
+        let mut encrypted = Vec::new();
+        encryptor
+            .encrypt_stream_async(&mut data.as_slice(), &mut encrypted)
+            .await
+            .unwrap();
+
+        let mut decrypted = Vec::new();
+        encryptor
+            .decrypt_stream_async(&mut encrypted.as_slice(), &mut decrypted)
+            .await
+            .unwrap();
+
+        assert_eq!(data, decrypted);
+    }
+
+    #[test]
+    fn test_seekable_reader() {
+        let encryptor = FileEncryptor::new(EncryptionKey::generate()).with_chunk_size(100);
+        let data = test_data(1050);
+
+        let mut encrypted = Vec::new();
+        encryptor
+            .encrypt_stream(&mut Cursor::new(&data), &mut encrypted)
+            .unwrap();
+
+        let mut reader = encryptor.reader(Cursor::new(encrypted)).unwrap();
+        assert_eq!(reader.len(), data.len() as u64);
+        assert_eq!(reader.decrypt_chunk(3).unwrap(), &data[300..400]);
+
+        // Read across a chunk boundary
+        reader.seek(SeekFrom::Start(250)).unwrap();
+        let mut buf = vec![0u8; 120];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, &data[250..370]);
+
+        // Read the tail
+        reader.seek(SeekFrom::End(-30)).unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &data[1020..]);
+    }
+}
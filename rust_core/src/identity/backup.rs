@@ -0,0 +1,259 @@
+//! Encrypted identity backups
+//!
+//! The keystore only holds the master seed for unlocking on this machine. A
+//! backup bundles everything needed to move to a new one (the seed and key
+//! epoch, certified devices, contacts and the file index) into a single
+//! file sealed under an Argon2id-derived key.
+
+use super::sealed_file::{self, PasswordSeal};
+use super::{DeviceCertificate, IdentityError, UserIdentity};
+use crate::crypto::{CipherSuite, KdfParams};
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Current backup format version
+const BACKUP_VERSION: u8 = 2;
+
+/// Someone the user exchanges files with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contact {
+    /// Name chosen by the user
+    pub name: String,
+
+    /// Public ID of the contact
+    pub public_id: String,
+}
+
+/// Everything a backup restores besides the identity itself
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupContents {
+    /// Certificates of the identity's devices
+    pub devices: Vec<DeviceCertificate>,
+
+    /// Known contacts
+    pub contacts: Vec<Contact>,
+
+    /// File index as produced by `FileManager::export_index`
+    pub file_index: Option<String>,
+}
+
+/// Password-sealed identity bundle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityBackup {
+    /// Backup format version
+    version: u8,
+
+    /// Public ID of the backed up identity
+    public_id: String,
+
+    /// Creation timestamp
+    created_at: i64,
+
+    /// Argon2id cost parameters and salt
+    #[serde(flatten)]
+    password: PasswordSeal,
+
+    /// Sealed `BackupPayload`
+    sealed: Vec<u8>,
+}
+
+/// Plaintext of a backup (the seed is wiped on drop)
+#[derive(Serialize, Deserialize)]
+struct BackupPayload {
+    master_seed: Vec<u8>,
+    key_epoch: u32,
+
+    /// Encryption key of the current epoch (empty at epoch 0, which derives from the seed)
+    #[serde(default)]
+    epoch_key: Vec<u8>,
+
+    /// Keys of the earlier rotated epochs, wrapped under `epoch_key`
+    #[serde(default)]
+    wrapped_epoch_keys: Vec<Vec<u8>>,
+
+    contents: BackupContents,
+}
+
+impl Drop for BackupPayload {
+    fn drop(&mut self) {
+        self.master_seed.zeroize();
+        self.epoch_key.zeroize();
+    }
+}
+
+impl IdentityBackup {
+    /// Seal an identity and its contents under a password
+    pub fn create(
+        identity: &UserIdentity,
+        contents: &BackupContents,
+        password: &str,
+        kdf: KdfParams,
+    ) -> Result<Self, IdentityError> {
+        for device in &contents.devices {
+            device.verify_for(&identity.public_id())?;
+        }
+
+        let mut backup = Self {
+            version: BACKUP_VERSION,
+            public_id: identity.public_id(),
+            created_at: chrono::Utc::now().timestamp(),
+            password: PasswordSeal::new(kdf),
+            sealed: Vec::new(),
+        };
+
+        let payload = BackupPayload {
+            master_seed: identity.master_seed().to_vec(),
+            key_epoch: identity.key_epoch(),
+            epoch_key: match identity.key_epoch() {
+                0 => vec![],
+                _ => identity.encryption_key().as_bytes().to_vec(),
+            },
+            wrapped_epoch_keys: identity.wrapped_epoch_keys()?,
+            contents: contents.clone(),
+        };
+        let plaintext =
+            Zeroizing::new(serde_json::to_vec(&payload).map_err(|e| IdentityError::Backup(e.to_string()))?);
+
+        let key = backup.password.derive_key(password)?;
+        backup.sealed =
+            key.encrypt_envelope_with_aad(CipherSuite::XChaCha20Poly1305, &plaintext, &backup.associated_data())?;
+        Ok(backup)
+    }
+
+    /// Unseal the backup and rebuild the identity and its contents
+    /// Fails with `IdentityError::WrongPassword` if the password is wrong
+    /// (or the backup was modified)
+    pub fn restore(&self, password: &str) -> Result<(UserIdentity, BackupContents), IdentityError> {
+        if self.version != BACKUP_VERSION {
+            return Err(IdentityError::Backup(format!("Unsupported backup version {}", self.version)));
+        }
+
+        let key = self.password.derive_key(password)?;
+        let plaintext = Zeroizing::new(
+            key.decrypt_with_aad(&self.sealed, &self.associated_data())
+                .map_err(|_| IdentityError::WrongPassword)?,
+        );
+        let payload: BackupPayload =
+            serde_json::from_slice(&plaintext).map_err(|e| IdentityError::Backup(e.to_string()))?;
+
+        let master_seed: [u8; 64] = payload
+            .master_seed
+            .as_slice()
+            .try_into()
+            .map_err(|_| IdentityError::Backup("Invalid master seed length".into()))?;
+        let mut identity = UserIdentity::from_master_seed(Zeroizing::new(master_seed))?;
+        if payload.key_epoch > 0 {
+            identity.restore_epoch_keys(&payload.epoch_key, &payload.wrapped_epoch_keys)?;
+        }
+
+        // The seed must lead back to the identity the backup claims to hold
+        if identity.public_id() != self.public_id || identity.key_epoch() != payload.key_epoch {
+            return Err(IdentityError::Backup("Integrity check failed".into()));
+        }
+        for device in &payload.contents.devices {
+            device.verify_for(&self.public_id)?;
+        }
+
+        Ok((identity, payload.contents.clone()))
+    }
+
+    /// Get the public ID of the backed up identity
+    pub fn public_id(&self) -> &str {
+        &self.public_id
+    }
+
+    /// Get the creation timestamp
+    pub fn created_at(&self) -> i64 {
+        self.created_at
+    }
+
+    /// Export to JSON
+    pub fn to_json(&self) -> Result<String, IdentityError> {
+        sealed_file::to_json(self, IdentityError::Backup)
+    }
+
+    /// Import from JSON
+    pub fn from_json(json: &str) -> Result<Self, IdentityError> {
+        sealed_file::from_json(json, IdentityError::Backup)
+    }
+
+    /// Write the backup to disk (atomically)
+    pub fn save(&self, path: &Path) -> Result<(), IdentityError> {
+        sealed_file::save(self, path, IdentityError::Backup)
+    }
+
+    /// Read a backup from disk
+    pub fn load(path: &Path) -> Result<Self, IdentityError> {
+        sealed_file::load(path, IdentityError::Backup)
+    }
+
+    /// Backup fields authenticated along with the sealed payload
+    fn associated_data(&self) -> Vec<u8> {
+        self.password.associated_data(
+            "backup",
+            &[&[self.version], self.public_id.as_bytes(), &self.created_at.to_be_bytes()],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters so tests run fast
+    fn test_params() -> KdfParams {
+        KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn test_backup_roundtrip() {
+        let (mut identity, _) = UserIdentity::generate(None).unwrap();
+        identity.rotate_encryption_key().unwrap();
+        let contents = BackupContents {
+            devices: vec![identity.certify_device(&identity.device_keys(1).unwrap(), "laptop").unwrap()],
+            contacts: vec![Contact {
+                name: "Alice".into(),
+                public_id: "cp2p_alice".into(),
+            }],
+            file_index: Some("{}".into()),
+        };
+        let backup = IdentityBackup::create(&identity, &contents, "backup password", test_params()).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.backup");
+        backup.save(&path).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("Alice"));
+
+        let (restored, restored_contents) = IdentityBackup::load(&path).unwrap().restore("backup password").unwrap();
+        assert_eq!(restored.public_id(), identity.public_id());
+        assert_eq!(restored.key_epoch(), 1);
+        assert_eq!(restored.encryption_key().as_bytes(), identity.encryption_key().as_bytes());
+        assert_eq!(restored_contents, contents);
+    }
+
+    #[test]
+    fn test_wrong_password_or_tampering_rejected() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let backup = IdentityBackup::create(&identity, &BackupContents::default(), "password", test_params()).unwrap();
+
+        assert!(matches!(backup.restore("not the password"), Err(IdentityError::WrongPassword)));
+
+        let mut relabeled = backup.clone();
+        relabeled.created_at += 1;
+        assert!(relabeled.restore("password").is_err());
+
+        // Devices of another identity cannot be bundled
+        let (other, _) = UserIdentity::generate(None).unwrap();
+        let contents = BackupContents {
+            devices: vec![other.certify_device(&other.device_keys(0).unwrap(), "x").unwrap()],
+            ..BackupContents::default()
+        };
+        assert!(IdentityBackup::create(&identity, &contents, "password", test_params()).is_err());
+    }
+}
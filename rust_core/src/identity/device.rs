@@ -0,0 +1,252 @@
+//! Per-device keys under one seed-phrase identity
+//!
+//! Each device gets its own signing, libp2p and X25519 keys, derived from the
+//! master seed through a per-device seed (so a device seed never reveals its
+//! siblings or the root). The root signing key issues a certificate, a
+//! `Signed<CertifiedDevice>` envelope, binding the device keys to the
+//! identity's public ID.
+
+use super::{IdentityError, UserIdentity};
+use crate::crypto::{AgreementKeyPair, Signed, SignedPayload, SigningKeyPair};
+
+use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Keys of one device
+/// Secrets are wiped from memory on drop
+pub struct DeviceKeys {
+    /// Device index under the identity
+    index: u32,
+
+    /// Signing key pair used by this device
+    signing_keys: SigningKeyPair,
+
+    /// X25519 key pair of this device
+    agreement_keys: AgreementKeyPair,
+
+    /// Ed25519 seed of the device's libp2p key
+    network_seed: Zeroizing<[u8; 32]>,
+}
+
+impl DeviceKeys {
+    /// Derive the keys of device `index` from the master seed
+    pub(super) fn derive(master_seed: &[u8; 64], index: u32) -> Result<Self, IdentityError> {
+        let device_seed = expand(
+            Hkdf::<Sha256>::new(Some(b"cloudp2p-device"), master_seed),
+            format!("device:{}", index).as_bytes(),
+        )?;
+        let hk = Hkdf::<Sha256>::new(None, device_seed.as_slice());
+
+        let signing_key = SigningKey::from_bytes(&*expand(hk.clone(), b"ed25519-signing-key")?);
+        let verifying_key = signing_key.verifying_key();
+
+        Ok(Self {
+            index,
+            signing_keys: SigningKeyPair {
+                signing_key,
+                verifying_key,
+            },
+            agreement_keys: AgreementKeyPair::from_secret_bytes(*expand(hk.clone(), b"x25519-agreement-key")?),
+            network_seed: expand(hk, b"libp2p-ed25519")?,
+        })
+    }
+
+    /// Get the device index
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Get the device signing key pair
+    pub fn signing_keys(&self) -> &SigningKeyPair {
+        &self.signing_keys
+    }
+
+    /// Get the device X25519 key pair
+    pub fn agreement_keys(&self) -> &AgreementKeyPair {
+        &self.agreement_keys
+    }
+
+    /// Get the Ed25519 seed of the device's libp2p key
+    pub(crate) fn network_seed(&self) -> &[u8; 32] {
+        &self.network_seed
+    }
+
+    /// Get the public half of the device's libp2p key
+    pub fn network_public_key(&self) -> [u8; 32] {
+        SigningKey::from_bytes(&self.network_seed).verifying_key().to_bytes()
+    }
+
+    /// Sign a message as this device
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.signing_keys.sign(message)
+    }
+}
+
+impl fmt::Debug for DeviceKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceKeys")
+            .field("index", &self.index)
+            .field("signing_keys", &self.signing_keys)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Statement that a device acts for an identity (signed as `DeviceCertificate`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertifiedDevice {
+    /// Public ID of the identity the device acts for
+    pub owner_id: String,
+
+    /// Root Ed25519 public key of the identity (must match `owner_id`)
+    pub owner_key: Vec<u8>,
+
+    /// Device index under the identity
+    pub device_index: u32,
+
+    /// Human readable device name
+    pub device_name: String,
+
+    /// Device Ed25519 signing key
+    pub signing_key: Vec<u8>,
+
+    /// Device X25519 key
+    pub agreement_key: Vec<u8>,
+
+    /// Device libp2p Ed25519 key (determines its PeerId)
+    pub network_key: Vec<u8>,
+}
+
+impl SignedPayload for CertifiedDevice {
+    const CONTEXT: &'static str = "device certificate";
+}
+
+/// Certificate letting a device act for its identity, issued by the root key
+pub type DeviceCertificate = Signed<CertifiedDevice>;
+
+impl Signed<CertifiedDevice> {
+    /// Issue a certificate for a device of `identity`
+    pub(super) fn issue(identity: &UserIdentity, device: &DeviceKeys, device_name: &str) -> Result<Self, IdentityError> {
+        let device = CertifiedDevice {
+            owner_id: identity.public_id(),
+            owner_key: identity.signing_keys().verifying_key.as_bytes().to_vec(),
+            device_index: device.index,
+            device_name: device_name.to_string(),
+            signing_key: device.signing_keys.verifying_key.as_bytes().to_vec(),
+            agreement_key: device.agreement_keys.public_key().as_bytes().to_vec(),
+            network_key: device.network_public_key().to_vec(),
+        };
+        Ok(Signed::sign(device, identity.signing_keys())?)
+    }
+
+    /// Check that the certificate was issued by the root key of the identity it names
+    pub fn verify_certificate(&self) -> Result<&CertifiedDevice, IdentityError> {
+        self.verify_by_owner(&self.payload.owner_key, &self.payload.owner_id)
+            .map_err(|e| IdentityError::InvalidDeviceCertificate(e.to_string()))
+    }
+
+    /// Check that this certificate lets its device act for `public_id`
+    pub fn verify_for(&self, public_id: &str) -> Result<&CertifiedDevice, IdentityError> {
+        if self.payload.owner_id != public_id {
+            return Err(IdentityError::InvalidDeviceCertificate(format!(
+                "Device acts for {}, not {}",
+                self.payload.owner_id, public_id
+            )));
+        }
+        self.verify_certificate()
+    }
+
+    /// Verify a message signed by the certified device
+    /// Only meaningful after `verify_certificate` / `verify_for` succeeded
+    pub fn verify_device_signature(&self, message: &[u8], signature: &[u8]) -> Result<(), IdentityError> {
+        verify_signature(&verifying_key(&self.payload.signing_key)?, message, signature)
+    }
+}
+
+impl UserIdentity {
+    /// Derive the keys of one of this identity's devices
+    pub fn device_keys(&self, index: u32) -> Result<DeviceKeys, IdentityError> {
+        DeviceKeys::derive(self.master_seed(), index)
+    }
+
+    /// Certify a device to act for this identity
+    pub fn certify_device(&self, device: &DeviceKeys, device_name: &str) -> Result<DeviceCertificate, IdentityError> {
+        DeviceCertificate::issue(self, device, device_name)
+    }
+}
+
+/// Expand 32 bytes of key material
+fn expand(hk: Hkdf<Sha256>, info: &[u8]) -> Result<Zeroizing<[u8; 32]>, IdentityError> {
+    let mut okm = Zeroizing::new([0u8; 32]);
+    hk.expand(info, okm.as_mut())
+        .map_err(|e| IdentityError::KeyDerivation(e.to_string()))?;
+    Ok(okm)
+}
+
+/// Parse an Ed25519 public key
+fn verifying_key(bytes: &[u8]) -> Result<VerifyingKey, IdentityError> {
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| IdentityError::InvalidDeviceCertificate("Invalid key length".into()))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| IdentityError::InvalidDeviceCertificate(e.to_string()))
+}
+
+/// Verify an Ed25519 signature
+fn verify_signature(key: &VerifyingKey, message: &[u8], signature: &[u8]) -> Result<(), IdentityError> {
+    let signature = Signature::from_slice(signature)
+        .map_err(|_| IdentityError::InvalidDeviceCertificate("Invalid signature".into()))?;
+    key.verify(message, &signature)
+        .map_err(|_| IdentityError::InvalidDeviceCertificate("Signature verification failed".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_keys_are_independent_and_recoverable() {
+        let (identity, phrase) = UserIdentity::generate(None).unwrap();
+        let laptop = identity.device_keys(0).unwrap();
+        let phone = identity.device_keys(1).unwrap();
+
+        assert_ne!(laptop.signing_keys().verifying_key, phone.signing_keys().verifying_key);
+        assert_ne!(laptop.network_public_key(), phone.network_public_key());
+        assert_ne!(laptop.agreement_keys().public_key(), phone.agreement_keys().public_key());
+        assert_ne!(laptop.signing_keys().verifying_key, identity.signing_keys().verifying_key);
+
+        let recovered = UserIdentity::from_seed_phrase(&phrase, None).unwrap();
+        assert_eq!(
+            recovered.device_keys(1).unwrap().network_public_key(),
+            phone.network_public_key()
+        );
+    }
+
+    #[test]
+    fn test_device_certificate() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let device = identity.device_keys(3).unwrap();
+        let certificate = identity.certify_device(&device, "laptop").unwrap();
+
+        certificate.verify_for(&identity.public_id()).unwrap();
+        let signature = device.sign(b"retrieve shard");
+        certificate.verify_device_signature(b"retrieve shard", &signature).unwrap();
+        assert!(certificate.verify_device_signature(b"delete shard", &signature).is_err());
+
+        // Not valid for anyone else
+        let (other, _) = UserIdentity::generate(None).unwrap();
+        assert!(certificate.verify_for(&other.public_id()).is_err());
+
+        // A swapped device key breaks the root signature
+        let mut tampered = certificate.clone();
+        tampered.payload.signing_key = other.signing_keys().verifying_key.as_bytes().to_vec();
+        assert!(tampered.verify_certificate().is_err());
+
+        // Another identity cannot certify devices for this one
+        let mut forged = other.certify_device(&other.device_keys(0).unwrap(), "evil").unwrap();
+        forged.payload.owner_id = identity.public_id();
+        assert!(forged.verify_certificate().is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! Signed identity documents
+//!
+//! A public ID is only a hash of the root key, so a peer holding just the ID
+//! cannot check anything signed by it. Each identity publishes one
+//! root-signed document (`Signed<IdentityInfo>`) to the DHT listing its keys,
+//! its certified devices and the PeerIds it can currently be reached at. Like revocation lists,
+//! documents carry a sequence number so an old one cannot be replayed.
+
+use super::{DeviceCertificate, IdentityError, UserIdentity};
+use crate::crypto::{Signed, SignedPayload};
+
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use x25519_dalek::PublicKey;
+
+/// DHT key prefix for identity documents
+const DOCUMENT_DHT_PREFIX: &str = "/cloudp2p/identities/";
+
+/// Description of an identity (signed as `IdentityDocument`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityInfo {
+    /// Public ID of the identity
+    pub owner_id: String,
+
+    /// Root Ed25519 public key of the identity (must match `owner_id`)
+    pub owner_key: Vec<u8>,
+
+    /// X25519 key others seal keys to
+    pub agreement_key: Vec<u8>,
+
+    /// Certificates of the identity's devices
+    pub devices: Vec<DeviceCertificate>,
+
+    /// libp2p PeerIds the identity is currently reachable at
+    pub peer_ids: Vec<String>,
+
+    /// Incremented with every new document
+    pub sequence: u64,
+}
+
+impl SignedPayload for IdentityInfo {
+    const CONTEXT: &'static str = "identity document";
+}
+
+/// Identity description as published to the DHT and cached by peers
+pub type IdentityDocument = Signed<IdentityInfo>;
+
+impl Signed<IdentityInfo> {
+    /// DHT key of an identity's document
+    pub fn dht_key(owner_id: &str) -> Vec<u8> {
+        format!("{}{}", DOCUMENT_DHT_PREFIX, owner_id).into_bytes()
+    }
+
+    /// Owner ID from an identity document DHT key (None for other keys)
+    pub fn owner_from_dht_key(key: &[u8]) -> Option<&str> {
+        std::str::from_utf8(key).ok()?.strip_prefix(DOCUMENT_DHT_PREFIX)
+    }
+
+    /// Check that the identity described signed the document itself and
+    /// that every listed device is certified for it
+    pub fn verify_document(&self) -> Result<&IdentityInfo, IdentityError> {
+        let invalid = |reason: &str| IdentityError::InvalidIdentityDocument(reason.to_string());
+
+        let info = self
+            .verify_by_owner(&self.payload.owner_key, &self.payload.owner_id)
+            .map_err(|e| invalid(&e.to_string()))?;
+        if info.agreement_key.len() != 32 {
+            return Err(invalid("Invalid agreement key length"));
+        }
+
+        for device in &info.devices {
+            device.verify_for(&info.owner_id)?;
+        }
+        Ok(info)
+    }
+
+    /// Verified root key of the identity
+    pub fn owner_key(&self) -> Result<VerifyingKey, IdentityError> {
+        let info = self.verify_document()?;
+        let key: [u8; 32] = info.owner_key.as_slice().try_into().expect("checked by verify_document");
+        Ok(VerifyingKey::from_bytes(&key).expect("checked by verify_document"))
+    }
+
+    /// Verified X25519 key of the identity
+    pub fn agreement_key(&self) -> Result<PublicKey, IdentityError> {
+        let info = self.verify_document()?;
+        let key: [u8; 32] = info.agreement_key.as_slice().try_into().expect("checked by verify_document");
+        Ok(PublicKey::from(key))
+    }
+
+    /// Certificate of the device with the given index, if listed
+    pub fn device(&self, device_index: u32) -> Option<&DeviceCertificate> {
+        self.payload.devices.iter().find(|d| d.payload.device_index == device_index)
+    }
+
+    /// Serialize for the DHT
+    pub fn to_bytes(&self) -> Result<Vec<u8>, IdentityError> {
+        serde_json::to_vec(self).map_err(|e| IdentityError::InvalidIdentityDocument(e.to_string()))
+    }
+
+    /// Parse and verify a document fetched from the DHT
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IdentityError> {
+        let document: Self =
+            serde_json::from_slice(bytes).map_err(|e| IdentityError::InvalidIdentityDocument(e.to_string()))?;
+        document.verify_document()?;
+        Ok(document)
+    }
+}
+
+impl UserIdentity {
+    /// Build and sign this identity's document
+    /// Follows on from `previous` (the latest published document, if any)
+    pub fn identity_document(
+        &self,
+        previous: Option<&IdentityDocument>,
+        devices: Vec<DeviceCertificate>,
+        peer_ids: Vec<String>,
+    ) -> Result<IdentityDocument, IdentityError> {
+        if let Some(previous) = previous {
+            if previous.verify_document()?.owner_id != self.public_id() {
+                return Err(IdentityError::InvalidIdentityDocument(
+                    "Document belongs to another identity".into(),
+                ));
+            }
+        }
+
+        let info = IdentityInfo {
+            owner_id: self.public_id(),
+            owner_key: self.signing_keys().verifying_key.as_bytes().to_vec(),
+            agreement_key: self.agreement_public_key().as_bytes().to_vec(),
+            devices,
+            peer_ids,
+            sequence: previous.map_or(1, |p| p.payload.sequence + 1),
+        };
+        for device in &info.devices {
+            device.verify_for(&info.owner_id)?;
+        }
+        Ok(Signed::sign(info, self.signing_keys())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_resolves_owner_keys() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let laptop = identity.certify_device(&identity.device_keys(1).unwrap(), "laptop").unwrap();
+
+        let first = identity
+            .identity_document(None, vec![laptop.clone()], vec!["12D3KooWLaptop".into()])
+            .unwrap();
+        assert_eq!(first.payload.sequence, 1);
+        assert_eq!(first.owner_key().unwrap(), identity.signing_keys().verifying_key);
+        assert_eq!(first.agreement_key().unwrap(), *identity.agreement_public_key());
+        assert_eq!(first.device(1), Some(&laptop));
+
+        let second = identity.identity_document(Some(&first), vec![], vec![]).unwrap();
+        assert_eq!(second.payload.sequence, 2);
+
+        let parsed = IdentityDocument::from_bytes(&second.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed, second);
+        assert_eq!(
+            IdentityDocument::owner_from_dht_key(&IdentityDocument::dht_key(&identity.public_id())),
+            Some(identity.public_id().as_str())
+        );
+    }
+
+    #[test]
+    fn test_tampered_document_rejected() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let document = identity.identity_document(None, vec![], vec![]).unwrap();
+
+        // Swapping in another agreement key breaks the signature
+        let (other, _) = UserIdentity::generate(None).unwrap();
+        let mut swapped = document.clone();
+        swapped.payload.agreement_key = other.agreement_public_key().as_bytes().to_vec();
+        assert!(IdentityDocument::from_bytes(&swapped.to_bytes().unwrap()).is_err());
+
+        // Another identity cannot publish a document for this one
+        let mut forged = other.identity_document(None, vec![], vec![]).unwrap();
+        forged.payload.owner_id = identity.public_id();
+        assert!(forged.owner_key().is_err());
+
+        // Nor list its own devices in it
+        let stranger = other.certify_device(&other.device_keys(0).unwrap(), "x").unwrap();
+        assert!(identity.identity_document(None, vec![stranger], vec![]).is_err());
+    }
+}
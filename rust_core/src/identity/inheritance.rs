@@ -0,0 +1,104 @@
+//! Inheritance escrow
+//!
+//! An owner can seal secrets (normally file keys) to an heir and hand the
+//! owner-signed record to storage hosts. Hosts only release it once the owner
+//! has been silent (no heartbeats) for the record's inactivity period, so any
+//! heartbeat postpones the release.
+
+use super::{IdentityDocument, IdentityError, UserIdentity};
+use crate::crypto::{sealed_box, Signed, SignedPayload};
+
+use serde::{Deserialize, Serialize};
+
+/// Secret sealed to an heir, released after the owner goes quiet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EscrowRecord {
+    /// Public ID of the owner
+    pub owner_id: String,
+
+    /// Owner's Ed25519 public key (must match `owner_id`)
+    pub owner_key: Vec<u8>,
+
+    /// Public ID of the heir
+    pub heir_id: String,
+
+    /// Days without heartbeats before hosts release the record
+    pub inactivity_days: u32,
+
+    /// Secret sealed to the heir's X25519 key
+    pub sealed_secret: Vec<u8>,
+}
+
+impl SignedPayload for EscrowRecord {
+    const CONTEXT: &'static str = "inheritance escrow";
+}
+
+impl EscrowRecord {
+    /// Inactivity period in seconds
+    pub fn inactivity_seconds(&self) -> i64 {
+        self.inactivity_days as i64 * 24 * 60 * 60
+    }
+}
+
+impl Signed<EscrowRecord> {
+    /// Check that the owner deposited this record (a host cannot swap the heir)
+    pub fn verify_escrow(&self) -> Result<&EscrowRecord, IdentityError> {
+        self.verify_by_owner(&self.payload.owner_key, &self.payload.owner_id)
+            .map_err(|e| IdentityError::InvalidEscrow(e.to_string()))
+    }
+}
+
+impl UserIdentity {
+    /// Seal a secret to an heir and sign the escrow record
+    /// The heir's document provides (and vouches for) its agreement key
+    pub fn create_escrow(
+        &self,
+        heir: &IdentityDocument,
+        inactivity_days: u32,
+        secret: &[u8],
+    ) -> Result<Signed<EscrowRecord>, IdentityError> {
+        let heir_key = heir.agreement_key()?;
+        let record = EscrowRecord {
+            owner_id: self.public_id(),
+            owner_key: self.signing_keys().verifying_key.as_bytes().to_vec(),
+            heir_id: heir.payload.owner_id.clone(),
+            inactivity_days,
+            sealed_secret: sealed_box::seal(&heir_key, secret)?,
+        };
+        Signed::sign(record, self.signing_keys()).map_err(Into::into)
+    }
+
+    /// Open a released escrow record addressed to this identity
+    pub fn open_escrow(&self, record: &Signed<EscrowRecord>) -> Result<Vec<u8>, IdentityError> {
+        let record = record.verify_escrow()?;
+        if record.heir_id != self.public_id() {
+            return Err(IdentityError::InvalidEscrow(format!("Escrow is meant for {}", record.heir_id)));
+        }
+        self.open_sealed(&record.sealed_secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escrow_opens_only_for_heir() {
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let (heir, _) = UserIdentity::generate(None).unwrap();
+        let heir_document = heir.identity_document(None, vec![], vec![]).unwrap();
+
+        let record = owner.create_escrow(&heir_document, 180, b"file keys").unwrap();
+        assert_eq!(record.verify_escrow().unwrap().inactivity_seconds(), 180 * 86400);
+        assert_eq!(heir.open_escrow(&record).unwrap(), b"file keys");
+
+        // Nobody else can open it, not even the owner
+        assert!(owner.open_escrow(&record).is_err());
+
+        // Shortening the waiting period breaks the owner signature
+        let mut hurried = record;
+        hurried.payload.inactivity_days = 0;
+        assert!(hurried.verify_escrow().is_err());
+        assert!(heir.open_escrow(&hurried).is_err());
+    }
+}
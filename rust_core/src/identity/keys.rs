@@ -1,9 +1,10 @@
 //! Key derivation and management
 
 use ed25519_dalek::{SigningKey, VerifyingKey};
+use std::fmt;
 
 /// Ed25519 key pair for signing operations
-#[derive(Clone)]
+/// The signing key is wiped from memory on drop
 pub struct KeyPair {
     pub signing_key: SigningKey,
     pub verifying_key: VerifyingKey,
@@ -24,10 +25,13 @@ impl KeyPair {
     pub fn public_key_bytes(&self) -> [u8; 32] {
         self.verifying_key.to_bytes()
     }
+}
 
-    /// Get the secret key bytes
-    pub fn secret_key_bytes(&self) -> [u8; 32] {
-        self.signing_key.to_bytes()
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyPair")
+            .field("verifying_key", &hex::encode(self.public_key_bytes()))
+            .finish_non_exhaustive()
     }
 }
 
@@ -42,7 +46,16 @@ mod tests {
 
         let keypair = KeyPair::from_bytes(&seed);
 
-        assert_eq!(keypair.secret_key_bytes(), seed);
+        assert_eq!(keypair.signing_key.to_bytes(), seed);
         assert_ne!(keypair.public_key_bytes(), seed);
     }
+
+    #[test]
+    fn test_debug_redacts_secret() {
+        let keypair = KeyPair::from_bytes(&[0x5A; 32]);
+        let debug = format!("{:?}", keypair);
+
+        assert!(debug.contains(&hex::encode(keypair.public_key_bytes())));
+        assert!(!debug.contains(&hex::encode([0x5A; 32])));
+    }
 }
@@ -0,0 +1,280 @@
+//! Password-protected local keystore
+//!
+//! Stores the master seed on disk encrypted under an Argon2id-derived key, so
+//! the app can start with a local password instead of the seed phrase. Rotated
+//! encryption keys cannot be derived from the seed, so the current one is
+//! sealed alongside it and older ones are kept wrapped under the current one.
+
+use super::sealed_file::{self, PasswordSeal};
+use super::{IdentityError, UserIdentity};
+use crate::crypto::{CipherSuite, KdfParams};
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Current keystore format version
+const KEYSTORE_VERSION: u8 = 2;
+
+/// Encrypted master seed plus everything needed to unlock it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keystore {
+    /// Keystore format version
+    version: u8,
+
+    /// Public ID of the stored identity
+    public_id: String,
+
+    /// Key epoch of the stored identity (re-create the keystore after rotating)
+    #[serde(default)]
+    key_epoch: u32,
+
+    /// Argon2id cost parameters and salt
+    #[serde(flatten)]
+    password: PasswordSeal,
+
+    /// Master seed sealed in a single-blob envelope
+    sealed_seed: Vec<u8>,
+
+    /// Encryption key of the current epoch, sealed like the seed (empty at epoch 0)
+    #[serde(default)]
+    sealed_epoch_key: Vec<u8>,
+
+    /// Keys of the earlier rotated epochs, wrapped under the current epoch key
+    #[serde(default)]
+    wrapped_epoch_keys: Vec<Vec<u8>>,
+}
+
+impl Keystore {
+    /// Seal an identity's master seed under a password
+    pub fn create(identity: &UserIdentity, password: &str, kdf: KdfParams) -> Result<Self, IdentityError> {
+        let mut keystore = Self {
+            version: KEYSTORE_VERSION,
+            public_id: identity.public_id(),
+            key_epoch: identity.key_epoch(),
+            password: PasswordSeal::new(kdf),
+            sealed_seed: Vec::new(),
+            sealed_epoch_key: Vec::new(),
+            wrapped_epoch_keys: identity.wrapped_epoch_keys()?,
+        };
+        keystore.seal(identity, password, kdf)?;
+        Ok(keystore)
+    }
+
+    /// Unlock the keystore and rebuild the identity
+    /// Fails with `IdentityError::WrongPassword` if the password is wrong
+    /// (or the sealed seed or its parameters were modified)
+    pub fn unlock(&self, password: &str) -> Result<UserIdentity, IdentityError> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(IdentityError::Keystore(format!(
+                "Unsupported keystore version {}",
+                self.version
+            )));
+        }
+
+        let key = self.password.derive_key(password)?;
+        let seed = Zeroizing::new(
+            key.decrypt_with_aad(&self.sealed_seed, &self.associated_data())
+                .map_err(|_| IdentityError::WrongPassword)?,
+        );
+
+        let master_seed: [u8; 64] = seed
+            .as_slice()
+            .try_into()
+            .map_err(|_| IdentityError::Keystore("Invalid master seed length".into()))?;
+        let mut identity = UserIdentity::from_master_seed(Zeroizing::new(master_seed))?;
+        if self.key_epoch > 0 {
+            let epoch_key = Zeroizing::new(
+                key.decrypt_with_aad(&self.sealed_epoch_key, &self.epoch_key_associated_data())
+                    .map_err(|_| IdentityError::WrongPassword)?,
+            );
+            identity.restore_epoch_keys(&epoch_key, &self.wrapped_epoch_keys)?;
+        }
+
+        // The seed must lead back to the identity the keystore claims to hold
+        if identity.public_id() != self.public_id || identity.key_epoch() != self.key_epoch {
+            return Err(IdentityError::Keystore("Integrity check failed".into()));
+        }
+
+        Ok(identity)
+    }
+
+    /// Re-seal the master seed under a new password and cost parameters
+    pub fn change_password(
+        &mut self,
+        old_password: &str,
+        new_password: &str,
+        kdf: KdfParams,
+    ) -> Result<(), IdentityError> {
+        let identity = self.unlock(old_password)?;
+        self.seal(&identity, new_password, kdf)
+    }
+
+    /// Get the public ID of the stored identity
+    pub fn public_id(&self) -> &str {
+        &self.public_id
+    }
+
+    /// Get the Argon2id cost parameters
+    pub fn kdf_params(&self) -> KdfParams {
+        self.password.kdf
+    }
+
+    /// Export to JSON
+    pub fn to_json(&self) -> Result<String, IdentityError> {
+        sealed_file::to_json(self, IdentityError::Keystore)
+    }
+
+    /// Import from JSON
+    pub fn from_json(json: &str) -> Result<Self, IdentityError> {
+        sealed_file::from_json(json, IdentityError::Keystore)
+    }
+
+    /// Write the keystore to disk (atomically)
+    pub fn save(&self, path: &Path) -> Result<(), IdentityError> {
+        sealed_file::save(self, path, IdentityError::Keystore)
+    }
+
+    /// Read a keystore from disk
+    pub fn load(path: &Path) -> Result<Self, IdentityError> {
+        sealed_file::load(path, IdentityError::Keystore)
+    }
+
+    /// Encrypt the master seed (and current epoch key) under a fresh salt
+    fn seal(&mut self, identity: &UserIdentity, password: &str, kdf: KdfParams) -> Result<(), IdentityError> {
+        self.password = PasswordSeal::new(kdf);
+
+        let key = self.password.derive_key(password)?;
+        self.sealed_seed = key.encrypt_envelope_with_aad(
+            CipherSuite::XChaCha20Poly1305,
+            identity.master_seed(),
+            &self.associated_data(),
+        )?;
+        if self.key_epoch > 0 {
+            self.sealed_epoch_key = key.encrypt_envelope_with_aad(
+                CipherSuite::XChaCha20Poly1305,
+                identity.encryption_key().as_bytes(),
+                &self.epoch_key_associated_data(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Keystore fields authenticated along with the sealed seed
+    fn associated_data(&self) -> Vec<u8> {
+        self.sealed_fields("keystore seed")
+    }
+
+    /// Associated data of the sealed epoch key
+    fn epoch_key_associated_data(&self) -> Vec<u8> {
+        self.sealed_fields("keystore epoch key")
+    }
+
+    /// Keystore fields under a context naming the sealed blob
+    fn sealed_fields(&self, context: &str) -> Vec<u8> {
+        self.password.associated_data(
+            context,
+            &[&[self.version], self.public_id.as_bytes(), &self.key_epoch.to_be_bytes()],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters so tests run fast
+    fn test_params() -> KdfParams {
+        KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn test_save_load_unlock() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let keystore = Keystore::create(&identity, "correct horse", test_params()).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.keystore");
+        keystore.save(&path).unwrap();
+
+        let loaded = Keystore::load(&path).unwrap();
+        assert_eq!(loaded.public_id(), identity.public_id());
+        assert_eq!(loaded.kdf_params(), test_params());
+
+        let unlocked = loaded.unlock("correct horse").unwrap();
+        assert_eq!(unlocked.public_id(), identity.public_id());
+    }
+
+    #[test]
+    fn test_key_epoch_restored() {
+        let (mut identity, _) = UserIdentity::generate(None).unwrap();
+        identity.rotate_encryption_key().unwrap();
+        let epoch_1 = identity.encrypt(b"wrapped under epoch 1").unwrap();
+        identity.rotate_encryption_key().unwrap();
+        let mut keystore = Keystore::create(&identity, "password", test_params()).unwrap();
+
+        // Random epoch keys survive unlocking and a password change
+        keystore.change_password("password", "new password", test_params()).unwrap();
+        let unlocked = keystore.unlock("new password").unwrap();
+        assert_eq!(unlocked.key_epoch(), 2);
+        assert_eq!(unlocked.encryption_key().as_bytes(), identity.encryption_key().as_bytes());
+        assert_eq!(unlocked.decrypt_with_epoch(&epoch_1, 1).unwrap(), b"wrapped under epoch 1");
+
+        // Rolling the epoch back is detected
+        let mut rolled_back = keystore;
+        rolled_back.key_epoch = 1;
+        rolled_back.wrapped_epoch_keys.clear();
+        assert!(rolled_back.unlock("new password").is_err());
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let keystore = Keystore::create(&identity, "correct horse", test_params()).unwrap();
+
+        assert!(matches!(keystore.unlock("battery staple"), Err(IdentityError::WrongPassword)));
+    }
+
+    #[test]
+    fn test_change_password() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let mut keystore = Keystore::create(&identity, "old password", test_params()).unwrap();
+
+        let new_params = KdfParams {
+            iterations: 2,
+            ..test_params()
+        };
+        assert!(keystore.change_password("wrong", "new password", new_params).is_err());
+        keystore.change_password("old password", "new password", new_params).unwrap();
+
+        assert_eq!(keystore.kdf_params(), new_params);
+        assert!(keystore.unlock("old password").is_err());
+        assert_eq!(keystore.unlock("new password").unwrap().public_id(), identity.public_id());
+    }
+
+    #[test]
+    fn test_tampered_keystore_rejected() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let keystore = Keystore::create(&identity, "password", test_params()).unwrap();
+
+        // Swapping in another identity's public ID breaks the authenticated data
+        let (other, _) = UserIdentity::generate(None).unwrap();
+        let mut swapped = keystore.clone();
+        swapped.public_id = other.public_id();
+        assert!(swapped.unlock("password").is_err());
+
+        // Flipping a ciphertext bit is detected
+        let mut corrupted = keystore.clone();
+        let last = corrupted.sealed_seed.len() - 1;
+        corrupted.sealed_seed[last] ^= 1;
+        assert!(corrupted.unlock("password").is_err());
+
+        let mut future = keystore;
+        future.version = 9;
+        assert!(matches!(future.unlock("password"), Err(IdentityError::Keystore(_))));
+    }
+}
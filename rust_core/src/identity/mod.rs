@@ -5,14 +5,32 @@
 
 mod seed;
 mod keys;
-
-pub use seed::SeedPhrase;
+mod keystore;
+mod backup;
+mod sealed_file;
+mod device;
+mod revocation;
+mod document;
+mod inheritance;
+mod public_id;
+
+pub use seed::{Language, RecoveryCandidate, SeedPhrase, SeedShare};
 pub use keys::KeyPair;
-
-use crate::crypto::{self, EncryptionKey, SigningKeyPair};
+pub use keystore::Keystore;
+pub use backup::{BackupContents, Contact, IdentityBackup};
+pub use device::{CertifiedDevice, DeviceCertificate, DeviceKeys};
+pub use revocation::{RevocationList, Revocations, RevokedDevice};
+pub use document::{IdentityDocument, IdentityInfo};
+pub use inheritance::EscrowRecord;
+pub use public_id::{KeyType, OwnerSignatureError, PublicId, PublicIdError, PUBLIC_ID_PREFIX};
+
+use crate::crypto::{self, AgreementKeyPair, CipherSuite, EncryptionKey, Signed, SignedPayload, SigningKeyPair};
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use thiserror::Error;
+use x25519_dalek::PublicKey;
+use zeroize::Zeroizing;
 
 #[derive(Error, Debug)]
 pub enum IdentityError {
@@ -24,23 +42,54 @@ pub enum IdentityError {
 
     #[error("Cryptographic error: {0}")]
     Crypto(#[from] crypto::CryptoError),
+
+    #[error("Wrong password")]
+    WrongPassword,
+
+    #[error("Keystore error: {0}")]
+    Keystore(String),
+
+    #[error("Backup error: {0}")]
+    Backup(String),
+
+    #[error("Invalid device certificate: {0}")]
+    InvalidDeviceCertificate(String),
+
+    #[error("Invalid revocation list: {0}")]
+    InvalidRevocationList(String),
+
+    #[error("Invalid identity document: {0}")]
+    InvalidIdentityDocument(String),
+
+    #[error("Invalid escrow record: {0}")]
+    InvalidEscrow(String),
+
+    #[error("Invalid seed share: {0}")]
+    InvalidShare(String),
+
+    #[error("Invalid public ID: {0}")]
+    InvalidPublicId(#[from] PublicIdError),
 }
 
 /// User identity derived from seed phrase
-/// Contains all cryptographic keys needed for the system
-#[derive(Clone)]
+/// Contains all cryptographic keys needed for the system; every secret
+/// is wiped from memory when the identity is dropped
 pub struct UserIdentity {
     /// Master seed (derived from mnemonic + password)
-    master_seed: [u8; 64],
+    master_seed: Zeroizing<[u8; 64]>,
 
     /// Signing key pair for authentication and signatures
     signing_keys: SigningKeyPair,
 
-    /// Encryption key for file encryption
-    encryption_key: EncryptionKey,
+    /// X25519 key pair so others can encrypt to this identity
+    agreement_keys: AgreementKeyPair,
 
-    /// Node ID for P2P network (derived from public key)
-    node_id: [u8; 32],
+    /// Encryption keys for file encryption, indexed by key epoch (the last
+    /// one is current). Epoch 0 is derived from the seed, later epochs are random
+    epoch_keys: Vec<EncryptionKey>,
+
+    /// Public ID (derived from public key)
+    public_id: PublicId,
 }
 
 impl UserIdentity {
@@ -60,22 +109,40 @@ impl UserIdentity {
         password: Option<&str>,
     ) -> Result<Self, IdentityError> {
         let seed_phrase = SeedPhrase::from_phrase(mnemonic)?;
-        let master_seed = seed_phrase.to_seed(password.unwrap_or(""));
+        Self::from_master_seed(seed_phrase.to_seed(password.unwrap_or("")))
+    }
+
+    /// Recover identity from Shamir shares of its seed phrase
+    /// Needs at least the split's threshold of share word lists
+    pub fn from_shares(shares: &[&str], password: Option<&str>) -> Result<Self, IdentityError> {
+        let shares = shares
+            .iter()
+            .map(|words| SeedShare::from_words(words))
+            .collect::<Result<Vec<_>, _>>()?;
+        let seed_phrase = SeedPhrase::combine(&shares)?;
+        Self::from_master_seed(seed_phrase.to_seed(password.unwrap_or("")))
+    }
 
+    /// Rebuild identity from its master seed (e.g. one unlocked from a keystore)
+    pub(crate) fn from_master_seed(master_seed: Zeroizing<[u8; 64]>) -> Result<Self, IdentityError> {
         // Derive signing keys (for authentication)
         let signing_keys = Self::derive_signing_keys(&master_seed)?;
 
+        // Derive key-agreement keys (for receiving shared keys)
+        let agreement_keys = Self::derive_agreement_keys(&master_seed)?;
+
         // Derive encryption key (for file encryption)
         let encryption_key = Self::derive_encryption_key(&master_seed)?;
 
-        // Derive node ID from public key
-        let node_id = Self::derive_node_id(&signing_keys);
+        // Derive public ID from public key
+        let public_id = PublicId::from_key(&signing_keys.verifying_key);
 
         Ok(Self {
             master_seed,
             signing_keys,
-            encryption_key,
-            node_id,
+            agreement_keys,
+            epoch_keys: vec![encryption_key],
+            public_id,
         })
     }
 
@@ -85,8 +152,8 @@ impl UserIdentity {
         use sha2::Sha256;
 
         let hk = Hkdf::<Sha256>::new(Some(b"cloudp2p-signing"), master_seed);
-        let mut signing_seed = [0u8; 32];
-        hk.expand(b"ed25519-signing-key", &mut signing_seed)
+        let mut signing_seed = Zeroizing::new([0u8; 32]);
+        hk.expand(b"ed25519-signing-key", signing_seed.as_mut())
             .map_err(|e| IdentityError::KeyDerivation(e.to_string()))?;
 
         let signing_key = SigningKey::from_bytes(&signing_seed);
@@ -98,30 +165,57 @@ impl UserIdentity {
         })
     }
 
-    /// Derive AES-256 encryption key from master seed
+    /// Derive X25519 key-agreement keys from master seed
+    fn derive_agreement_keys(master_seed: &[u8; 64]) -> Result<AgreementKeyPair, IdentityError> {
+        use hkdf::Hkdf;
+        use sha2::Sha256;
+
+        let hk = Hkdf::<Sha256>::new(Some(b"cloudp2p-agreement"), master_seed);
+        let mut secret = Zeroizing::new([0u8; 32]);
+        hk.expand(b"x25519-agreement-key", secret.as_mut())
+            .map_err(|e| IdentityError::KeyDerivation(e.to_string()))?;
+
+        Ok(AgreementKeyPair::from_secret_bytes(*secret))
+    }
+
+    /// Derive the AES-256 encryption key of epoch 0 from master seed
+    /// (keys of later epochs are random, see `rotate_encryption_key`)
     fn derive_encryption_key(master_seed: &[u8; 64]) -> Result<EncryptionKey, IdentityError> {
         use hkdf::Hkdf;
         use sha2::Sha256;
 
         let hk = Hkdf::<Sha256>::new(Some(b"cloudp2p-encryption"), master_seed);
-        let mut enc_key = [0u8; 32];
-        hk.expand(b"aes-256-gcm-key", &mut enc_key)
+        let mut enc_key = Zeroizing::new([0u8; 32]);
+        hk.expand(b"aes-256-gcm-key", enc_key.as_mut())
             .map_err(|e| IdentityError::KeyDerivation(e.to_string()))?;
 
-        Ok(EncryptionKey::new(enc_key))
+        Ok(EncryptionKey::new(*enc_key))
+    }
+
+    /// Derive a key for one fixed purpose from master seed (`context` must be unique to it)
+    /// Unlike the encryption key it never changes with the key epoch
+    fn derive_purpose_key(master_seed: &[u8; 64], context: &str) -> EncryptionKey {
+        EncryptionKey::new(*Zeroizing::new(blake3::derive_key(context, master_seed)))
     }
 
-    /// Derive node ID from public signing key
-    fn derive_node_id(signing_keys: &SigningKeyPair) -> [u8; 32] {
-        use sha2::{Sha256, Digest};
-        let mut hasher = Sha256::new();
-        hasher.update(signing_keys.verifying_key.as_bytes());
-        hasher.finalize().into()
+    /// Get the public ID belonging to a signing public key
+    pub fn public_id_from_key(verifying_key: &VerifyingKey) -> String {
+        PublicId::from_key(verifying_key).to_string()
+    }
+
+    /// Get the master seed
+    pub(crate) fn master_seed(&self) -> &[u8; 64] {
+        &self.master_seed
     }
 
     /// Get public ID (can be shared with others)
     pub fn public_id(&self) -> String {
-        bs58::encode(&self.node_id).into_string()
+        self.public_id.to_string()
+    }
+
+    /// Get the parsed public ID
+    pub fn id(&self) -> &PublicId {
+        &self.public_id
     }
 
     /// Get the signing key pair
@@ -129,14 +223,82 @@ impl UserIdentity {
         &self.signing_keys
     }
 
-    /// Get the encryption key
+    /// Get the X25519 public key others use to seal keys to this identity
+    pub fn agreement_public_key(&self) -> &PublicKey {
+        self.agreement_keys.public_key()
+    }
+
+    /// Open a sealed box addressed to this identity (e.g. a shared file key)
+    pub fn open_sealed(&self, sealed: &[u8]) -> Result<Vec<u8>, IdentityError> {
+        self.agreement_keys.open(sealed).map_err(Into::into)
+    }
+
+    /// Get the encryption key (of the current key epoch)
     pub fn encryption_key(&self) -> &EncryptionKey {
-        &self.encryption_key
+        &self.epoch_keys[self.epoch_keys.len() - 1]
+    }
+
+    /// Secret scoping this identity's convergent keys and chunk IDs
+    /// Stable across key rotations, so deduplication keeps working after one
+    pub fn convergence_secret(&self) -> EncryptionKey {
+        Self::derive_purpose_key(&self.master_seed, "cloudp2p 2024-01 convergence scope")
+    }
+
+    /// Get the current key epoch
+    pub fn key_epoch(&self) -> u32 {
+        (self.epoch_keys.len() - 1) as u32
+    }
+
+    /// Rotate the encryption key to the next epoch and return it
+    /// The new key is random, so it cannot be derived from the seed phrase
+    /// (or by a device that only holds the seed); save a new `Keystore` or
+    /// `IdentityBackup` to keep it. New data is encrypted under the new key;
+    /// data wrapped under older epochs stays readable through
+    /// `decrypt_with_epoch` until re-wrapped.
+    pub fn rotate_encryption_key(&mut self) -> Result<u32, IdentityError> {
+        let epoch = u32::try_from(self.epoch_keys.len())
+            .map_err(|_| IdentityError::KeyDerivation("Key epoch overflow".into()))?;
+        self.epoch_keys.push(EncryptionKey::generate());
+        Ok(epoch)
+    }
+
+    /// Keys of the rotated epochs before the current one, each wrapped under
+    /// the current key; with the current key they restore every epoch
+    /// (see `restore_epoch_keys`)
+    pub(crate) fn wrapped_epoch_keys(&self) -> Result<Vec<Vec<u8>>, IdentityError> {
+        let current = self.encryption_key();
+        (1..self.key_epoch())
+            .map(|epoch| {
+                current
+                    .encrypt_envelope_with_aad(
+                        CipherSuite::XChaCha20Poly1305,
+                        self.epoch_keys[epoch as usize].as_bytes(),
+                        &epoch_key_aad(epoch),
+                    )
+                    .map_err(Into::into)
+            })
+            .collect()
+    }
+
+    /// Restore the rotated epochs saved in a keystore or backup
+    /// `current` is the raw key of the last epoch, `wrapped` the output of `wrapped_epoch_keys`
+    pub(crate) fn restore_epoch_keys(&mut self, current: &[u8], wrapped: &[Vec<u8>]) -> Result<(), IdentityError> {
+        let current = epoch_key_from_bytes(current)?;
+
+        let mut epoch_keys = vec![self.epoch_keys[0].clone()];
+        for (epoch, sealed) in (1u32..).zip(wrapped) {
+            let key = Zeroizing::new(current.decrypt_with_aad(sealed, &epoch_key_aad(epoch))?);
+            epoch_keys.push(epoch_key_from_bytes(&key)?);
+        }
+        epoch_keys.push(current);
+
+        self.epoch_keys = epoch_keys;
+        Ok(())
     }
 
     /// Get the node ID bytes
     pub fn node_id(&self) -> &[u8; 32] {
-        &self.node_id
+        self.public_id.node_id()
     }
 
     /// Sign a message
@@ -158,59 +320,77 @@ impl UserIdentity {
 
     /// Encrypt data with the user's encryption key
     pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, IdentityError> {
-        self.encryption_key.encrypt(plaintext).map_err(Into::into)
+        self.encryption_key().encrypt(plaintext).map_err(Into::into)
     }
 
     /// Decrypt data with the user's encryption key
     pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, IdentityError> {
-        self.encryption_key.decrypt(ciphertext).map_err(Into::into)
+        self.encryption_key().decrypt(ciphertext).map_err(Into::into)
     }
 
-    /// Generate a heartbeat message (to prove liveness)
-    pub fn generate_heartbeat(&self) -> HeartbeatMessage {
-        let timestamp = chrono::Utc::now().timestamp();
-        let message = format!("heartbeat:{}:{}", self.public_id(), timestamp);
-        let signature = self.sign(message.as_bytes());
+    /// Decrypt data encrypted under the key of the given epoch
+    pub fn decrypt_with_epoch(&self, ciphertext: &[u8], epoch: u32) -> Result<Vec<u8>, IdentityError> {
+        let key = self
+            .epoch_keys
+            .get(epoch as usize)
+            .ok_or_else(|| IdentityError::KeyDerivation(format!("Unknown key epoch {}", epoch)))?;
+        key.decrypt(ciphertext).map_err(Into::into)
+    }
 
-        HeartbeatMessage {
+    /// Generate a heartbeat message for one storage host (to prove liveness)
+    /// `sequence` must be higher than in any heartbeat sent to that host before
+    pub fn generate_heartbeat(&self, host_id: &str, sequence: u64) -> Result<HeartbeatMessage, IdentityError> {
+        let heartbeat = Heartbeat {
             node_id: self.public_id(),
-            timestamp,
-            signature,
-        }
+            host_id: host_id.to_string(),
+            sequence,
+        };
+        Signed::sign(heartbeat, &self.signing_keys).map_err(Into::into)
     }
 }
 
-/// Heartbeat message for proving liveness (avoids 90-day expiration)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct HeartbeatMessage {
-    pub node_id: String,
-    pub timestamp: i64,
-    pub signature: Vec<u8>,
+/// Associated data binding a wrapped epoch key to its epoch
+fn epoch_key_aad(epoch: u32) -> Vec<u8> {
+    format!("epoch-key:{}", epoch).into_bytes()
 }
 
-impl HeartbeatMessage {
-    /// Verify the heartbeat signature
-    pub fn verify(&self, verifying_key: &VerifyingKey) -> bool {
-        use ed25519_dalek::{Signature, Verifier};
+/// Parse a raw epoch key
+fn epoch_key_from_bytes(bytes: &[u8]) -> Result<EncryptionKey, IdentityError> {
+    let key: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| IdentityError::KeyDerivation("Invalid epoch key length".into()))?;
+    Ok(EncryptionKey::new(key))
+}
 
-        let message = format!("heartbeat:{}:{}", self.node_id, self.timestamp);
+impl fmt::Debug for UserIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserIdentity")
+            .field("public_id", &self.public_id())
+            .finish_non_exhaustive()
+    }
+}
 
-        if self.signature.len() != 64 {
-            return false;
-        }
-        let sig_bytes: [u8; 64] = self.signature.clone().try_into().unwrap();
-        let sig = Signature::from_bytes(&sig_bytes);
+/// Heartbeat for proving liveness (avoids 90-day expiration)
+/// Bound to one host and counter, so it cannot be replayed elsewhere or later
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    /// Public ID of the identity that is alive
+    pub node_id: String,
 
-        verifying_key.verify(message.as_bytes(), &sig).is_ok()
-    }
+    /// Public ID of the storage host the heartbeat is meant for
+    pub host_id: String,
 
-    /// Check if heartbeat is recent (within allowed window)
-    pub fn is_recent(&self, max_age_seconds: i64) -> bool {
-        let now = chrono::Utc::now().timestamp();
-        (now - self.timestamp).abs() < max_age_seconds
-    }
+    /// Monotonic counter (hosts reject anything not above the last one seen)
+    pub sequence: u64,
 }
 
+impl SignedPayload for Heartbeat {
+    const CONTEXT: &'static str = "heartbeat";
+}
+
+/// Signed heartbeat; the envelope's `signed_at` is the heartbeat time
+pub type HeartbeatMessage = Signed<Heartbeat>;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -224,6 +404,10 @@ mod tests {
 
         // Public ID should not be empty
         assert!(!identity.public_id().is_empty());
+
+        let public_id = PublicId::parse(&identity.public_id()).unwrap();
+        assert!(public_id.matches_key(&identity.signing_keys().verifying_key));
+        assert_eq!(public_id.node_id(), identity.node_id());
     }
 
     #[test]
@@ -262,9 +446,95 @@ mod tests {
     fn test_heartbeat() {
         let (identity, _) = UserIdentity::generate(None).unwrap();
 
-        let heartbeat = identity.generate_heartbeat();
+        let heartbeat = identity.generate_heartbeat("host", 1).unwrap();
 
-        assert!(heartbeat.verify(&identity.signing_keys().verifying_key));
+        assert!(heartbeat.verify(&identity.signing_keys().verifying_key).is_ok());
         assert!(heartbeat.is_recent(60)); // Within 60 seconds
     }
+
+    #[test]
+    fn test_master_seed_zeroized_on_drop() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        assert_ne!(*identity.master_seed, [0u8; 64]);
+
+        // Run the destructor in place, then inspect the memory it leaves behind
+        let mut slot = std::mem::MaybeUninit::new(identity);
+        unsafe {
+            std::ptr::drop_in_place(slot.as_mut_ptr());
+            assert_eq!(*(*slot.as_ptr()).master_seed, [0u8; 64]);
+        }
+    }
+
+    #[test]
+    fn test_debug_redacts_secrets() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let debug = format!("{:?} {:?}", identity, identity.signing_keys());
+
+        assert!(debug.contains(&identity.public_id()));
+        assert!(!debug.contains(&hex::encode(identity.master_seed.as_slice())));
+        assert!(!debug.contains(&hex::encode(identity.signing_keys().signing_key.as_bytes())));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let (mut identity, phrase) = UserIdentity::generate(None).unwrap();
+        let old_ciphertext = identity.encrypt(b"wrapped under epoch 0").unwrap();
+
+        assert_eq!(identity.rotate_encryption_key().unwrap(), 1);
+        assert_eq!(identity.key_epoch(), 1);
+
+        // The new key is different, old data stays readable by epoch
+        assert!(identity.decrypt(&old_ciphertext).is_err());
+        assert_eq!(identity.decrypt_with_epoch(&old_ciphertext, 0).unwrap(), b"wrapped under epoch 0");
+
+        // Rotated keys are random: the seed phrase alone does not lead to them
+        let new_ciphertext = identity.encrypt(b"wrapped under epoch 1").unwrap();
+        let mut recovered = UserIdentity::from_seed_phrase(&phrase, None).unwrap();
+        assert!(recovered.decrypt(&new_ciphertext).is_err());
+        assert!(recovered.decrypt_with_epoch(&new_ciphertext, 1).is_err());
+
+        // The current key unlocks the older rotated keys
+        identity.rotate_encryption_key().unwrap();
+        let current = identity.encryption_key().as_bytes().to_vec();
+        recovered.restore_epoch_keys(&current, &identity.wrapped_epoch_keys().unwrap()).unwrap();
+        assert_eq!(recovered.key_epoch(), 2);
+        assert_eq!(recovered.decrypt_with_epoch(&new_ciphertext, 1).unwrap(), b"wrapped under epoch 1");
+        assert_eq!(recovered.decrypt_with_epoch(&old_ciphertext, 0).unwrap(), b"wrapped under epoch 0");
+    }
+
+    #[test]
+    fn test_sealed_file_key_to_another_identity() {
+        let (alice, _) = UserIdentity::generate(None).unwrap();
+        let (bob, bob_phrase) = UserIdentity::generate(None).unwrap();
+        assert_ne!(alice.agreement_public_key(), bob.agreement_public_key());
+
+        let file_key = crypto::random_32_bytes();
+        let sealed = crypto::sealed_box::seal(bob.agreement_public_key(), &file_key).unwrap();
+
+        assert!(alice.open_sealed(&sealed).is_err());
+        assert_eq!(bob.open_sealed(&sealed).unwrap(), file_key);
+
+        // The agreement key is derived from the seed phrase
+        let recovered = UserIdentity::from_seed_phrase(&bob_phrase, None).unwrap();
+        assert_eq!(recovered.agreement_public_key(), bob.agreement_public_key());
+    }
+
+    #[test]
+    fn test_from_shares() {
+        let seed = SeedPhrase::generate(12).unwrap();
+        let identity = UserIdentity::from_seed_phrase(&seed.to_string(), Some("pw")).unwrap();
+
+        let shares: Vec<_> = seed.split(2, 3).unwrap().iter().map(|s| s.to_words()).collect();
+        let recovered = UserIdentity::from_shares(&[&shares[2], &shares[0]], Some("pw")).unwrap();
+        assert_eq!(recovered.public_id(), identity.public_id());
+
+        assert!(UserIdentity::from_shares(&[&shares[1]], Some("pw")).is_err());
+
+        // A non-English seed comes back in its own wordlist (and so as the same identity)
+        let seed = SeedPhrase::generate_in(Language::Portuguese, 12).unwrap();
+        let identity = UserIdentity::from_seed_phrase(&seed.to_string(), None).unwrap();
+        let shares: Vec<_> = seed.split(2, 3).unwrap().iter().map(|s| s.to_words()).collect();
+        let recovered = UserIdentity::from_shares(&[&shares[1], &shares[2]], None).unwrap();
+        assert_eq!(recovered.public_id(), identity.public_id());
+    }
 }
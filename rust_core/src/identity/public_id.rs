@@ -0,0 +1,295 @@
+//! Public ID format
+//!
+//! Human-readable form: `cp2p_` followed by base58 of
+//! version (1 byte) || key type (1 byte) || node ID (32 bytes) || checksum (4 bytes).
+//! The node ID is SHA-256 of the public key, and the checksum is the first
+//! 4 bytes of SHA-256 over everything before it, so a mistyped ID is rejected
+//! instead of silently naming nobody.
+
+use crate::crypto::{Signed, SignedPayload};
+
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Prefix of every public ID
+pub const PUBLIC_ID_PREFIX: &str = "cp2p_";
+
+/// Current public ID version
+const PUBLIC_ID_VERSION: u8 = 1;
+
+/// Checksum size
+const CHECKSUM_SIZE: usize = 4;
+
+/// Decoded size: version, key type, node ID, checksum
+const ENCODED_SIZE: usize = 2 + 32 + CHECKSUM_SIZE;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PublicIdError {
+    #[error("Public ID must start with {PUBLIC_ID_PREFIX}")]
+    MissingPrefix,
+
+    #[error("Public ID is not valid base58")]
+    InvalidEncoding,
+
+    #[error("Public ID has wrong length: {0} bytes")]
+    InvalidLength(usize),
+
+    #[error("Unsupported public ID version: {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Unsupported key type: {0}")]
+    UnsupportedKeyType(u8),
+
+    #[error("Public ID checksum mismatch (mistyped?)")]
+    ChecksumMismatch,
+}
+
+/// Why an envelope naming its owner's key failed to verify
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum OwnerSignatureError {
+    #[error("Invalid owner key")]
+    InvalidOwnerKey,
+
+    #[error("Owner key does not match owner ID")]
+    OwnerMismatch,
+
+    #[error("Signature verification failed")]
+    BadSignature,
+}
+
+/// Type of key a public ID refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyType {
+    Ed25519,
+}
+
+impl KeyType {
+    /// Wire identifier
+    fn id(self) -> u8 {
+        match self {
+            KeyType::Ed25519 => 1,
+        }
+    }
+
+    /// Parse a wire identifier
+    fn from_id(id: u8) -> Result<Self, PublicIdError> {
+        match id {
+            1 => Ok(KeyType::Ed25519),
+            _ => Err(PublicIdError::UnsupportedKeyType(id)),
+        }
+    }
+}
+
+/// Validated public ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicId {
+    key_type: KeyType,
+    node_id: [u8; 32],
+}
+
+impl PublicId {
+    /// Public ID of an Ed25519 signing key
+    pub fn from_key(verifying_key: &VerifyingKey) -> Self {
+        Self {
+            key_type: KeyType::Ed25519,
+            node_id: Sha256::digest(verifying_key.as_bytes()).into(),
+        }
+    }
+
+    /// Parse and validate a public ID
+    pub fn parse(s: &str) -> Result<Self, PublicIdError> {
+        let encoded = s.trim().strip_prefix(PUBLIC_ID_PREFIX).ok_or(PublicIdError::MissingPrefix)?;
+        let bytes = bs58::decode(encoded)
+            .into_vec()
+            .map_err(|_| PublicIdError::InvalidEncoding)?;
+        if bytes.len() != ENCODED_SIZE {
+            return Err(PublicIdError::InvalidLength(bytes.len()));
+        }
+
+        let (payload, checksum) = bytes.split_at(ENCODED_SIZE - CHECKSUM_SIZE);
+        if Self::checksum(payload) != checksum {
+            return Err(PublicIdError::ChecksumMismatch);
+        }
+        if payload[0] != PUBLIC_ID_VERSION {
+            return Err(PublicIdError::UnsupportedVersion(payload[0]));
+        }
+
+        Ok(Self {
+            key_type: KeyType::from_id(payload[1])?,
+            node_id: payload[2..].try_into().unwrap(),
+        })
+    }
+
+    /// Find every valid public ID mentioned in a text (e.g. a chat message)
+    pub fn find_all(text: &str) -> Vec<Self> {
+        text.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .filter(|word| word.starts_with(PUBLIC_ID_PREFIX))
+            .filter_map(|word| Self::parse(word).ok())
+            .collect()
+    }
+
+    /// Get the key type
+    pub fn key_type(&self) -> KeyType {
+        self.key_type
+    }
+
+    /// Get the node ID (SHA-256 of the public key)
+    pub fn node_id(&self) -> &[u8; 32] {
+        &self.node_id
+    }
+
+    /// Check whether this ID belongs to a signing key
+    pub fn matches_key(&self, verifying_key: &VerifyingKey) -> bool {
+        *self == Self::from_key(verifying_key)
+    }
+
+    /// Checksum over the version, key type and node ID
+    fn checksum(payload: &[u8]) -> [u8; CHECKSUM_SIZE] {
+        let digest = Sha256::digest(payload);
+        [digest[0], digest[1], digest[2], digest[3]]
+    }
+}
+
+impl<T: SignedPayload> Signed<T> {
+    /// Check that `owner_key` belongs to `owner_id` and signed this envelope
+    /// For records that carry their owner's key, so anyone holding just the
+    /// owner ID can check them
+    pub fn verify_by_owner(&self, owner_key: &[u8], owner_id: &str) -> Result<&T, OwnerSignatureError> {
+        let owner_key = owner_key
+            .try_into()
+            .ok()
+            .and_then(|key| VerifyingKey::from_bytes(key).ok())
+            .ok_or(OwnerSignatureError::InvalidOwnerKey)?;
+        if !PublicId::parse(owner_id).is_ok_and(|id| id.matches_key(&owner_key)) {
+            return Err(OwnerSignatureError::OwnerMismatch);
+        }
+        self.verify(&owner_key).map_err(|_| OwnerSignatureError::BadSignature)
+    }
+}
+
+impl fmt::Display for PublicId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = Vec::with_capacity(ENCODED_SIZE);
+        bytes.push(PUBLIC_ID_VERSION);
+        bytes.push(self.key_type.id());
+        bytes.extend_from_slice(&self.node_id);
+        let checksum = Self::checksum(&bytes);
+        bytes.extend_from_slice(&checksum);
+
+        write!(f, "{}{}", PUBLIC_ID_PREFIX, bs58::encode(bytes).into_string())
+    }
+}
+
+impl FromStr for PublicId {
+    type Err = PublicIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl Serialize for PublicId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Self::parse(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    fn test_key() -> VerifyingKey {
+        SigningKey::from_bytes(&[7u8; 32]).verifying_key()
+    }
+
+    #[test]
+    fn test_roundtrip_and_golden() {
+        let id = PublicId::from_key(&test_key());
+        let encoded = id.to_string();
+
+        assert!(encoded.starts_with(PUBLIC_ID_PREFIX));
+        assert_eq!(encoded, GOLDEN_ID);
+        assert_eq!(PublicId::parse(&encoded).unwrap(), id);
+        assert!(id.matches_key(&test_key()));
+        assert!(!id.matches_key(&SigningKey::from_bytes(&[8u8; 32]).verifying_key()));
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let encoded = PublicId::from_key(&test_key()).to_string();
+
+        assert_eq!(PublicId::parse(&encoded[PUBLIC_ID_PREFIX.len()..]), Err(PublicIdError::MissingPrefix));
+        assert_eq!(PublicId::parse("cp2p_0OIl"), Err(PublicIdError::InvalidEncoding));
+        assert!(matches!(PublicId::parse("cp2p_abc"), Err(PublicIdError::InvalidLength(_))));
+
+        // A single mistyped character is caught by the checksum
+        let mut typo: Vec<char> = encoded.chars().collect();
+        let i = typo.len() / 2;
+        typo[i] = if typo[i] == 'a' { 'b' } else { 'a' };
+        let typo: String = typo.into_iter().collect();
+        assert!(matches!(
+            PublicId::parse(&typo),
+            Err(PublicIdError::ChecksumMismatch | PublicIdError::InvalidLength(_))
+        ));
+    }
+
+    #[test]
+    fn test_find_in_chat_message() {
+        let id = PublicId::from_key(&test_key());
+        let message = format!("share it with {}, thanks! (not cp2p_nonsense)", id);
+
+        assert_eq!(PublicId::find_all(&message), vec![id]);
+    }
+
+    /// Public ID of the key with secret bytes [7; 32]
+    const GOLDEN_ID: &str = "cp2p_9eWQKJwycz4BddnwLjRgBoAxz9L94zcXp3qAmzUD4qvwdn3QBHf";
+
+    #[test]
+    fn test_verify_by_owner() {
+        use crate::crypto::SigningKeyPair;
+
+        #[derive(Debug, serde::Serialize)]
+        struct Note(String);
+        impl SignedPayload for Note {
+            const CONTEXT: &'static str = "note";
+        }
+
+        let keys = |seed: u8| {
+            let signing_key = SigningKey::from_bytes(&[seed; 32]);
+            SigningKeyPair {
+                verifying_key: signing_key.verifying_key(),
+                signing_key,
+            }
+        };
+        let (owner, other) = (keys(7), keys(8));
+        let owner_id = PublicId::from_key(&owner.verifying_key).to_string();
+        let owner_key = owner.verifying_key.as_bytes();
+        let note = Signed::sign(Note("hi".into()), &owner).unwrap();
+
+        assert_eq!(note.verify_by_owner(owner_key, &owner_id).unwrap().0, "hi");
+        assert_eq!(
+            note.verify_by_owner(&owner_key[1..], &owner_id).unwrap_err(),
+            OwnerSignatureError::InvalidOwnerKey
+        );
+        assert_eq!(
+            note.verify_by_owner(other.verifying_key.as_bytes(), &owner_id).unwrap_err(),
+            OwnerSignatureError::OwnerMismatch
+        );
+
+        // The right owner, but someone else signed
+        let forged = Signed::sign(Note("hi".into()), &other).unwrap();
+        assert_eq!(forged.verify_by_owner(owner_key, &owner_id).unwrap_err(), OwnerSignatureError::BadSignature);
+    }
+}
@@ -0,0 +1,187 @@
+//! Device revocation lists
+//!
+//! An identity publishes one root-signed list of revoked device keys to the
+//! DHT, as a `Signed<Revocations>` envelope. Each new list carries a higher sequence number, so hosts keep the
+//! newest one they have seen and an old list cannot be replayed to un-revoke
+//! a device.
+//!
+//! Revocation only cuts off a device key. Every device holds the master
+//! seed, so a device that is lost (rather than one whose key leaked) can
+//! still sign as the root key or certify itself again; only moving to a new
+//! identity locks it out.
+
+use super::{DeviceCertificate, IdentityError, UserIdentity};
+use crate::crypto::{Signed, SignedPayload};
+
+use serde::{Deserialize, Serialize};
+
+/// DHT key prefix for revocation lists
+const REVOCATION_DHT_PREFIX: &str = "/cloudp2p/revocations/";
+
+/// An identity's revoked devices (signed as `RevocationList`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Revocations {
+    /// Public ID of the identity
+    pub owner_id: String,
+
+    /// Root Ed25519 public key of the identity (must match `owner_id`)
+    pub owner_key: Vec<u8>,
+
+    /// Incremented with every new list
+    pub sequence: u64,
+
+    /// Revoked devices
+    pub revoked: Vec<RevokedDevice>,
+}
+
+impl SignedPayload for Revocations {
+    const CONTEXT: &'static str = "device revocations";
+}
+
+/// Revocations as published to the DHT; newer lists win by `sequence`
+pub type RevocationList = Signed<Revocations>;
+
+/// A revoked device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevokedDevice {
+    /// Device index under the identity
+    pub device_index: u32,
+
+    /// Device Ed25519 signing key
+    pub signing_key: Vec<u8>,
+
+    /// Revocation timestamp
+    pub revoked_at: i64,
+}
+
+impl Signed<Revocations> {
+    /// DHT key of an identity's revocation list
+    pub fn dht_key(owner_id: &str) -> Vec<u8> {
+        format!("{}{}", REVOCATION_DHT_PREFIX, owner_id).into_bytes()
+    }
+
+    /// Owner ID from a revocation list DHT key (None for other keys)
+    pub fn owner_from_dht_key(key: &[u8]) -> Option<&str> {
+        std::str::from_utf8(key).ok()?.strip_prefix(REVOCATION_DHT_PREFIX)
+    }
+
+    /// Check whether a device signing key is revoked
+    pub fn is_revoked(&self, signing_key: &[u8]) -> bool {
+        self.payload.revoked.iter().any(|d| d.signing_key == signing_key)
+    }
+
+    /// Check whether the device of a certificate is revoked
+    pub fn revokes(&self, certificate: &DeviceCertificate) -> bool {
+        certificate.payload.owner_id == self.payload.owner_id && self.is_revoked(&certificate.payload.signing_key)
+    }
+
+    /// Check that the identity itself revoked these devices
+    /// Anyone could otherwise publish a list cutting off someone else's devices
+    pub fn verify_revocations(&self) -> Result<&Revocations, IdentityError> {
+        self.verify_by_owner(&self.payload.owner_key, &self.payload.owner_id)
+            .map_err(|e| IdentityError::InvalidRevocationList(e.to_string()))
+    }
+
+    /// Serialize for the DHT
+    pub fn to_bytes(&self) -> Result<Vec<u8>, IdentityError> {
+        serde_json::to_vec(self).map_err(|e| IdentityError::InvalidRevocationList(e.to_string()))
+    }
+
+    /// Parse and verify a list fetched from the DHT
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IdentityError> {
+        let list: Self =
+            serde_json::from_slice(bytes).map_err(|e| IdentityError::InvalidRevocationList(e.to_string()))?;
+        list.verify_revocations()?;
+        Ok(list)
+    }
+}
+
+impl UserIdentity {
+    /// Revoke one of this identity's devices
+    /// Builds the next list from `previous` (the latest published list, if any)
+    pub fn revoke_device(
+        &self,
+        previous: Option<&RevocationList>,
+        device_index: u32,
+    ) -> Result<RevocationList, IdentityError> {
+        let now = chrono::Utc::now().timestamp();
+        let signing_key = self
+            .device_keys(device_index)?
+            .signing_keys()
+            .verifying_key
+            .as_bytes()
+            .to_vec();
+
+        let mut revoked = match previous {
+            Some(previous) => {
+                let previous = previous.verify_revocations()?;
+                if previous.owner_id != self.public_id() {
+                    return Err(IdentityError::InvalidRevocationList("List belongs to another identity".into()));
+                }
+                previous.revoked.clone()
+            }
+            None => vec![],
+        };
+        if !revoked.iter().any(|d| d.signing_key == signing_key) {
+            revoked.push(RevokedDevice {
+                device_index,
+                signing_key,
+                revoked_at: now,
+            });
+        }
+
+        let revocations = Revocations {
+            owner_id: self.public_id(),
+            owner_key: self.signing_keys().verifying_key.as_bytes().to_vec(),
+            sequence: previous.map_or(1, |p| p.payload.sequence + 1),
+            revoked,
+        };
+        Ok(Signed::sign_at(revocations, self.signing_keys(), now)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revoke_devices() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let laptop = identity.certify_device(&identity.device_keys(1).unwrap(), "laptop").unwrap();
+        let phone = identity.certify_device(&identity.device_keys(2).unwrap(), "phone").unwrap();
+
+        let first = identity.revoke_device(None, 1).unwrap();
+        assert_eq!(first.payload.sequence, 1);
+        assert!(first.revokes(&laptop));
+        assert!(!first.revokes(&phone));
+
+        let second = identity.revoke_device(Some(&first), 2).unwrap();
+        assert_eq!(second.payload.sequence, 2);
+        assert!(second.revokes(&laptop) && second.revokes(&phone));
+
+        let parsed = RevocationList::from_bytes(&second.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed, second);
+        assert_eq!(
+            RevocationList::owner_from_dht_key(&RevocationList::dht_key(&identity.public_id())),
+            Some(identity.public_id().as_str())
+        );
+    }
+
+    #[test]
+    fn test_tampered_revocation_list_rejected() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let list = identity.revoke_device(None, 1).unwrap();
+
+        // Dropping a revoked device breaks the signature
+        let mut unrevoked = list.clone();
+        unrevoked.payload.revoked.clear();
+        assert!(RevocationList::from_bytes(&unrevoked.to_bytes().unwrap()).is_err());
+
+        // Another identity cannot publish a list for this one
+        let (other, _) = UserIdentity::generate(None).unwrap();
+        let mut forged = other.revoke_device(None, 0).unwrap();
+        forged.payload.owner_id = identity.public_id();
+        assert!(forged.verify_revocations().is_err());
+        assert!(identity.revoke_device(Some(&other.revoke_device(None, 0).unwrap()), 2).is_err());
+    }
+}
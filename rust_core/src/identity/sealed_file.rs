@@ -0,0 +1,128 @@
+//! Password-sealed files
+//!
+//! The keystore and identity backups are both JSON files whose secrets are
+//! sealed under an Argon2id-derived key. This holds the parts they share:
+//! the salt and cost parameters, key derivation, associated data and atomic
+//! writes to disk.
+
+use super::IdentityError;
+use crate::crypto::{self, EncryptionKey, KdfParams};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Salt size for the password KDF
+const SALT_SIZE: usize = 16;
+
+/// Argon2id parameters and salt a file is sealed with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct PasswordSeal {
+    /// Argon2id cost parameters
+    pub(super) kdf: KdfParams,
+
+    /// Argon2id salt
+    pub(super) salt: Vec<u8>,
+}
+
+impl PasswordSeal {
+    /// Fresh salt with the given cost parameters
+    pub(super) fn new(kdf: KdfParams) -> Self {
+        Self {
+            kdf,
+            salt: crypto::random_bytes(SALT_SIZE),
+        }
+    }
+
+    /// Derive the sealing key from the password
+    pub(super) fn derive_key(&self, password: &str) -> Result<EncryptionKey, IdentityError> {
+        let key = crypto::derive_key_from_password_with_params(password.as_bytes(), &self.salt, &self.kdf)?;
+        Ok(EncryptionKey::new(*key))
+    }
+
+    /// Associated data binding `fields` and the cost parameters to a sealed blob
+    /// Every field is length-prefixed, so no two field lists encode the same
+    pub(super) fn associated_data(&self, context: &str, fields: &[&[u8]]) -> Vec<u8> {
+        let mut aad = Vec::new();
+        let mut push = |field: &[u8]| {
+            aad.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            aad.extend_from_slice(field);
+        };
+
+        push(context.as_bytes());
+        for field in fields {
+            push(field);
+        }
+        for param in [self.kdf.memory_kib, self.kdf.iterations, self.kdf.parallelism] {
+            push(&param.to_be_bytes());
+        }
+        aad
+    }
+}
+
+/// Encode a sealed file as JSON
+pub(super) fn to_json<T: Serialize>(
+    file: &T,
+    error: fn(String) -> IdentityError,
+) -> Result<String, IdentityError> {
+    serde_json::to_string_pretty(file).map_err(|e| error(e.to_string()))
+}
+
+/// Decode a sealed file from JSON
+pub(super) fn from_json<T: DeserializeOwned>(
+    json: &str,
+    error: fn(String) -> IdentityError,
+) -> Result<T, IdentityError> {
+    serde_json::from_str(json).map_err(|e| error(e.to_string()))
+}
+
+/// Write a sealed file to disk
+/// Goes through a temporary file so a crash never leaves a half-written file
+pub(super) fn save<T: Serialize>(
+    file: &T,
+    path: &Path,
+    error: fn(String) -> IdentityError,
+) -> Result<(), IdentityError> {
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, to_json(file, error)?).map_err(|e| error(e.to_string()))?;
+    std::fs::rename(&tmp_path, path).map_err(|e| error(e.to_string()))
+}
+
+/// Read a sealed file from disk
+pub(super) fn load<T: DeserializeOwned>(
+    path: &Path,
+    error: fn(String) -> IdentityError,
+) -> Result<T, IdentityError> {
+    let json = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    from_json(&json, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_associated_data_is_unambiguous() {
+        let seal = PasswordSeal::new(KdfParams::default());
+
+        // Field boundaries are part of the encoding
+        assert_ne!(
+            seal.associated_data("keystore", &[b"ab", b"c"]),
+            seal.associated_data("keystore", &[b"a", b"bc"])
+        );
+        assert_ne!(
+            seal.associated_data("keystore", &[b"a"]),
+            seal.associated_data("backup", &[b"a"])
+        );
+
+        // And so are the cost parameters
+        let cheaper = PasswordSeal {
+            kdf: KdfParams {
+                iterations: 1,
+                ..KdfParams::default()
+            },
+            ..seal.clone()
+        };
+        assert_ne!(seal.associated_data("keystore", &[]), cheaper.associated_data("keystore", &[]));
+    }
+}
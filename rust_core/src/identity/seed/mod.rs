@@ -2,10 +2,20 @@
 //!
 //! Generates and validates 10-word mnemonic phrases for identity recovery.
 
+mod recovery;
+mod shamir;
+
+pub use bip39::Language;
+pub use recovery::RecoveryCandidate;
+pub use shamir::SeedShare;
+
 use super::IdentityError;
-use bip39::{Language, Mnemonic};
+use bip39::Mnemonic;
+use std::fmt;
+use zeroize::Zeroizing;
 
 /// Wrapper around BIP39 mnemonic
+/// The mnemonic is wiped from memory on drop
 pub struct SeedPhrase {
     mnemonic: Mnemonic,
 }
@@ -14,6 +24,11 @@ impl SeedPhrase {
     /// Generate a new random seed phrase with specified word count
     /// For CloudP2P, we use 10 words (107 bits of entropy)
     pub fn generate(word_count: usize) -> Result<Self, IdentityError> {
+        Self::generate_in(Language::English, word_count)
+    }
+
+    /// Generate a new random seed phrase from the wordlist of a language
+    pub fn generate_in(language: Language, word_count: usize) -> Result<Self, IdentityError> {
         // BIP39 supports 12, 15, 18, 21, 24 words
         // For 10 words, we generate 12 and take the first 10
         // This gives us sufficient entropy while being user-friendly
@@ -41,21 +56,20 @@ impl SeedPhrase {
 
         // Generate entropy
         let entropy_bytes = entropy_bits / 8;
-        let mut entropy = vec![0u8; entropy_bytes];
+        let mut entropy = Zeroizing::new(vec![0u8; entropy_bytes]);
         rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut entropy);
 
         // Create mnemonic from entropy
-        let mnemonic = Mnemonic::from_entropy(&entropy)
+        let mnemonic = Mnemonic::from_entropy_in(language, &entropy)
             .map_err(|e| IdentityError::InvalidSeedPhrase(e.to_string()))?;
 
         Ok(Self { mnemonic })
     }
 
-    /// Parse an existing seed phrase
+    /// Parse an existing seed phrase (in any BIP39 language)
     pub fn from_phrase(phrase: &str) -> Result<Self, IdentityError> {
-        // Normalize whitespace
-        let normalized: Vec<&str> = phrase.split_whitespace().collect();
-        let normalized_phrase = normalized.join(" ");
+        // Normalize case, accents and whitespace
+        let normalized_phrase = recovery::normalize(phrase);
 
         let mnemonic = Mnemonic::parse_normalized(&normalized_phrase)
             .map_err(|e| IdentityError::InvalidSeedPhrase(e.to_string()))?;
@@ -63,9 +77,22 @@ impl SeedPhrase {
         Ok(Self { mnemonic })
     }
 
+    /// Rebuild a seed phrase from its entropy
+    /// The language matters: the BIP39 seed is derived from the words, not the entropy
+    pub(crate) fn from_entropy_in(language: Language, entropy: &[u8]) -> Result<Self, IdentityError> {
+        let mnemonic = Mnemonic::from_entropy_in(language, entropy)
+            .map_err(|e| IdentityError::InvalidSeedPhrase(e.to_string()))?;
+        Ok(Self { mnemonic })
+    }
+
+    /// Get the entropy behind the mnemonic
+    pub(crate) fn entropy(&self) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(self.mnemonic.to_entropy())
+    }
+
     /// Convert to seed bytes (512 bits) using optional passphrase
-    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
-        self.mnemonic.to_seed(passphrase)
+    pub fn to_seed(&self, passphrase: &str) -> Zeroizing<[u8; 64]> {
+        Zeroizing::new(self.mnemonic.to_seed(passphrase))
     }
 
     /// Get the mnemonic words as a string
@@ -80,21 +107,25 @@ impl SeedPhrase {
 
     /// Validate a seed phrase without creating an instance
     pub fn validate(phrase: &str) -> bool {
-        let normalized: Vec<&str> = phrase.split_whitespace().collect();
-        let normalized_phrase = normalized.join(" ");
+        Mnemonic::parse_normalized(&recovery::normalize(phrase)).is_ok()
+    }
 
-        Mnemonic::parse_normalized(&normalized_phrase).is_ok()
+    /// Get the language of the phrase's wordlist
+    pub fn language(&self) -> Language {
+        self.mnemonic.language()
     }
 
     /// Get word suggestions for autocomplete
     pub fn suggest_word(prefix: &str) -> Vec<&'static str> {
-        let wordlist = Language::English.word_list();
-        wordlist
-            .iter()
-            .filter(|word| word.starts_with(prefix))
-            .take(5)
-            .cloned()
-            .collect()
+        Self::suggest_word_in(Language::English, prefix)
+    }
+}
+
+impl fmt::Debug for SeedPhrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeedPhrase")
+            .field("words", &self.mnemonic.word_count())
+            .finish_non_exhaustive()
     }
 }
 
@@ -0,0 +1,211 @@
+//! Language detection, typo suggestions and one-word phrase recovery
+//!
+//! Phrases are normalized (NFKD, lowercase, single spaces) before lookup, so
+//! capitalized or accented input from phone keyboards still matches.
+
+use super::SeedPhrase;
+
+use bip39::{Language, Mnemonic};
+use std::borrow::Cow;
+use zeroize::Zeroizing;
+
+/// Word counts allowed by BIP39
+const VALID_WORD_COUNTS: [usize; 5] = [12, 15, 18, 21, 24];
+
+/// Largest edit distance offered as a correction
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// Candidate phrase that passes the BIP39 checksum
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryCandidate {
+    /// Full corrected phrase
+    pub phrase: Zeroizing<String>,
+
+    /// Position of the replaced or inserted word
+    pub position: usize,
+
+    /// Word put at that position
+    pub word: &'static str,
+}
+
+impl SeedPhrase {
+    /// Detect the wordlist a (possibly misspelled) phrase was written in
+    /// Picks the language that knows the most words of the phrase
+    pub fn detect_language(phrase: &str) -> Option<Language> {
+        let normalized = normalize(phrase);
+        let words: Vec<&str> = normalized.split_whitespace().collect();
+
+        Language::ALL
+            .iter()
+            .map(|&language| {
+                let known = words.iter().filter(|w| language.find_word(w).is_some()).count();
+                (language, known)
+            })
+            .filter(|&(_, known)| known > 0)
+            .max_by_key(|&(_, known)| known)
+            .map(|(language, _)| language)
+    }
+
+    /// Get word suggestions for autocomplete in a given language
+    pub fn suggest_word_in(language: Language, prefix: &str) -> Vec<&'static str> {
+        let prefix = normalize(prefix);
+        language
+            .words_by_prefix(&prefix)
+            .iter()
+            .take(5)
+            .cloned()
+            .collect()
+    }
+
+    /// Get the closest wordlist words to a misspelled word, nearest first
+    pub fn suggest_corrections(language: Language, word: &str) -> Vec<&'static str> {
+        let word = normalize(word);
+        if let Some(index) = language.find_word(&word) {
+            return vec![language.word_list()[index as usize]];
+        }
+
+        let mut candidates: Vec<(usize, &'static str)> = language
+            .word_list()
+            .iter()
+            .map(|&candidate| (edit_distance(&word, candidate), candidate))
+            .filter(|&(distance, _)| distance <= MAX_SUGGESTION_DISTANCE)
+            .collect();
+        candidates.sort();
+        candidates.into_iter().take(5).map(|(_, candidate)| candidate).collect()
+    }
+
+    /// List phrases that pass the checksum when exactly one word is wrong or missing
+    /// Unknown words are the only positions tried when present; otherwise every
+    /// position is. Candidates closest to what was typed come first.
+    pub fn recovery_candidates(phrase: &str) -> Vec<RecoveryCandidate> {
+        let Some(language) = Self::detect_language(phrase) else {
+            return vec![];
+        };
+        let normalized = normalize(phrase);
+        let words: Vec<&str> = normalized.split_whitespace().collect();
+
+        let mut candidates = Vec::new();
+        if VALID_WORD_COUNTS.contains(&(words.len() + 1)) {
+            // One word missing: try every word at every position
+            for position in 0..=words.len() {
+                for &word in language.word_list() {
+                    let mut attempt = words.clone();
+                    attempt.insert(position, word);
+                    push_if_valid(&mut candidates, language, &attempt, position, word, 0);
+                }
+            }
+        } else if VALID_WORD_COUNTS.contains(&words.len()) {
+            let unknown: Vec<usize> = (0..words.len())
+                .filter(|&i| language.find_word(words[i]).is_none())
+                .collect();
+            let positions: Vec<usize> = match unknown.len() {
+                0 => (0..words.len()).collect(),
+                1 => unknown,
+                _ => return vec![],
+            };
+
+            for position in positions {
+                for &word in language.word_list() {
+                    if word == words[position] {
+                        continue;
+                    }
+                    let distance = edit_distance(words[position], word);
+                    let mut attempt = words.clone();
+                    attempt[position] = word;
+                    push_if_valid(&mut candidates, language, &attempt, position, word, distance);
+                }
+            }
+        }
+
+        candidates.sort_by_key(|(distance, candidate)| (*distance, candidate.position));
+        candidates.into_iter().map(|(_, candidate)| candidate).collect()
+    }
+}
+
+/// Record a candidate if its words pass the checksum
+fn push_if_valid(
+    candidates: &mut Vec<(usize, RecoveryCandidate)>,
+    language: Language,
+    words: &[&str],
+    position: usize,
+    word: &'static str,
+    distance: usize,
+) {
+    let phrase = Zeroizing::new(words.join(" "));
+    if Mnemonic::parse_in_normalized(language, &phrase).is_ok() {
+        candidates.push((distance, RecoveryCandidate { phrase, position, word }));
+    }
+}
+
+/// Normalize user input: NFKD, lowercase, single spaces
+pub(super) fn normalize(input: &str) -> Zeroizing<String> {
+    let lowercase = Zeroizing::new(input.to_lowercase());
+    let mut cow = Cow::Borrowed(lowercase.as_str());
+    Mnemonic::normalize_utf8_cow(&mut cow);
+    Zeroizing::new(cow.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Levenshtein distance over characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_portuguese_phrase() {
+        let seed = SeedPhrase::generate_in(Language::Portuguese, 12).unwrap();
+        let phrase = seed.to_string();
+        assert_eq!(SeedPhrase::detect_language(&phrase), Some(Language::Portuguese));
+
+        // Phone keyboards capitalize the first word
+        let mut typed = phrase.clone();
+        typed[..1].make_ascii_uppercase();
+        let parsed = SeedPhrase::from_phrase(&typed).unwrap();
+        assert_eq!(parsed.to_seed(""), seed.to_seed(""));
+
+        assert!(SeedPhrase::suggest_word_in(Language::Portuguese, "abac").contains(&"abacate"));
+    }
+
+    #[test]
+    fn test_suggest_corrections() {
+        assert_eq!(edit_distance("abandno", "abandon"), 2);
+        assert_eq!(SeedPhrase::suggest_corrections(Language::English, "abandn")[0], "abandon");
+        assert_eq!(SeedPhrase::suggest_corrections(Language::Portuguese, "abacat")[0], "abacate");
+        assert!(SeedPhrase::suggest_corrections(Language::English, "xxxxxxxx").is_empty());
+    }
+
+    #[test]
+    fn test_recover_one_wrong_word() {
+        let seed = SeedPhrase::generate_in(Language::Portuguese, 12).unwrap();
+        let phrase = seed.to_string();
+        let mut words: Vec<String> = phrase.split_whitespace().map(String::from).collect();
+
+        // Typo: the intended word is offered first
+        let original = words[4].clone();
+        words[4].push('x');
+        let candidates = SeedPhrase::recovery_candidates(&words.join(" "));
+        assert_eq!(candidates[0].phrase.as_str(), phrase);
+        assert!(candidates.iter().all(|c| c.position == 4));
+
+        // Missing word: the original phrase is among the candidates
+        words.remove(4);
+        let candidates = SeedPhrase::recovery_candidates(&words.join(" "));
+        assert!(candidates
+            .iter()
+            .any(|c| c.phrase.as_str() == phrase && c.position == 4 && c.word == original));
+    }
+}
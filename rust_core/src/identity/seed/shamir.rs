@@ -0,0 +1,393 @@
+//! Shamir secret sharing of the seed entropy (SLIP-39 style)
+//!
+//! Splits the mnemonic entropy into N shares so that any T of them recover it
+//! and fewer reveal nothing. Arithmetic is over GF(256) with the AES
+//! polynomial, one polynomial per entropy byte, evaluated at x = share index.
+//!
+//! Each share is written as BIP39 English words (11 bits per word) encoding:
+//! format version || identifier (2 bytes, shared by all shares of one split)
+//! || threshold || index || seed language || entropy length || share value ||
+//! checksum (first 4 bytes of SHA-256 over everything before it), zero-padded
+//! to a whole word. The seed language is kept because the BIP39 seed is
+//! derived from the words, so the same entropy in another wordlist would
+//! restore a different identity.
+
+use super::SeedPhrase;
+use crate::identity::IdentityError;
+
+use bip39::Language;
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Share format version (the first, unversioned format had no language byte)
+const SHARE_VERSION: u8 = 1;
+
+/// Header size: version, identifier, threshold, index, language, entropy length
+const HEADER_SIZE: usize = 7;
+
+/// Seed wordlists by their code in the share header
+const LANGUAGES: [Language; 10] = [
+    Language::English,
+    Language::SimplifiedChinese,
+    Language::TraditionalChinese,
+    Language::Czech,
+    Language::French,
+    Language::Italian,
+    Language::Japanese,
+    Language::Korean,
+    Language::Portuguese,
+    Language::Spanish,
+];
+
+/// Checksum size
+const CHECKSUM_SIZE: usize = 4;
+
+/// One share of a split seed
+/// The share value is wiped from memory on drop
+#[derive(Clone)]
+pub struct SeedShare {
+    identifier: u16,
+    threshold: u8,
+    index: u8,
+    language: Language,
+    value: Zeroizing<Vec<u8>>,
+}
+
+impl SeedShare {
+    /// Identifier shared by all shares of one split
+    pub fn identifier(&self) -> u16 {
+        self.identifier
+    }
+
+    /// Number of shares needed to recover the seed
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// Share index (1-based)
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Wordlist of the seed phrase the shares recover
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Encode as a checksummed word list
+    pub fn to_words(&self) -> Zeroizing<String> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(HEADER_SIZE + self.value.len() + CHECKSUM_SIZE));
+        let language = LANGUAGES
+            .iter()
+            .position(|&l| l == self.language)
+            .expect("every wordlist has a code") as u8;
+        bytes.push(SHARE_VERSION);
+        bytes.extend_from_slice(&self.identifier.to_be_bytes());
+        bytes.extend_from_slice(&[self.threshold, self.index, language, self.value.len() as u8]);
+        bytes.extend_from_slice(&self.value);
+        let checksum = checksum(&bytes);
+        bytes.extend_from_slice(&checksum);
+
+        let words = Language::English.word_list();
+        let bit = |i: usize| bytes.get(i / 8).is_some_and(|b| b >> (7 - i % 8) & 1 == 1);
+        let word_count = (bytes.len() * 8).div_ceil(11);
+        let phrase = (0..word_count)
+            .map(|w| {
+                let index = (0..11).fold(0usize, |acc, i| acc << 1 | bit(w * 11 + i) as usize);
+                words[index]
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        Zeroizing::new(phrase)
+    }
+
+    /// Parse a word list written by `to_words`
+    pub fn from_words(phrase: &str) -> Result<Self, IdentityError> {
+        let invalid = |reason: &str| IdentityError::InvalidShare(reason.to_string());
+
+        let mut bits = Zeroizing::new(Vec::new());
+        for word in phrase.split_whitespace() {
+            let index = Language::English
+                .find_word(&word.to_lowercase())
+                .ok_or_else(|| IdentityError::InvalidShare(format!("Unknown word: {}", word)))?;
+            bits.extend((0..11).rev().map(|i| (index >> i) & 1 == 1));
+        }
+        let mut bytes = Zeroizing::new(
+            bits.chunks(8)
+                .filter(|chunk| chunk.len() == 8)
+                .map(|chunk| chunk.iter().fold(0u8, |acc, &b| acc << 1 | b as u8))
+                .collect::<Vec<u8>>(),
+        );
+        if bytes.len() < HEADER_SIZE + CHECKSUM_SIZE {
+            return Err(invalid("Share too short"));
+        }
+
+        if bytes[0] != SHARE_VERSION {
+            return Err(invalid("Unsupported share version"));
+        }
+
+        // The entropy length fixes where the share ends; the rest is padding
+        let total = HEADER_SIZE + bytes[6] as usize + CHECKSUM_SIZE;
+        if bytes.len() < total || bits[total * 8..].iter().any(|&b| b) || (bits.len() - total * 8) >= 11 {
+            return Err(invalid("Invalid share length"));
+        }
+        bytes.truncate(total);
+
+        let (body, expected) = bytes.split_at(total - CHECKSUM_SIZE);
+        if checksum(body) != expected {
+            return Err(invalid("Checksum mismatch"));
+        }
+
+        let language = *LANGUAGES
+            .get(body[5] as usize)
+            .ok_or_else(|| invalid("Unknown seed language"))?;
+        let share = Self {
+            identifier: u16::from_be_bytes([body[1], body[2]]),
+            threshold: body[3],
+            index: body[4],
+            language,
+            value: Zeroizing::new(body[HEADER_SIZE..].to_vec()),
+        };
+        if share.threshold == 0 || share.index == 0 {
+            return Err(invalid("Invalid share header"));
+        }
+        Ok(share)
+    }
+}
+
+impl fmt::Debug for SeedShare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeedShare")
+            .field("identifier", &self.identifier)
+            .field("threshold", &self.threshold)
+            .field("index", &self.index)
+            .field("language", &self.language)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SeedPhrase {
+    /// Split the seed entropy into `count` shares, any `threshold` of which recover it
+    pub fn split(&self, threshold: u8, count: u8) -> Result<Vec<SeedShare>, IdentityError> {
+        split_entropy(self.language(), &self.entropy(), threshold, count, &mut rand::thread_rng())
+    }
+
+    /// Recover a seed phrase from at least `threshold` shares of one split
+    pub fn combine(shares: &[SeedShare]) -> Result<Self, IdentityError> {
+        let entropy = combine_entropy(shares)?;
+        Self::from_entropy_in(shares[0].language, &entropy)
+    }
+}
+
+/// Split entropy with the given randomness source
+fn split_entropy<R: RngCore + CryptoRng>(
+    language: Language,
+    entropy: &[u8],
+    threshold: u8,
+    count: u8,
+    rng: &mut R,
+) -> Result<Vec<SeedShare>, IdentityError> {
+    if threshold == 0 || threshold > count {
+        return Err(IdentityError::InvalidShare(format!(
+            "Threshold must be between 1 and {}",
+            count
+        )));
+    }
+    if entropy.is_empty() || entropy.len() > u8::MAX as usize {
+        return Err(IdentityError::InvalidShare("Invalid entropy length".into()));
+    }
+
+    let identifier = rng.next_u32() as u16;
+
+    // coefficients[j] holds the degree j coefficient of every byte's polynomial
+    let mut coefficients = vec![Zeroizing::new(entropy.to_vec())];
+    for _ in 1..threshold {
+        let mut coefficient = Zeroizing::new(vec![0u8; entropy.len()]);
+        rng.fill_bytes(&mut coefficient);
+        coefficients.push(coefficient);
+    }
+
+    let shares = (1..=count)
+        .map(|x| {
+            let value = (0..entropy.len())
+                .map(|i| {
+                    // Horner's rule
+                    coefficients.iter().rev().fold(0u8, |acc, c| gf_mul(acc, x) ^ c[i])
+                })
+                .collect();
+            SeedShare {
+                identifier,
+                threshold,
+                index: x,
+                language,
+                value: Zeroizing::new(value),
+            }
+        })
+        .collect();
+    Ok(shares)
+}
+
+/// Recombine entropy by Lagrange interpolation at x = 0
+fn combine_entropy(shares: &[SeedShare]) -> Result<Zeroizing<Vec<u8>>, IdentityError> {
+    let first = shares
+        .first()
+        .ok_or_else(|| IdentityError::InvalidShare("No shares given".into()))?;
+
+    for share in shares {
+        if share.identifier != first.identifier
+            || share.threshold != first.threshold
+            || share.language != first.language
+            || share.value.len() != first.value.len()
+        {
+            return Err(IdentityError::InvalidShare("Shares belong to different splits".into()));
+        }
+    }
+
+    let mut used: Vec<&SeedShare> = Vec::new();
+    for share in shares {
+        if !used.iter().any(|s| s.index == share.index) {
+            used.push(share);
+        }
+    }
+    if used.len() < first.threshold as usize {
+        return Err(IdentityError::InvalidShare(format!(
+            "Need {} distinct shares, got {}",
+            first.threshold,
+            used.len()
+        )));
+    }
+    used.truncate(first.threshold as usize);
+
+    let mut entropy = Zeroizing::new(vec![0u8; first.value.len()]);
+    for share in &used {
+        // Lagrange basis polynomial of this share, evaluated at 0
+        let basis = used
+            .iter()
+            .filter(|other| other.index != share.index)
+            .fold(1u8, |acc, other| {
+                gf_mul(acc, gf_div(other.index, other.index ^ share.index))
+            });
+        for (byte, value) in entropy.iter_mut().zip(share.value.iter()) {
+            *byte ^= gf_mul(basis, *value);
+        }
+    }
+    Ok(entropy)
+}
+
+/// Multiply in GF(256) modulo x^8 + x^4 + x^3 + x + 1
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    while b != 0 {
+        if b & 1 == 1 {
+            product ^= a;
+        }
+        let carry = a & 0x80;
+        a <<= 1;
+        if carry != 0 {
+            a ^= 0x1B;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+/// Divide in GF(256) (b must be non-zero)
+fn gf_div(a: u8, b: u8) -> u8 {
+    // b^254 = b^-1
+    let mut inverse = 1u8;
+    let mut power = b;
+    let mut exponent = 254u8;
+    while exponent != 0 {
+        if exponent & 1 == 1 {
+            inverse = gf_mul(inverse, power);
+        }
+        power = gf_mul(power, power);
+        exponent >>= 1;
+    }
+    gf_mul(a, inverse)
+}
+
+/// Share checksum
+fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let digest = Sha256::digest(bytes);
+    [digest[0], digest[1], digest[2], digest[3]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    const ENTROPY: [u8; 16] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF,
+    ];
+
+    #[test]
+    fn test_gf_arithmetic() {
+        // FIPS-197 example: {57} * {83} = {c1}
+        assert_eq!(gf_mul(0x57, 0x83), 0xC1);
+        for b in 1..=255u8 {
+            assert_eq!(gf_mul(gf_div(1, b), b), 1);
+        }
+    }
+
+    #[test]
+    fn test_any_threshold_subset_recovers() {
+        let seed = SeedPhrase::generate(24).unwrap();
+        let shares = seed.split(3, 5).unwrap();
+
+        for subset in [[0, 1, 2], [0, 2, 4], [4, 3, 1]] {
+            let chosen: Vec<SeedShare> = subset.iter().map(|&i| shares[i].clone()).collect();
+            assert_eq!(SeedPhrase::combine(&chosen).unwrap().to_string(), seed.to_string());
+        }
+
+        assert!(SeedPhrase::combine(&shares[..2]).is_err());
+        assert!(SeedPhrase::combine(&[shares[0].clone(), shares[0].clone(), shares[1].clone()]).is_err());
+    }
+
+    #[test]
+    fn test_share_words_roundtrip_and_checksum() {
+        let seed = SeedPhrase::generate(12).unwrap();
+        let share = seed.split(2, 3).unwrap().remove(1);
+
+        let words = share.to_words();
+        assert_eq!(words.split_whitespace().count(), 20);
+        let parsed = SeedShare::from_words(&words).unwrap();
+        assert_eq!(parsed.index(), 2);
+        assert_eq!(parsed.threshold(), 2);
+        assert_eq!(*parsed.value, *share.value);
+
+        // Swapping a word is caught by the checksum
+        let mut altered: Vec<&str> = words.split_whitespace().collect();
+        altered[5] = if altered[5] == "zoo" { "zebra" } else { "zoo" };
+        assert!(SeedShare::from_words(&altered.join(" ")).is_err());
+        assert!(SeedShare::from_words("abandon ability").is_err());
+    }
+
+    #[test]
+    fn test_vectors() {
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(2024);
+        let shares = split_entropy(Language::English, &ENTROPY, 2, 3, &mut rng).unwrap();
+        let words: Vec<String> = shares.iter().map(|s| s.to_words().to_string()).collect();
+
+        assert_eq!(words, SHARE_VECTORS);
+
+        // Any two of the pinned shares recover the entropy
+        for pair in [[0, 1], [1, 2], [2, 0]] {
+            let chosen: Vec<SeedShare> = pair
+                .iter()
+                .map(|&i| SeedShare::from_words(SHARE_VECTORS[i]).unwrap())
+                .collect();
+            assert_eq!(*combine_entropy(&chosen).unwrap(), ENTROPY);
+        }
+    }
+
+    /// 2-of-3 split of `ENTROPY`
+    const SHARE_VECTORS: [&str; 3] = [
+        "account awake letter acoustic absurd chief juice brain pink sure enlist base field dance practice order damage half immense champion",
+        "account awake letter advice absurd excuse exotic swing oven enable crop cargo pitch lunar adapt own insane reunion dolphin capital",
+        "account awake letter alcohol absurd hand hobby letter lady debris tooth cannon hello use october say rubber body flush source",
+    ];
+}
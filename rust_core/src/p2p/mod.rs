@@ -8,8 +8,12 @@ mod discovery;
 mod storage_protocol;
 
 pub use node::{P2PNode, P2PNodeConfig, P2PEvent};
-pub use protocol::{StorageRequest, StorageResponse};
+pub use protocol::{
+    ChallengeRequest, ContractAcceptance, DeleteRequest, OwnerSignature, RetrieveRequest, StorageContract,
+    StorageRequest, StorageResponse, StoreRequest,
+};
 pub use discovery::PeerInfo;
+pub use storage_protocol::StorageManager;
 
 use thiserror::Error;
 
@@ -35,4 +39,19 @@ pub enum P2PError {
 
     #[error("Timeout")]
     Timeout,
+
+    #[error("Unauthorized request: {0}")]
+    Unauthorized(String),
+
+    #[error("Device revoked: {0}")]
+    DeviceRevoked(String),
+
+    #[error("Unknown identity: {0}")]
+    UnknownIdentity(String),
+
+    #[error("Replayed message: {0}")]
+    Replay(String),
+
+    #[error("Escrow still locked: {0}")]
+    EscrowLocked(String),
 }
@@ -1,7 +1,7 @@
 //! P2P Node implementation using libp2p
 
-use super::{P2PError, StorageRequest, StorageResponse};
-use crate::identity::UserIdentity;
+use super::{protocol::ErrorCode, P2PError, StorageManager, StorageRequest, StorageResponse};
+use crate::identity::{DeviceCertificate, IdentityDocument, RevocationList, UserIdentity};
 
 use libp2p::{
     autonat,
@@ -20,12 +20,15 @@ use libp2p::{
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
 const PROTOCOL_VERSION: &str = "/cloudp2p/1.0.0";
 const STORAGE_PROTOCOL: &str = "/cloudp2p/storage/1.0.0";
 
+/// How long a resolved identity document is used before looking it up again
+const IDENTITY_CACHE_TTL: Duration = Duration::from_secs(3600);
+
 /// Configuration for P2P node
 #[derive(Debug, Clone)]
 pub struct P2PNodeConfig {
@@ -43,6 +46,10 @@ pub struct P2PNodeConfig {
 
     /// External address (if known)
     pub external_address: Option<Multiaddr>,
+
+    /// Index of this device under the identity (each device needs its own,
+    /// since the PeerId is derived from the device keys)
+    pub device_index: u32,
 }
 
 impl Default for P2PNodeConfig {
@@ -56,6 +63,7 @@ impl Default for P2PNodeConfig {
                 "/ip4/0.0.0.0/udp/0/quic-v1".parse().unwrap(),
             ],
             external_address: None,
+            device_index: 0,
         }
     }
 }
@@ -94,6 +102,12 @@ pub enum P2PEvent {
         value: Vec<u8>,
     },
 
+    /// Found a valid device revocation list in DHT
+    RevocationList(RevocationList),
+
+    /// Found a valid identity document in DHT
+    IdentityDocument(IdentityDocument),
+
     /// Network status update
     NetworkStatus {
         connected_peers: usize,
@@ -154,6 +168,12 @@ pub struct P2PNode {
 
     /// Peer storage info (how much each peer offers/uses)
     peer_storage_info: HashMap<PeerId, PeerStorageInfo>,
+
+    /// Resolved identity documents with the time they were cached
+    identity_cache: HashMap<String, (IdentityDocument, Instant)>,
+
+    /// Local storage answering peers' storage requests
+    storage: Option<StorageManager>,
 }
 
 /// Storage info for a peer
@@ -179,7 +199,7 @@ impl P2PNode {
         config: P2PNodeConfig,
     ) -> Result<Self, P2PError> {
         // Create libp2p keypair from identity
-        let keypair = Self::derive_libp2p_keypair(identity)?;
+        let keypair = Self::derive_libp2p_keypair(identity, config.device_index)?;
         let local_peer_id = PeerId::from(keypair.public());
 
         tracing::info!("Creating P2P node with PeerId: {}", local_peer_id);
@@ -197,33 +217,39 @@ impl P2PNode {
             event_rx,
             connected_peers: HashSet::new(),
             peer_storage_info: HashMap::new(),
+            identity_cache: HashMap::new(),
+            storage: None,
         })
     }
 
-    /// Derive libp2p keypair from user identity
+    /// Derive libp2p keypair from the keys of one of the identity's devices
     fn derive_libp2p_keypair(
         identity: &UserIdentity,
+        device_index: u32,
     ) -> Result<libp2p::identity::Keypair, P2PError> {
-        use hkdf::Hkdf;
-        use sha2::Sha256;
-
-        // Derive a separate key for libp2p from the signing key
-        let hk = Hkdf::<Sha256>::new(
-            Some(b"cloudp2p-libp2p"),
-            identity.signing_keys().signing_key.as_bytes(),
-        );
-
-        let mut libp2p_seed = [0u8; 32];
-        hk.expand(b"libp2p-ed25519", &mut libp2p_seed)
+        let device = identity
+            .device_keys(device_index)
             .map_err(|e| P2PError::InitializationFailed(e.to_string()))?;
 
-        let secret_key = libp2p::identity::ed25519::SecretKey::try_from_bytes(libp2p_seed)
+        let secret_key = libp2p::identity::ed25519::SecretKey::try_from_bytes(*device.network_seed())
             .map_err(|e| P2PError::InitializationFailed(e.to_string()))?;
 
         let keypair = libp2p::identity::ed25519::Keypair::from(secret_key);
         Ok(libp2p::identity::Keypair::from(keypair))
     }
 
+    /// PeerId of the device named in a certificate (after verifying it)
+    /// A peer presenting this certificate from that PeerId acts for `owner_id`
+    pub fn certified_peer_id(certificate: &DeviceCertificate) -> Result<PeerId, P2PError> {
+        let device = certificate
+            .verify_certificate()
+            .map_err(|e| P2PError::Protocol(e.to_string()))?;
+
+        let public_key = libp2p::identity::ed25519::PublicKey::try_from_bytes(&device.network_key)
+            .map_err(|e| P2PError::Protocol(e.to_string()))?;
+        Ok(PeerId::from(libp2p::identity::PublicKey::from(public_key)))
+    }
+
     /// Build the libp2p swarm with all protocols
     async fn build_swarm(
         keypair: libp2p::identity::Keypair,
@@ -403,6 +429,95 @@ impl P2PNode {
             .get_record(RecordKey::new(&key))
     }
 
+    /// Publish an identity's device revocation list to DHT
+    pub fn publish_revocations(&mut self, list: &RevocationList) -> Result<(), P2PError> {
+        let revocations = list.verify_revocations().map_err(|e| P2PError::Dht(e.to_string()))?;
+        let value = list.to_bytes().map_err(|e| P2PError::Dht(e.to_string()))?;
+        self.put_dht(RevocationList::dht_key(&revocations.owner_id), value)
+    }
+
+    /// Look up an identity's device revocation list in DHT
+    /// A valid list arrives as `P2PEvent::RevocationList`
+    pub fn fetch_revocations(&mut self, owner_id: &str) -> kad::QueryId {
+        self.get_dht(RevocationList::dht_key(owner_id))
+    }
+
+    /// Publish an identity document to DHT
+    pub fn publish_identity(&mut self, document: &IdentityDocument) -> Result<(), P2PError> {
+        let info = document.verify_document().map_err(|e| P2PError::Dht(e.to_string()))?;
+        let value = document.to_bytes().map_err(|e| P2PError::Dht(e.to_string()))?;
+        self.put_dht(IdentityDocument::dht_key(&info.owner_id), value)?;
+        self.cache_identity(document.clone());
+        Ok(())
+    }
+
+    /// Resolve an identity document
+    /// Returns the cached document while it is fresh; otherwise starts a DHT
+    /// lookup whose result arrives as `P2PEvent::IdentityDocument`
+    pub fn resolve_identity(&mut self, owner_id: &str) -> Option<IdentityDocument> {
+        if let Some((document, cached_at)) = self.identity_cache.get(owner_id) {
+            if cached_at.elapsed() < IDENTITY_CACHE_TTL {
+                return Some(document.clone());
+            }
+        }
+        self.get_dht(IdentityDocument::dht_key(owner_id));
+        None
+    }
+
+    /// Get a cached identity document, however old
+    pub fn cached_identity(&self, owner_id: &str) -> Option<&IdentityDocument> {
+        self.identity_cache.get(owner_id).map(|(document, _)| document)
+    }
+
+    /// Cache a verified identity document
+    /// Returns false if it is older than the cached one
+    fn cache_identity(&mut self, document: IdentityDocument) -> bool {
+        if let Some((known, _)) = self.identity_cache.get(&document.payload.owner_id) {
+            if known.payload.sequence > document.payload.sequence {
+                return false;
+            }
+        }
+        self.identity_cache
+            .insert(document.payload.owner_id.clone(), (document, Instant::now()));
+        true
+    }
+
+    /// Answer peers' storage requests from a storage manager
+    /// Without one, storage requests are refused
+    pub fn set_storage_manager(&mut self, storage: StorageManager) {
+        self.storage = Some(storage);
+    }
+
+    /// Get the storage manager
+    pub fn storage_manager(&mut self) -> Option<&mut StorageManager> {
+        self.storage.as_mut()
+    }
+
+    /// Answer a storage request with the storage manager
+    /// Owner requests are authorized first. If the owner's identity document
+    /// is not known yet it is resolved: a cached one is recorded and the
+    /// request retried, otherwise the request is refused while the DHT
+    /// lookup runs and the owner has to try again
+    async fn answer_storage_request(&mut self, request: &StorageRequest) -> StorageResponse {
+        let Some(storage) = self.storage.as_mut() else {
+            return StorageResponse::error(ErrorCode::PermissionDenied, "This node offers no storage");
+        };
+        let mut result = storage.handle_request(request).await;
+
+        if let Err(P2PError::UnknownIdentity(owner_id)) = &result {
+            if let Some(document) = self.resolve_identity(owner_id) {
+                if let Some(storage) = self.storage.as_mut() {
+                    if let Err(e) = storage.update_identity(document) {
+                        tracing::warn!("Ignoring identity document for {}: {}", owner_id, e);
+                    }
+                    result = storage.handle_request(request).await;
+                }
+            }
+        }
+
+        result.unwrap_or_else(|e| StorageResponse::from_error(&e))
+    }
+
     /// Send a storage request to a peer
     pub fn send_storage_request(
         &mut self,
@@ -474,8 +589,44 @@ impl P2PNode {
                 result: kad::QueryResult::GetRecord(Ok(kad::GetRecordOk::FoundRecord(record))),
                 ..
             }) => {
+                let key = record.record.key.to_vec();
+
+                // Revocation lists are only passed on if the owner signed them
+                if let Some(owner_id) = RevocationList::owner_from_dht_key(&key) {
+                    match RevocationList::from_bytes(&record.record.value) {
+                        Ok(list) if list.payload.owner_id == owner_id => {
+                            if let Some(storage) = self.storage.as_mut() {
+                                if let Err(e) = storage.update_revocations(list.clone()) {
+                                    tracing::warn!("Ignoring revocation list for {}: {}", owner_id, e);
+                                }
+                            }
+                            let _ = self.event_tx.send(P2PEvent::RevocationList(list));
+                        }
+                        _ => tracing::warn!("Ignoring invalid revocation list for {}", owner_id),
+                    }
+                    return;
+                }
+
+                // Same for identity documents, which are also cached
+                if let Some(owner_id) = IdentityDocument::owner_from_dht_key(&key) {
+                    match IdentityDocument::from_bytes(&record.record.value) {
+                        Ok(document) if document.payload.owner_id == owner_id => {
+                            if self.cache_identity(document.clone()) {
+                                if let Some(storage) = self.storage.as_mut() {
+                                    if let Err(e) = storage.update_identity(document.clone()) {
+                                        tracing::warn!("Ignoring identity document for {}: {}", owner_id, e);
+                                    }
+                                }
+                                let _ = self.event_tx.send(P2PEvent::IdentityDocument(document));
+                            }
+                        }
+                        _ => tracing::warn!("Ignoring invalid identity document for {}", owner_id),
+                    }
+                    return;
+                }
+
                 let _ = self.event_tx.send(P2PEvent::DhtValue {
-                    key: record.record.key.to_vec(),
+                    key,
                     value: record.record.value,
                 });
             }
@@ -503,7 +654,10 @@ impl P2PNode {
                             request: request.clone(),
                         });
 
-                        // TODO: Handle request and send response via channel
+                        let response = self.answer_storage_request(&request).await;
+                        if self.swarm.behaviour_mut().storage.send_response(channel, response).is_err() {
+                            tracing::warn!("Could not answer storage request from {}", peer);
+                        }
                     }
                     request_response::Message::Response { response, .. } => {
                         let _ = self.event_tx.send(P2PEvent::StorageResponse {
@@ -548,4 +702,85 @@ mod tests {
         let node = node.unwrap();
         assert_eq!(node.connected_peers_count(), 0);
     }
+
+    #[tokio::test]
+    async fn test_devices_get_distinct_certified_peer_ids() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+
+        let laptop = P2PNode::new(&identity, P2PNodeConfig::default()).await.unwrap();
+        let phone_config = P2PNodeConfig {
+            device_index: 1,
+            ..P2PNodeConfig::default()
+        };
+        let phone = P2PNode::new(&identity, phone_config).await.unwrap();
+        assert_ne!(laptop.local_peer_id, phone.local_peer_id);
+
+        let certificate = identity
+            .certify_device(&identity.device_keys(1).unwrap(), "phone")
+            .unwrap();
+        assert_eq!(P2PNode::certified_peer_id(&certificate).unwrap(), phone.local_peer_id);
+    }
+
+    #[tokio::test]
+    async fn test_identity_cache_keeps_newest_document() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let mut node = P2PNode::new(&identity, P2PNodeConfig::default()).await.unwrap();
+        let owner_id = identity.public_id();
+
+        assert!(node.resolve_identity(&owner_id).is_none());
+
+        let first = identity
+            .identity_document(None, vec![], vec![node.local_peer_id.to_string()])
+            .unwrap();
+        let second = identity.identity_document(Some(&first), vec![], vec![]).unwrap();
+        assert!(node.cache_identity(second.clone()));
+        assert!(!node.cache_identity(first));
+
+        assert_eq!(node.resolve_identity(&owner_id), Some(second.clone()));
+        assert_eq!(node.cached_identity(&owner_id), Some(&second));
+    }
+
+    #[tokio::test]
+    async fn test_storage_requests_answered_by_storage_manager() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let mut node = P2PNode::new(&identity, P2PNodeConfig::default()).await.unwrap();
+
+        let info = StorageRequest::GetStorageInfo;
+        assert!(matches!(
+            node.answer_storage_request(&info).await,
+            StorageResponse::Error { code: ErrorCode::PermissionDenied, .. }
+        ));
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut storage = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+        storage.initialize().await.unwrap();
+        node.set_storage_manager(storage);
+        assert!(matches!(
+            node.answer_storage_request(&info).await,
+            StorageResponse::StorageInfo { offered_bytes: 1_000_000, .. }
+        ));
+
+        // A root-signed request is checked against the owner's cached document
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let delete = StorageRequest::Delete {
+            request: crate::crypto::Signed::sign(
+                crate::p2p::DeleteRequest {
+                    fragment_id: "frag-001".into(),
+                    owner_id: owner.public_id(),
+                },
+                owner.signing_keys(),
+            )
+            .unwrap(),
+            device_certificate: None,
+        };
+        assert!(matches!(
+            node.answer_storage_request(&delete).await,
+            StorageResponse::Error { code: ErrorCode::PermissionDenied, .. }
+        ));
+        node.cache_identity(owner.identity_document(None, vec![], vec![]).unwrap());
+        assert!(matches!(
+            node.answer_storage_request(&delete).await,
+            StorageResponse::Deleted { .. }
+        ));
+    }
 }
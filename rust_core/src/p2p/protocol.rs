@@ -1,63 +1,55 @@
 //! Storage Protocol - Request/Response messages for storage operations
 
 use serde::{Deserialize, Serialize};
-use crate::crypto::ContentHash;
+use super::P2PError;
+use crate::crypto::{CryptoError, Signed, SignedPayload};
+use crate::identity::{DeviceCertificate, EscrowRecord, HeartbeatMessage};
+use ed25519_dalek::VerifyingKey;
 
 /// Storage request types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum StorageRequest {
     /// Store a data fragment
     Store {
-        /// Unique fragment ID (hash of content)
-        fragment_id: String,
-
-        /// Owner's public ID
-        owner_id: String,
+        /// Fragment, owner and data hash, signed by the owner
+        request: Signed<StoreRequest>,
 
         /// Encrypted fragment data
         data: Vec<u8>,
 
-        /// Expiration timestamp (Unix)
-        expires_at: i64,
-
-        /// Storage contract signature
-        signature: Vec<u8>,
+        /// Certificate of the signing device (absent when signed by the root key)
+        #[serde(default)]
+        device_certificate: Option<DeviceCertificate>,
     },
 
     /// Retrieve a stored fragment
     Retrieve {
-        /// Fragment ID to retrieve
-        fragment_id: String,
+        /// Fragment and requester, signed as proof of ownership
+        request: Signed<RetrieveRequest>,
 
-        /// Requester's public ID
-        requester_id: String,
-
-        /// Proof of ownership
-        signature: Vec<u8>,
+        /// Certificate of the signing device (absent when signed by the root key)
+        #[serde(default)]
+        device_certificate: Option<DeviceCertificate>,
     },
 
     /// Delete a fragment (by owner)
     Delete {
-        /// Fragment ID to delete
-        fragment_id: String,
-
-        /// Owner's public ID
-        owner_id: String,
+        /// Fragment and owner, signed as deletion authorization
+        request: Signed<DeleteRequest>,
 
-        /// Deletion authorization signature
-        signature: Vec<u8>,
+        /// Certificate of the signing device (absent when signed by the root key)
+        #[serde(default)]
+        device_certificate: Option<DeviceCertificate>,
     },
 
     /// Heartbeat to renew storage contract
     Heartbeat {
-        /// Owner's public ID
-        owner_id: String,
-
-        /// Timestamp
-        timestamp: i64,
+        /// Owner's signed heartbeat
+        heartbeat: HeartbeatMessage,
 
-        /// Signature
-        signature: Vec<u8>,
+        /// Certificate of the signing device (absent when signed by the root key)
+        #[serde(default)]
+        device_certificate: Option<DeviceCertificate>,
     },
 
     /// Query storage availability
@@ -71,18 +63,151 @@ pub enum StorageRequest {
 
     /// Proof of Storage challenge
     StorageChallenge {
-        /// Fragment ID to prove
-        fragment_id: String,
-
-        /// Random challenge bytes
-        challenge: Vec<u8>,
+        /// Fragment, challenger and challenge bytes, signed by the challenger
+        request: Signed<ChallengeRequest>,
 
-        /// Challenger's signature
-        signature: Vec<u8>,
+        /// Certificate of the signing device (absent when signed by the root key)
+        #[serde(default)]
+        device_certificate: Option<DeviceCertificate>,
     },
 
     /// Request peer's storage info
     GetStorageInfo,
+
+    /// Hand an owner-signed inheritance escrow record to a host
+    DepositEscrow {
+        /// Escrow record (keys sealed to the heir)
+        record: Signed<EscrowRecord>,
+    },
+
+    /// Ask for an escrow record once its owner has gone quiet
+    ClaimEscrow {
+        /// Owner's public ID
+        owner_id: String,
+
+        /// Heir's public ID
+        heir_id: String,
+    },
+}
+
+/// Signed part of a `Store` request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreRequest {
+    /// Unique fragment ID (hash of content)
+    pub fragment_id: String,
+
+    /// Owner's public ID
+    pub owner_id: String,
+
+    /// Base58 content hash of the fragment data
+    pub content_hash: String,
+
+    /// Expiration timestamp (Unix)
+    pub expires_at: i64,
+}
+
+impl SignedPayload for StoreRequest {
+    const CONTEXT: &'static str = "store request";
+}
+
+/// Signed part of a `Retrieve` request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetrieveRequest {
+    /// Fragment ID to retrieve
+    pub fragment_id: String,
+
+    /// Requester's public ID
+    pub requester_id: String,
+}
+
+impl SignedPayload for RetrieveRequest {
+    const CONTEXT: &'static str = "retrieve request";
+}
+
+/// Signed part of a `Delete` request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeleteRequest {
+    /// Fragment ID to delete
+    pub fragment_id: String,
+
+    /// Owner's public ID
+    pub owner_id: String,
+}
+
+impl SignedPayload for DeleteRequest {
+    const CONTEXT: &'static str = "delete request";
+}
+
+/// Signed part of a `StorageChallenge` request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChallengeRequest {
+    /// Fragment ID to prove
+    pub fragment_id: String,
+
+    /// Challenger's public ID
+    pub challenger_id: String,
+
+    /// Random challenge bytes
+    pub challenge: Vec<u8>,
+}
+
+impl SignedPayload for ChallengeRequest {
+    const CONTEXT: &'static str = "storage challenge";
+}
+
+/// Signature of an owner request and what it covers
+#[derive(Debug, Clone)]
+pub struct OwnerSignature<'a> {
+    /// Public ID the request acts for
+    pub owner_id: &'a str,
+
+    /// Canonical signed bytes
+    pub message: Vec<u8>,
+
+    /// Signature over `message`
+    pub signature: &'a [u8],
+
+    /// Certificate of the signing device (None when signed by the root key)
+    pub device_certificate: Option<&'a DeviceCertificate>,
+}
+
+impl StorageRequest {
+    /// Signature of an owner request (`Store`, `Retrieve`, `Delete`,
+    /// `StorageChallenge` and `Heartbeat`)
+    /// `None` for any other request
+    pub fn owner_signature(&self) -> Result<Option<OwnerSignature<'_>>, CryptoError> {
+        let (owner_id, message, signature, device_certificate) = match self {
+            StorageRequest::Store {
+                request,
+                device_certificate,
+                ..
+            } => (&request.payload.owner_id, request.signing_bytes(), &request.signature, device_certificate),
+            StorageRequest::Retrieve {
+                request,
+                device_certificate,
+            } => (&request.payload.requester_id, request.signing_bytes(), &request.signature, device_certificate),
+            StorageRequest::Delete {
+                request,
+                device_certificate,
+            } => (&request.payload.owner_id, request.signing_bytes(), &request.signature, device_certificate),
+            StorageRequest::StorageChallenge {
+                request,
+                device_certificate,
+            } => (&request.payload.challenger_id, request.signing_bytes(), &request.signature, device_certificate),
+            StorageRequest::Heartbeat {
+                heartbeat,
+                device_certificate,
+            } => (&heartbeat.payload.node_id, heartbeat.signing_bytes(), &heartbeat.signature, device_certificate),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(OwnerSignature {
+            owner_id,
+            message: message?,
+            signature,
+            device_certificate: device_certificate.as_ref(),
+        }))
+    }
 }
 
 /// Storage response types
@@ -149,6 +274,17 @@ pub enum StorageResponse {
         uptime: f32,
     },
 
+    /// Escrow record held
+    EscrowDeposited {
+        owner_id: String,
+        heir_id: String,
+    },
+
+    /// Escrow record released to the heir
+    EscrowReleased {
+        record: Signed<EscrowRecord>,
+    },
+
     /// Error response
     Error {
         code: ErrorCode,
@@ -156,6 +292,28 @@ pub enum StorageResponse {
     },
 }
 
+impl StorageResponse {
+    /// Error response
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        StorageResponse::Error {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Error response for a refused or failed request
+    pub fn from_error(error: &P2PError) -> Self {
+        let code = match error {
+            P2PError::Unauthorized(_) | P2PError::UnknownIdentity(_) => ErrorCode::PermissionDenied,
+            P2PError::DeviceRevoked(_) => ErrorCode::DeviceRevoked,
+            P2PError::Replay(_) => ErrorCode::Replayed,
+            P2PError::EscrowLocked(_) => ErrorCode::EscrowLocked,
+            _ => ErrorCode::InternalError,
+        };
+        Self::error(code, error.to_string())
+    }
+}
+
 /// Error codes for storage operations
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum ErrorCode {
@@ -174,6 +332,15 @@ pub enum ErrorCode {
     /// Permission denied
     PermissionDenied,
 
+    /// Request signed by a revoked device
+    DeviceRevoked,
+
+    /// Replayed or future-dated heartbeat
+    Replayed,
+
+    /// Escrow owner has not been inactive long enough
+    EscrowLocked,
+
     /// Rate limited
     RateLimited,
 
@@ -185,7 +352,8 @@ pub enum ErrorCode {
 }
 
 /// Storage contract - agreement between data owner and storage peer
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Signed by the owner as `Signed<StorageContract>`, then accepted by the storage peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StorageContract {
     /// Fragment ID
     pub fragment_id: String,
@@ -204,12 +372,33 @@ pub struct StorageContract {
 
     /// Expiration timestamp (90 days from last heartbeat)
     pub expires_at: i64,
+}
 
-    /// Owner's signature
-    pub owner_signature: Vec<u8>,
+impl SignedPayload for StorageContract {
+    const CONTEXT: &'static str = "storage contract";
+}
+
+/// Storage peer's acceptance of an owner-signed contract
+/// Signed by the storage peer, so a `Signed<ContractAcceptance>` carries both signatures
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractAcceptance {
+    /// Contract as signed by the owner
+    pub contract: Signed<StorageContract>,
+}
 
-    /// Storage peer's signature
-    pub storage_signature: Vec<u8>,
+impl SignedPayload for ContractAcceptance {
+    const CONTEXT: &'static str = "storage contract acceptance";
+}
+
+impl Signed<ContractAcceptance> {
+    /// Check the storage peer's and the owner's signatures and return the contract
+    pub fn verify_contract(
+        &self,
+        owner_key: &VerifyingKey,
+        storage_key: &VerifyingKey,
+    ) -> Result<&StorageContract, CryptoError> {
+        self.verify(storage_key)?.contract.verify(owner_key)
+    }
 }
 
 impl StorageContract {
@@ -231,25 +420,9 @@ impl StorageContract {
             size_bytes,
             created_at: now,
             expires_at,
-            owner_signature: vec![],
-            storage_signature: vec![],
         }
     }
 
-    /// Get the data to sign
-    pub fn signing_data(&self) -> Vec<u8> {
-        format!(
-            "{}:{}:{}:{}:{}:{}",
-            self.fragment_id,
-            self.owner_id,
-            self.storage_peer_id,
-            self.size_bytes,
-            self.created_at,
-            self.expires_at
-        )
-        .into_bytes()
-    }
-
     /// Check if contract is expired
     pub fn is_expired(&self) -> bool {
         let now = chrono::Utc::now().timestamp();
@@ -310,6 +483,7 @@ pub struct FragmentMetadata {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::identity::UserIdentity;
 
     #[test]
     fn test_storage_contract() {
@@ -341,4 +515,60 @@ mod tests {
         contract.extend(90);
         assert!(!contract.is_expired());
     }
+
+    #[test]
+    fn test_contract_signed_by_both_parties() {
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let (storage, _) = UserIdentity::generate(None).unwrap();
+        let owner_key = owner.signing_keys().verifying_key;
+        let storage_key = storage.signing_keys().verifying_key;
+
+        let contract = StorageContract::new(
+            "frag:001".to_string(),
+            owner.public_id(),
+            storage.public_id(),
+            1024,
+            90,
+        );
+        let offer = Signed::sign(contract.clone(), owner.signing_keys()).unwrap();
+        let accepted = Signed::sign(ContractAcceptance { contract: offer.clone() }, storage.signing_keys()).unwrap();
+        assert_eq!(accepted.verify_contract(&owner_key, &storage_key).unwrap(), &contract);
+
+        // Each party must have signed its own part
+        assert!(accepted.verify_contract(&storage_key, &storage_key).is_err());
+        let self_accepted = Signed::sign(ContractAcceptance { contract: offer }, owner.signing_keys()).unwrap();
+        assert!(self_accepted.verify_contract(&owner_key, &storage_key).is_err());
+
+        // The terms cannot change after signing
+        let mut extended = accepted;
+        extended.payload.contract.payload.expires_at += 1;
+        assert!(extended.verify_contract(&owner_key, &storage_key).is_err());
+    }
+
+    #[test]
+    fn test_owner_signature_covers_request_type() {
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let retrieve = RetrieveRequest {
+            fragment_id: "frag-001".into(),
+            requester_id: owner.public_id(),
+        };
+        let delete = DeleteRequest {
+            fragment_id: "frag-001".into(),
+            owner_id: owner.public_id(),
+        };
+        let retrieve = StorageRequest::Retrieve {
+            request: Signed::sign_at(retrieve, owner.signing_keys(), 1).unwrap(),
+            device_certificate: None,
+        };
+        let delete = StorageRequest::Delete {
+            request: Signed::sign_at(delete, owner.signing_keys(), 1).unwrap(),
+            device_certificate: None,
+        };
+
+        // Same fields and time, but a retrieve signature never authorizes a delete
+        let retrieve = retrieve.owner_signature().unwrap().unwrap();
+        let delete = delete.owner_signature().unwrap().unwrap();
+        assert_eq!(retrieve.owner_id, delete.owner_id);
+        assert_ne!(retrieve.message, delete.message);
+    }
 }
@@ -1,13 +1,29 @@
 //! Storage protocol handler - manages fragment storage and retrieval
 
-use super::{P2PError, StorageRequest, StorageResponse, protocol::ErrorCode};
-use crate::crypto::{ContentHash, EncryptionKey};
-use crate::identity::UserIdentity;
+use super::{DeleteRequest, P2PError, StorageRequest, StorageResponse, StoreRequest, protocol::ErrorCode};
+use crate::crypto::signed::DEFAULT_MAX_FUTURE_SKEW;
+use crate::crypto::{ContentHash, EncryptionKey, Signed};
+use crate::identity::{EscrowRecord, Heartbeat, IdentityDocument, RevocationList, UserIdentity};
 
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 
+/// How long an owner's fragments are kept after an accepted heartbeat (days)
+const HEARTBEAT_EXTENSION_DAYS: u32 = 90;
+
+/// How long a signed store, retrieve or delete request may be replayed (seconds)
+const MAX_REQUEST_AGE: i64 = 10 * 60;
+
+/// Whether a fragment ID is safe to use as a file name
+fn is_valid_fragment_id(fragment_id: &str) -> bool {
+    fragment_id.len() >= 2
+        && fragment_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
 /// Manages local storage of fragments (both own and others')
 pub struct StorageManager {
     /// Base path for storage
@@ -24,6 +40,41 @@ pub struct StorageManager {
 
     /// User identity for signing
     identity: Option<UserIdentity>,
+
+    /// Newest known device revocation list per owner
+    revocations: HashMap<String, RevocationList>,
+
+    /// Newest known identity document per owner
+    identities: HashMap<String, IdentityDocument>,
+
+    /// Last heartbeat accepted per owner
+    heartbeat_marks: HashMap<String, HeartbeatMark>,
+
+    /// Inheritance escrow records held per owner
+    escrows: HashMap<String, Vec<HeldEscrow>>,
+
+    /// How far ahead of local time a heartbeat or request may be dated (seconds)
+    max_clock_skew: i64,
+}
+
+/// Last heartbeat accepted from an owner
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct HeartbeatMark {
+    /// Heartbeat sequence
+    sequence: u64,
+
+    /// Local time it was accepted
+    received_at: i64,
+}
+
+/// Escrow record held for an owner's heir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HeldEscrow {
+    /// Owner-signed record
+    record: Signed<EscrowRecord>,
+
+    /// Local time it was deposited
+    deposited_at: i64,
 }
 
 /// Information about a stored fragment
@@ -66,6 +117,11 @@ impl StorageManager {
             used_storage_bytes: 0,
             fragment_index: HashMap::new(),
             identity: None,
+            revocations: HashMap::new(),
+            identities: HashMap::new(),
+            heartbeat_marks: HashMap::new(),
+            escrows: HashMap::new(),
+            max_clock_skew: DEFAULT_MAX_FUTURE_SKEW,
         }
     }
 
@@ -74,6 +130,326 @@ impl StorageManager {
         self.identity = Some(identity);
     }
 
+    /// Set how far ahead of local time a heartbeat or request may be dated (seconds)
+    pub fn set_max_clock_skew(&mut self, seconds: i64) {
+        self.max_clock_skew = seconds;
+    }
+
+    /// Record an owner's revocation list (e.g. one fetched from the DHT)
+    /// Returns false if a list with the same or a higher sequence is already known
+    pub fn update_revocations(&mut self, list: RevocationList) -> Result<bool, P2PError> {
+        let revocations = list.verify_revocations().map_err(|e| P2PError::Protocol(e.to_string()))?;
+
+        if let Some(known) = self.revocations.get(&revocations.owner_id) {
+            if known.payload.sequence >= revocations.sequence {
+                return Ok(false);
+            }
+        }
+        self.revocations.insert(revocations.owner_id.clone(), list);
+        Ok(true)
+    }
+
+    /// Record an owner's identity document (e.g. one resolved from the DHT)
+    /// Returns false if a document with a higher sequence is already known
+    pub fn update_identity(&mut self, document: IdentityDocument) -> Result<bool, P2PError> {
+        let info = document.verify_document().map_err(|e| P2PError::Protocol(e.to_string()))?;
+
+        if let Some(known) = self.identities.get(&info.owner_id) {
+            if known.payload.sequence > info.sequence {
+                return Ok(false);
+            }
+        }
+        self.identities.insert(info.owner_id.clone(), document);
+        Ok(true)
+    }
+
+    /// Verified root key of an owner
+    /// Fails with `P2PError::UnknownIdentity` until its document was recorded
+    pub fn owner_key(&self, owner_id: &str) -> Result<VerifyingKey, P2PError> {
+        let document = self
+            .identities
+            .get(owner_id)
+            .ok_or_else(|| P2PError::UnknownIdentity(owner_id.to_string()))?;
+        document.owner_key().map_err(|e| P2PError::Protocol(e.to_string()))
+    }
+
+    /// Check a signed owner request before honoring it
+    /// A device certificate must be valid for the owner, the device must not
+    /// be revoked, and the device must have signed the request. Requests
+    /// signed by the root key are checked against the owner's document and
+    /// fail with `P2PError::UnknownIdentity` until it is known: resolve it,
+    /// record it with `update_identity` and retry. Requests that carry no
+    /// owner signature are refused, and store, retrieve and delete requests
+    /// must have been signed within the last `MAX_REQUEST_AGE` seconds, so a
+    /// captured one cannot be replayed later (say, to delete a fragment
+    /// uploaded again since).
+    ///
+    /// Revocation only cuts off a device key. Every device holds the master
+    /// seed, so a lost device can still sign as the root key or certify new
+    /// device keys; only moving to a new identity locks it out.
+    pub fn authorize_request(&self, request: &StorageRequest) -> Result<(), P2PError> {
+        let signed = request
+            .owner_signature()
+            .map_err(|e| P2PError::Unauthorized(e.to_string()))?
+            .ok_or_else(|| P2PError::Unauthorized("Request carries no owner signature".into()))?;
+        let owner_id = signed.owner_id;
+
+        let recent = match request {
+            StorageRequest::Store { request, .. } => {
+                request.is_recent_with_skew(MAX_REQUEST_AGE, self.max_clock_skew)
+            }
+            StorageRequest::Retrieve { request, .. } => {
+                request.is_recent_with_skew(MAX_REQUEST_AGE, self.max_clock_skew)
+            }
+            StorageRequest::Delete { request, .. } => {
+                request.is_recent_with_skew(MAX_REQUEST_AGE, self.max_clock_skew)
+            }
+            _ => true,
+        };
+        if !recent {
+            return Err(P2PError::Replay(format!("Stale or future-dated request from {}", owner_id)));
+        }
+        let Some(certificate) = signed.device_certificate else {
+            let owner_key = self.owner_key(owner_id)?;
+            let signature = Signature::from_slice(signed.signature)
+                .map_err(|_| P2PError::Unauthorized("Invalid signature".into()))?;
+            return owner_key
+                .verify(&signed.message, &signature)
+                .map_err(|_| P2PError::Unauthorized("Signature verification failed".into()));
+        };
+
+        certificate
+            .verify_for(owner_id)
+            .map_err(|e| P2PError::Unauthorized(e.to_string()))?;
+
+        if let Some(list) = self.revocations.get(owner_id) {
+            if list.revokes(certificate) {
+                return Err(P2PError::DeviceRevoked(certificate.payload.device_name.clone()));
+            }
+        }
+
+        certificate
+            .verify_device_signature(&signed.message, signed.signature)
+            .map_err(|e| P2PError::Unauthorized(e.to_string()))
+    }
+
+    /// Check and record an owner heartbeat
+    /// Besides `authorize_request`, the heartbeat must be addressed to this
+    /// host, not be dated beyond the allowed clock skew, and carry a higher
+    /// sequence than any heartbeat accepted from the owner before. The mark
+    /// only moves once every check passed, so a forged heartbeat (say with
+    /// sequence `u64::MAX`) cannot lock the owner out
+    pub async fn accept_heartbeat(&mut self, request: &StorageRequest) -> Result<(), P2PError> {
+        let StorageRequest::Heartbeat { heartbeat, .. } = request else {
+            return Err(P2PError::Protocol("Not a heartbeat".into()));
+        };
+
+        // Signed by the owner's root key or a certified, unrevoked device
+        self.authorize_request(request)?;
+
+        let host_id = self
+            .identity
+            .as_ref()
+            .map(|identity| identity.public_id())
+            .ok_or_else(|| P2PError::Protocol("Host identity not set".into()))?;
+        let Heartbeat {
+            node_id,
+            host_id: target,
+            sequence,
+        } = &heartbeat.payload;
+
+        if *target != host_id {
+            return Err(P2PError::Unauthorized(format!("Heartbeat addressed to {}", target)));
+        }
+        if heartbeat.is_future_dated(self.max_clock_skew) {
+            return Err(P2PError::Replay(format!("Heartbeat from {} is dated in the future", node_id)));
+        }
+        if let Some(mark) = self.heartbeat_marks.get(node_id) {
+            if *sequence <= mark.sequence {
+                return Err(P2PError::Replay(format!(
+                    "Heartbeat {} from {} is not above {}",
+                    sequence, node_id, mark.sequence
+                )));
+            }
+        }
+
+        let mark = HeartbeatMark {
+            sequence: *sequence,
+            received_at: chrono::Utc::now().timestamp(),
+        };
+        self.heartbeat_marks.insert(node_id.clone(), mark);
+        self.save_heartbeat_marks().await
+    }
+
+    /// Hold an owner-signed inheritance escrow record
+    /// Replaces the record held for the same heir unless that one is newer
+    pub async fn deposit_escrow(&mut self, record: Signed<EscrowRecord>) -> Result<(), P2PError> {
+        record
+            .verify_escrow()
+            .map_err(|e| P2PError::Unauthorized(e.to_string()))?;
+
+        let held = self.escrows.entry(record.payload.owner_id.clone()).or_default();
+        if let Some(existing) = held.iter().find(|h| h.record.payload.heir_id == record.payload.heir_id) {
+            if existing.record.signed_at > record.signed_at {
+                return Err(P2PError::Replay(format!(
+                    "A newer escrow for {} is already held",
+                    record.payload.heir_id
+                )));
+            }
+        }
+        held.retain(|h| h.record.payload.heir_id != record.payload.heir_id);
+        held.push(HeldEscrow {
+            record,
+            deposited_at: chrono::Utc::now().timestamp(),
+        });
+
+        self.save_escrows().await
+    }
+
+    /// Release an escrow record to its heir
+    /// Fails with `P2PError::EscrowLocked` until the owner has sent no
+    /// heartbeat (and deposited nothing) for the record's inactivity period.
+    /// Only heartbeats that passed `accept_heartbeat` count, so nobody but
+    /// the owner can hold the record back or hurry it along
+    pub fn release_escrow(&self, owner_id: &str, heir_id: &str) -> Result<Signed<EscrowRecord>, P2PError> {
+        let held = self
+            .escrows
+            .get(owner_id)
+            .and_then(|held| held.iter().find(|h| h.record.payload.heir_id == heir_id))
+            .ok_or_else(|| P2PError::Protocol(format!("No escrow from {} for {}", owner_id, heir_id)))?;
+
+        let last_heartbeat = self.heartbeat_marks.get(owner_id).map_or(0, |mark| mark.received_at);
+        let last_activity = held.deposited_at.max(last_heartbeat);
+        let release_at = last_activity + held.record.payload.inactivity_seconds();
+
+        let now = chrono::Utc::now().timestamp();
+        if now < release_at {
+            return Err(P2PError::EscrowLocked(format!(
+                "{} was active {} days ago",
+                owner_id,
+                (now - last_activity) / (24 * 60 * 60)
+            )));
+        }
+        Ok(held.record.clone())
+    }
+
+    /// Answer a request from a peer
+    /// Owner requests go through `authorize_request` (or `accept_heartbeat`)
+    /// first, and only the owner of a fragment may replace or delete it. Refused requests
+    /// fail with the refusal (on `P2PError::UnknownIdentity`, resolve the
+    /// owner's document, record it and retry); answer them with
+    /// `StorageResponse::from_error`
+    pub async fn handle_request(&mut self, request: &StorageRequest) -> Result<StorageResponse, P2PError> {
+        match request {
+            StorageRequest::Store { request: signed, data, .. } => {
+                self.authorize_request(request)?;
+                let StoreRequest {
+                    fragment_id,
+                    owner_id,
+                    content_hash,
+                    expires_at,
+                } = &signed.payload;
+                if !is_valid_fragment_id(fragment_id) {
+                    return Ok(StorageResponse::error(ErrorCode::InvalidRequest, "Invalid fragment ID"));
+                }
+                if ContentHash::hash(data).to_base58() != *content_hash {
+                    return Ok(StorageResponse::error(ErrorCode::InvalidRequest, "Content hash mismatch"));
+                }
+                let replaced = self.fragment_index.get(fragment_id).map_or(0, |f| f.size_bytes);
+                if !self.has_space((data.len() as u64).saturating_sub(replaced)) {
+                    return Ok(StorageResponse::error(ErrorCode::InsufficientSpace, "Insufficient storage space"));
+                }
+                self.store_fragment(fragment_id, owner_id, data, *expires_at).await?;
+                Ok(StorageResponse::Stored {
+                    fragment_id: fragment_id.clone(),
+                    receipt: self.sign_receipt(fragment_id),
+                })
+            }
+
+            StorageRequest::Retrieve { request: signed, .. } => {
+                self.authorize_request(request)?;
+                let fragment_id = &signed.payload.fragment_id;
+                if !self.fragment_index.contains_key(fragment_id) {
+                    return Ok(StorageResponse::error(ErrorCode::NotFound, "Fragment not found"));
+                }
+                let data = self.retrieve_fragment(fragment_id).await?;
+                Ok(StorageResponse::Data {
+                    fragment_id: fragment_id.clone(),
+                    hash: ContentHash::hash(&data).to_base58(),
+                    data,
+                })
+            }
+
+            StorageRequest::Delete { request: signed, .. } => {
+                self.authorize_request(request)?;
+                let DeleteRequest { fragment_id, owner_id } = &signed.payload;
+                if let Some(fragment) = self.fragment_index.get(fragment_id) {
+                    if fragment.owner_id != *owner_id {
+                        return Err(P2PError::Unauthorized(format!("{} does not own {}", owner_id, fragment_id)));
+                    }
+                }
+                self.delete_fragment(fragment_id).await?;
+                Ok(StorageResponse::Deleted {
+                    fragment_id: fragment_id.clone(),
+                    confirmation: self.sign_receipt(fragment_id),
+                })
+            }
+
+            StorageRequest::Heartbeat { heartbeat, .. } => {
+                self.accept_heartbeat(request).await?;
+                self.extend_owner_fragments(&heartbeat.payload.node_id, HEARTBEAT_EXTENSION_DAYS)
+                    .await?;
+                Ok(StorageResponse::HeartbeatAck {
+                    new_expiration: chrono::Utc::now().timestamp() + HEARTBEAT_EXTENSION_DAYS as i64 * 24 * 60 * 60,
+                })
+            }
+
+            StorageRequest::QueryAvailability { .. } => Ok(StorageResponse::Availability {
+                available_bytes: self.available_space(),
+                offered_bytes: self.max_storage_bytes,
+                reliability: 1.0,
+            }),
+
+            StorageRequest::StorageChallenge { request: signed, .. } => {
+                self.authorize_request(request)?;
+                let fragment_id = &signed.payload.fragment_id;
+                if !self.fragment_index.contains_key(fragment_id) {
+                    return Ok(StorageResponse::error(ErrorCode::NotFound, "Fragment not found"));
+                }
+                Ok(StorageResponse::StorageProof {
+                    fragment_id: fragment_id.clone(),
+                    proof: self.prove_storage(fragment_id, &signed.payload.challenge).await?,
+                })
+            }
+
+            StorageRequest::GetStorageInfo => Ok(StorageResponse::StorageInfo {
+                offered_bytes: self.max_storage_bytes,
+                used_bytes: self.used_storage_bytes,
+                fragment_count: self.fragment_index.len() as u64,
+                uptime: 100.0,
+            }),
+
+            StorageRequest::DepositEscrow { record } => {
+                self.deposit_escrow(record.clone()).await?;
+                Ok(StorageResponse::EscrowDeposited {
+                    owner_id: record.payload.owner_id.clone(),
+                    heir_id: record.payload.heir_id.clone(),
+                })
+            }
+
+            StorageRequest::ClaimEscrow { owner_id, heir_id } => Ok(StorageResponse::EscrowReleased {
+                record: self.release_escrow(owner_id, heir_id)?,
+            }),
+        }
+    }
+
+    /// Sign a fragment ID as this host (empty without an identity)
+    fn sign_receipt(&self, fragment_id: &str) -> Vec<u8> {
+        self.identity
+            .as_ref()
+            .map_or_else(Vec::new, |identity| identity.sign(fragment_id.as_bytes()))
+    }
+
     /// Initialize storage (create directories, load index)
     pub async fn initialize(&mut self) -> Result<(), P2PError> {
         // Create storage directories
@@ -97,6 +473,28 @@ impl StorageManager {
             self.used_storage_bytes = self.fragment_index.values().map(|f| f.size_bytes).sum();
         }
 
+        // Load heartbeat high-water marks, so restarts don't reopen old heartbeats
+        let marks_path = self.storage_path.join("heartbeats.json");
+        if marks_path.exists() {
+            let marks_data = tokio::fs::read_to_string(&marks_path)
+                .await
+                .map_err(|e| P2PError::Protocol(format!("Failed to read heartbeat marks: {}", e)))?;
+
+            self.heartbeat_marks = serde_json::from_str(&marks_data)
+                .map_err(|e| P2PError::Protocol(format!("Failed to parse heartbeat marks: {}", e)))?;
+        }
+
+        // Load held escrow records
+        let escrows_path = self.storage_path.join("escrows.json");
+        if escrows_path.exists() {
+            let escrows_data = tokio::fs::read_to_string(&escrows_path)
+                .await
+                .map_err(|e| P2PError::Protocol(format!("Failed to read escrows: {}", e)))?;
+
+            self.escrows = serde_json::from_str(&escrows_data)
+                .map_err(|e| P2PError::Protocol(format!("Failed to parse escrows: {}", e)))?;
+        }
+
         Ok(())
     }
 
@@ -113,7 +511,35 @@ impl StorageManager {
         Ok(())
     }
 
+    /// Save the heartbeat high-water marks to disk
+    async fn save_heartbeat_marks(&self) -> Result<(), P2PError> {
+        let marks_path = self.storage_path.join("heartbeats.json");
+        let marks_data = serde_json::to_string_pretty(&self.heartbeat_marks)
+            .map_err(|e| P2PError::Protocol(format!("Failed to serialize heartbeat marks: {}", e)))?;
+
+        tokio::fs::write(&marks_path, marks_data)
+            .await
+            .map_err(|e| P2PError::Protocol(format!("Failed to write heartbeat marks: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Save the held escrow records to disk
+    async fn save_escrows(&self) -> Result<(), P2PError> {
+        let escrows_path = self.storage_path.join("escrows.json");
+        let escrows_data = serde_json::to_string_pretty(&self.escrows)
+            .map_err(|e| P2PError::Protocol(format!("Failed to serialize escrows: {}", e)))?;
+
+        tokio::fs::write(&escrows_path, escrows_data)
+            .await
+            .map_err(|e| P2PError::Protocol(format!("Failed to write escrows: {}", e)))?;
+
+        Ok(())
+    }
+
     /// Store a fragment
+    /// Replaces a fragment of the same owner under the same ID; a fragment ID
+    /// held for another owner is refused with `P2PError::Unauthorized`
     pub async fn store_fragment(
         &mut self,
         fragment_id: &str,
@@ -123,8 +549,16 @@ impl StorageManager {
     ) -> Result<StoredFragment, P2PError> {
         let size = data.len() as u64;
 
+        let replaced = match self.fragment_index.get(fragment_id) {
+            Some(existing) if existing.owner_id != owner_id => {
+                return Err(P2PError::Unauthorized(format!("{} is held for another owner", fragment_id)));
+            }
+            Some(existing) => existing.size_bytes,
+            None => 0,
+        };
+
         // Check storage capacity
-        if self.used_storage_bytes + size > self.max_storage_bytes {
+        if self.used_storage_bytes.saturating_sub(replaced) + size > self.max_storage_bytes {
             return Err(P2PError::Protocol("Insufficient storage space".into()));
         }
 
@@ -163,7 +597,7 @@ impl StorageManager {
         // Update index
         self.fragment_index
             .insert(fragment_id.to_string(), fragment.clone());
-        self.used_storage_bytes += size;
+        self.used_storage_bytes = self.used_storage_bytes.saturating_sub(replaced) + size;
 
         // Save index
         self.save_index().await?;
@@ -368,8 +802,25 @@ pub struct StorageStats {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::Signed;
+    use crate::p2p::{DeleteRequest, RetrieveRequest};
     use tempfile::TempDir;
 
+    /// Store request for `owner_id` signed by `signer`'s root key
+    fn store_request(fragment_id: &str, owner_id: &str, data: &[u8], signer: &UserIdentity) -> StorageRequest {
+        let request = StoreRequest {
+            fragment_id: fragment_id.into(),
+            owner_id: owner_id.into(),
+            content_hash: ContentHash::hash(data).to_base58(),
+            expires_at: chrono::Utc::now().timestamp() + 86400,
+        };
+        StorageRequest::Store {
+            request: Signed::sign(request, signer.signing_keys()).unwrap(),
+            data: data.to_vec(),
+            device_certificate: None,
+        }
+    }
+
     #[tokio::test]
     async fn test_store_retrieve_fragment() {
         let temp_dir = TempDir::new().unwrap();
@@ -424,4 +875,474 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_revoked_device_requests_refused() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let laptop = owner.device_keys(1).unwrap();
+        let certificate = owner.certify_device(&laptop, "laptop").unwrap();
+
+        let heartbeat = Heartbeat {
+            node_id: owner.public_id(),
+            host_id: "host".into(),
+            sequence: 1,
+        };
+        let heartbeat = StorageRequest::Heartbeat {
+            heartbeat: Signed::sign(heartbeat, laptop.signing_keys()).unwrap(),
+            device_certificate: Some(certificate.clone()),
+        };
+        manager.authorize_request(&heartbeat).unwrap();
+
+        // Signed by a device of someone else
+        let (other, _) = UserIdentity::generate(None).unwrap();
+        let delete = DeleteRequest {
+            fragment_id: "frag-001".into(),
+            owner_id: owner.public_id(),
+        };
+        let stranger = StorageRequest::Delete {
+            request: Signed::sign(delete, other.signing_keys()).unwrap(),
+            device_certificate: Some(other.certify_device(&other.device_keys(0).unwrap(), "x").unwrap()),
+        };
+        assert!(matches!(manager.authorize_request(&stranger), Err(P2PError::Unauthorized(_))));
+
+        // After the revocation list arrives the laptop is cut off
+        let first = owner.revoke_device(None, 1).unwrap();
+        assert!(manager.update_revocations(first.clone()).unwrap());
+        assert!(matches!(manager.authorize_request(&heartbeat), Err(P2PError::DeviceRevoked(_))));
+
+        // An older list cannot replace a newer one
+        let second = owner.revoke_device(Some(&first), 2).unwrap();
+        assert!(manager.update_revocations(second).unwrap());
+        assert!(!manager.update_revocations(first).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_root_signed_requests_checked_against_document() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let owner_id = owner.public_id();
+        assert!(matches!(manager.owner_key(&owner_id), Err(P2PError::UnknownIdentity(_))));
+
+        let first = owner.identity_document(None, vec![], vec![]).unwrap();
+        let second = owner.identity_document(Some(&first), vec![], vec![]).unwrap();
+        assert!(manager.update_identity(second).unwrap());
+        assert!(!manager.update_identity(first).unwrap());
+        assert_eq!(manager.owner_key(&owner_id).unwrap(), owner.signing_keys().verifying_key);
+
+        let delete = |signer: &UserIdentity| {
+            let request = DeleteRequest {
+                fragment_id: "frag-001".into(),
+                owner_id: owner_id.clone(),
+            };
+            StorageRequest::Delete {
+                request: Signed::sign(request, signer.signing_keys()).unwrap(),
+                device_certificate: None,
+            }
+        };
+        manager.authorize_request(&delete(&owner)).unwrap();
+
+        let (other, _) = UserIdentity::generate(None).unwrap();
+        assert!(matches!(
+            manager.authorize_request(&delete(&other)),
+            Err(P2PError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_or_unknown_owner_requests_refused() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let delete = StorageRequest::Delete {
+            request: Signed::sign(
+                DeleteRequest {
+                    fragment_id: "frag-001".into(),
+                    owner_id: owner.public_id(),
+                },
+                owner.signing_keys(),
+            )
+            .unwrap(),
+            device_certificate: None,
+        };
+
+        // Nothing to check a root signature against until the document is known
+        assert!(matches!(manager.authorize_request(&delete), Err(P2PError::UnknownIdentity(_))));
+        manager
+            .update_identity(owner.identity_document(None, vec![], vec![]).unwrap())
+            .unwrap();
+        manager.authorize_request(&delete).unwrap();
+
+        // Stripped signature
+        let mut unsigned = delete;
+        if let StorageRequest::Delete { request, .. } = &mut unsigned {
+            request.signature.clear();
+        }
+        assert!(matches!(manager.authorize_request(&unsigned), Err(P2PError::Unauthorized(_))));
+
+        // Requests without an owner signature never pass as owner requests
+        assert!(matches!(
+            manager.authorize_request(&StorageRequest::GetStorageInfo),
+            Err(P2PError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_replays_refused() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+        manager.initialize().await.unwrap();
+        let (host, host_phrase) = UserIdentity::generate(None).unwrap();
+        let host_id = host.public_id();
+        manager.set_identity(host);
+
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        manager
+            .update_identity(owner.identity_document(None, vec![], vec![]).unwrap())
+            .unwrap();
+        let heartbeat = |host_id: &str, sequence, signed_at| {
+            let heartbeat = Heartbeat {
+                node_id: owner.public_id(),
+                host_id: host_id.to_string(),
+                sequence,
+            };
+            StorageRequest::Heartbeat {
+                heartbeat: Signed::sign_at(heartbeat, owner.signing_keys(), signed_at).unwrap(),
+                device_certificate: None,
+            }
+        };
+        let now = chrono::Utc::now().timestamp();
+
+        let first = heartbeat(&host_id, 1, now);
+        manager.accept_heartbeat(&first).await.unwrap();
+        assert!(matches!(manager.accept_heartbeat(&first).await, Err(P2PError::Replay(_))));
+
+        // Meant for another host
+        let elsewhere = heartbeat("another-host", 2, now);
+        assert!(matches!(manager.accept_heartbeat(&elsewhere).await, Err(P2PError::Unauthorized(_))));
+
+        // Dated beyond the allowed skew
+        manager.set_max_clock_skew(60);
+        let future = heartbeat(&host_id, 2, now + 3600);
+        assert!(matches!(manager.accept_heartbeat(&future).await, Err(P2PError::Replay(_))));
+        manager.accept_heartbeat(&heartbeat(&host_id, 2, now + 30)).await.unwrap();
+
+        // The high-water mark survives a restart
+        let mut restarted = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+        restarted.initialize().await.unwrap();
+        restarted.set_identity(UserIdentity::from_seed_phrase(&host_phrase, None).unwrap());
+        restarted
+            .update_identity(owner.identity_document(None, vec![], vec![]).unwrap())
+            .unwrap();
+        assert!(matches!(restarted.accept_heartbeat(&first).await, Err(P2PError::Replay(_))));
+    }
+
+    #[tokio::test]
+    async fn test_forged_heartbeat_leaves_mark_unchanged() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+        manager.initialize().await.unwrap();
+        let (host, _) = UserIdentity::generate(None).unwrap();
+        let host_id = host.public_id();
+        manager.set_identity(host);
+
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let owner_id = owner.public_id();
+        manager
+            .update_identity(owner.identity_document(None, vec![], vec![]).unwrap())
+            .unwrap();
+        let genuine = |sequence| StorageRequest::Heartbeat {
+            heartbeat: owner.generate_heartbeat(&host_id, sequence).unwrap(),
+            device_certificate: None,
+        };
+        manager.accept_heartbeat(&genuine(1)).await.unwrap();
+        let marks_before = tokio::fs::read(temp_dir.path().join("heartbeats.json")).await.unwrap();
+
+        // Someone else signs the owner's heartbeat, with or without a device certificate
+        let (attacker, _) = UserIdentity::generate(None).unwrap();
+        let poison = Heartbeat {
+            node_id: owner_id.clone(),
+            host_id: host_id.clone(),
+            sequence: u64::MAX,
+        };
+        let root_forged = StorageRequest::Heartbeat {
+            heartbeat: Signed::sign(poison.clone(), attacker.signing_keys()).unwrap(),
+            device_certificate: None,
+        };
+        let attacker_device = attacker.device_keys(0).unwrap();
+        let device_forged = StorageRequest::Heartbeat {
+            heartbeat: Signed::sign(poison, attacker_device.signing_keys()).unwrap(),
+            device_certificate: Some(attacker.certify_device(&attacker_device, "x").unwrap()),
+        };
+        for forged in [root_forged, device_forged] {
+            assert!(matches!(manager.accept_heartbeat(&forged).await, Err(P2PError::Unauthorized(_))));
+        }
+
+        assert_eq!(manager.heartbeat_marks[&owner_id].sequence, 1);
+        assert_eq!(
+            tokio::fs::read(temp_dir.path().join("heartbeats.json")).await.unwrap(),
+            marks_before
+        );
+        manager.accept_heartbeat(&genuine(2)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_escrow_released_after_owner_goes_quiet() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+        manager.initialize().await.unwrap();
+        let (host, _) = UserIdentity::generate(None).unwrap();
+        let host_id = host.public_id();
+        manager.set_identity(host);
+
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let (heir, _) = UserIdentity::generate(None).unwrap();
+        let owner_id = owner.public_id();
+        let heir_id = heir.public_id();
+        let heir_document = heir.identity_document(None, vec![], vec![]).unwrap();
+        manager
+            .update_identity(owner.identity_document(None, vec![], vec![]).unwrap())
+            .unwrap();
+
+        let record = owner.create_escrow(&heir_document, 30, b"file keys").unwrap();
+        manager.deposit_escrow(record).await.unwrap();
+        assert!(matches!(manager.release_escrow(&owner_id, &heir_id), Err(P2PError::EscrowLocked(_))));
+
+        // A month of silence releases the record
+        let month = 31 * 24 * 60 * 60;
+        manager.escrows.get_mut(&owner_id).unwrap()[0].deposited_at -= month;
+        let released = manager.release_escrow(&owner_id, &heir_id).unwrap();
+        assert_eq!(heir.open_escrow(&released).unwrap(), b"file keys");
+
+        // A heartbeat from the owner locks it again
+        let heartbeat = StorageRequest::Heartbeat {
+            heartbeat: owner.generate_heartbeat(&host_id, 1).unwrap(),
+            device_certificate: None,
+        };
+        manager.accept_heartbeat(&heartbeat).await.unwrap();
+        assert!(matches!(manager.release_escrow(&owner_id, &heir_id), Err(P2PError::EscrowLocked(_))));
+
+        // Records survive a restart; tampered ones are refused
+        let mut restarted = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+        restarted.initialize().await.unwrap();
+        assert!(matches!(restarted.release_escrow(&owner_id, &heir_id), Err(P2PError::EscrowLocked(_))));
+
+        let mut hurried = owner.create_escrow(&heir_document, 30, b"file keys").unwrap();
+        hurried.payload.inactivity_days = 0;
+        assert!(matches!(restarted.deposit_escrow(hurried).await, Err(P2PError::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn test_forged_heartbeat_does_not_move_escrow_release() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+        manager.initialize().await.unwrap();
+        let (host, _) = UserIdentity::generate(None).unwrap();
+        let host_id = host.public_id();
+        manager.set_identity(host);
+
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let (heir, _) = UserIdentity::generate(None).unwrap();
+        let (attacker, _) = UserIdentity::generate(None).unwrap();
+        let owner_id = owner.public_id();
+        let heir_id = heir.public_id();
+        manager
+            .update_identity(owner.identity_document(None, vec![], vec![]).unwrap())
+            .unwrap();
+
+        let heir_document = heir.identity_document(None, vec![], vec![]).unwrap();
+        let record = owner.create_escrow(&heir_document, 30, b"file keys").unwrap();
+        manager.deposit_escrow(record).await.unwrap();
+        manager.escrows.get_mut(&owner_id).unwrap()[0].deposited_at -= 31 * 24 * 60 * 60;
+
+        let forged = StorageRequest::Heartbeat {
+            heartbeat: Signed::sign(
+                Heartbeat {
+                    node_id: owner_id.clone(),
+                    host_id: host_id.clone(),
+                    sequence: u64::MAX,
+                },
+                attacker.signing_keys(),
+            )
+            .unwrap(),
+            device_certificate: None,
+        };
+
+        // A forged heartbeat does not keep a quiet owner's escrow locked...
+        assert!(manager.accept_heartbeat(&forged).await.is_err());
+        manager.release_escrow(&owner_id, &heir_id).unwrap();
+
+        // ...nor stop the owner's own heartbeats from locking it again
+        let genuine = StorageRequest::Heartbeat {
+            heartbeat: owner.generate_heartbeat(&host_id, 1).unwrap(),
+            device_certificate: None,
+        };
+        manager.accept_heartbeat(&genuine).await.unwrap();
+        assert!(matches!(manager.release_escrow(&owner_id, &heir_id), Err(P2PError::EscrowLocked(_))));
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_authorizes_owner_requests() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+        manager.initialize().await.unwrap();
+
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let owner_id = owner.public_id();
+        let store = |fragment_id: &str| store_request(fragment_id, &owner_id, b"Test fragment data", &owner);
+
+        // Root-signed requests wait for the owner's document
+        assert!(matches!(
+            manager.handle_request(&store("frag-001")).await,
+            Err(P2PError::UnknownIdentity(_))
+        ));
+        manager
+            .update_identity(owner.identity_document(None, vec![], vec![]).unwrap())
+            .unwrap();
+        let response = manager.handle_request(&store("frag-001")).await.unwrap();
+        assert!(matches!(response, StorageResponse::Stored { .. }));
+        let response = manager.handle_request(&store("../escape")).await.unwrap();
+        assert!(matches!(response, StorageResponse::Error { code: ErrorCode::InvalidRequest, .. }));
+
+        let delete = |owner_id: &str, signer: &UserIdentity| StorageRequest::Delete {
+            request: Signed::sign(
+                DeleteRequest {
+                    fragment_id: "frag-001".into(),
+                    owner_id: owner_id.to_string(),
+                },
+                signer.signing_keys(),
+            )
+            .unwrap(),
+            device_certificate: None,
+        };
+
+        // Only the owner may delete the fragment
+        let (other, _) = UserIdentity::generate(None).unwrap();
+        manager
+            .update_identity(other.identity_document(None, vec![], vec![]).unwrap())
+            .unwrap();
+        let error = manager
+            .handle_request(&delete(&other.public_id(), &other))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            StorageResponse::from_error(&error),
+            StorageResponse::Error { code: ErrorCode::PermissionDenied, .. }
+        ));
+
+        // A revoked device is refused
+        let laptop = owner.device_keys(1).unwrap();
+        let retrieve = StorageRequest::Retrieve {
+            request: Signed::sign(
+                RetrieveRequest {
+                    fragment_id: "frag-001".into(),
+                    requester_id: owner_id.clone(),
+                },
+                laptop.signing_keys(),
+            )
+            .unwrap(),
+            device_certificate: Some(owner.certify_device(&laptop, "laptop").unwrap()),
+        };
+        let response = manager.handle_request(&retrieve).await.unwrap();
+        assert!(matches!(response, StorageResponse::Data { data, .. } if data == b"Test fragment data"));
+        manager.update_revocations(owner.revoke_device(None, 1).unwrap()).unwrap();
+        let error = manager.handle_request(&retrieve).await.unwrap_err();
+        assert!(matches!(
+            StorageResponse::from_error(&error),
+            StorageResponse::Error { code: ErrorCode::DeviceRevoked, .. }
+        ));
+
+        let response = manager.handle_request(&delete(&owner_id, &owner)).await.unwrap();
+        assert!(matches!(response, StorageResponse::Deleted { .. }));
+        assert!(manager.retrieve_fragment("frag-001").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_store_cannot_take_over_fragments() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+        manager.initialize().await.unwrap();
+
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let (attacker, _) = UserIdentity::generate(None).unwrap();
+        let owner_id = owner.public_id();
+        for identity in [&owner, &attacker] {
+            manager
+                .update_identity(identity.identity_document(None, vec![], vec![]).unwrap())
+                .unwrap();
+        }
+        manager
+            .handle_request(&store_request("frag-001", &owner_id, b"owner data", &owner))
+            .await
+            .unwrap();
+
+        // Neither a store forged in the owner's name nor one in the attacker's own name replaces it
+        let forged = store_request("frag-001", &owner_id, b"attacker data", &attacker);
+        assert!(matches!(manager.handle_request(&forged).await, Err(P2PError::Unauthorized(_))));
+        let takeover = store_request("frag-001", &attacker.public_id(), b"attacker data", &attacker);
+        assert!(matches!(manager.handle_request(&takeover).await, Err(P2PError::Unauthorized(_))));
+        assert_eq!(manager.retrieve_fragment("frag-001").await.unwrap(), b"owner data");
+
+        // Data must match the signed hash
+        let mut swapped = store_request("frag-002", &owner_id, b"owner data", &owner);
+        if let StorageRequest::Store { data, .. } = &mut swapped {
+            *data = b"other data".to_vec();
+        }
+        let response = manager.handle_request(&swapped).await.unwrap();
+        assert!(matches!(response, StorageResponse::Error { code: ErrorCode::InvalidRequest, .. }));
+
+        // The owner may replace it, and the old copy stops counting
+        manager
+            .handle_request(&store_request("frag-001", &owner_id, b"new owner data", &owner))
+            .await
+            .unwrap();
+        assert_eq!(manager.used_storage_bytes, b"new owner data".len() as u64);
+        assert_eq!(manager.retrieve_fragment("frag-001").await.unwrap(), b"new owner data");
+    }
+
+    #[tokio::test]
+    async fn test_stale_owner_requests_refused() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = StorageManager::new(temp_dir.path().to_path_buf(), 1_000_000);
+        manager.initialize().await.unwrap();
+
+        let (owner, _) = UserIdentity::generate(None).unwrap();
+        let owner_id = owner.public_id();
+        manager
+            .update_identity(owner.identity_document(None, vec![], vec![]).unwrap())
+            .unwrap();
+        manager
+            .handle_request(&store_request("frag-001", &owner_id, b"uploaded again", &owner))
+            .await
+            .unwrap();
+
+        let now = chrono::Utc::now().timestamp();
+        let delete_at = |signed_at: i64| StorageRequest::Delete {
+            request: Signed::sign_at(
+                DeleteRequest {
+                    fragment_id: "frag-001".into(),
+                    owner_id: owner_id.clone(),
+                },
+                owner.signing_keys(),
+                signed_at,
+            )
+            .unwrap(),
+            device_certificate: None,
+        };
+
+        // A captured delete replayed later, or one dated ahead, leaves the fragment alone
+        for signed_at in [now - MAX_REQUEST_AGE - 1, now + DEFAULT_MAX_FUTURE_SKEW + 60] {
+            let error = manager.handle_request(&delete_at(signed_at)).await.unwrap_err();
+            assert!(matches!(error, P2PError::Replay(_)));
+        }
+        assert_eq!(manager.retrieve_fragment("frag-001").await.unwrap(), b"uploaded again");
+
+        let response = manager.handle_request(&delete_at(now)).await.unwrap();
+        assert!(matches!(response, StorageResponse::Deleted { .. }));
+    }
 }
@@ -0,0 +1,204 @@
+//! Content-defined chunking (FastCDC)
+//!
+//! Splits files at boundaries chosen by the content itself, so an edit only
+//! changes the chunks around it and the rest of the file can be reused.
+
+use serde::{Deserialize, Serialize};
+
+/// Chunk size bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkerConfig {
+    /// No cut point before this many bytes
+    pub min_size: usize,
+
+    /// Target average chunk size (rounded down to a power of two)
+    pub avg_size: usize,
+
+    /// Chunks are always cut at this size
+    pub max_size: usize,
+}
+
+impl Default for ChunkerConfig {
+    fn default() -> Self {
+        // 16 KB / 64 KB / 256 KB
+        Self {
+            min_size: 16 * 1024,
+            avg_size: 64 * 1024,
+            max_size: 256 * 1024,
+        }
+    }
+}
+
+/// FastCDC chunker with normalized chunking
+#[derive(Debug, Clone)]
+pub struct Chunker {
+    config: ChunkerConfig,
+
+    /// Harder mask used before the average size
+    mask_small: u64,
+
+    /// Easier mask used after the average size
+    mask_large: u64,
+}
+
+impl Chunker {
+    /// Create a chunker with the given size bounds
+    pub fn new(config: ChunkerConfig) -> Self {
+        let min_size = config.min_size.max(1);
+        let max_size = config.max_size.max(min_size);
+        let avg_size = config.avg_size.clamp(min_size, max_size);
+        let bits = (usize::BITS - 1 - avg_size.leading_zeros()).clamp(2, 62);
+
+        Self {
+            config: ChunkerConfig {
+                min_size,
+                avg_size,
+                max_size,
+            },
+            mask_small: mask(bits + 2),
+            mask_large: mask(bits - 2),
+        }
+    }
+
+    /// Get the chunk size bounds
+    pub fn config(&self) -> ChunkerConfig {
+        self.config
+    }
+
+    /// Split data into content-defined chunks
+    pub fn chunks<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut chunks = Vec::new();
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at(self.cut_point(rest));
+            chunks.push(chunk);
+            rest = tail;
+        }
+
+        chunks
+    }
+
+    /// Length of the next chunk at the start of `data`
+    fn cut_point(&self, data: &[u8]) -> usize {
+        let ChunkerConfig {
+            min_size,
+            avg_size,
+            max_size,
+        } = self.config;
+
+        if data.len() <= min_size {
+            return data.len();
+        }
+        let end = data.len().min(max_size);
+        let normal = avg_size.min(end);
+
+        let mut hash = 0u64;
+        for (i, byte) in data.iter().enumerate().take(end).skip(min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+            let mask = if i < normal { self.mask_small } else { self.mask_large };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+
+        end
+    }
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::new(ChunkerConfig::default())
+    }
+}
+
+/// Mask of the `bits` highest bits
+/// The gear hash shifts left once per byte, so bit k only depends on the
+/// last k + 1 bytes: the newest byte lands in the low bits, and the high
+/// bits mix in the older bytes of the 64-byte window. Testing the high bits
+/// makes a cut point depend on that whole window.
+fn mask(bits: u32) -> u64 {
+    !0u64 << (64 - bits)
+}
+
+/// Gear hash table (fixed pseudo-random values, so boundaries never change)
+static GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // SplitMix64
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x636C_6F75_6470_3270; // "cloudp2p"
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{RngCore, SeedableRng};
+
+    fn random_data(len: usize, seed: u64) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        rand_chacha::ChaCha8Rng::seed_from_u64(seed).fill_bytes(&mut data);
+        data
+    }
+
+    fn small_chunker() -> Chunker {
+        Chunker::new(ChunkerConfig {
+            min_size: 256,
+            avg_size: 1024,
+            max_size: 4096,
+        })
+    }
+
+    #[test]
+    fn test_chunks_cover_data_within_bounds() {
+        let chunker = small_chunker();
+        let data = random_data(100_000, 1);
+        let chunks = chunker.chunks(&data);
+
+        assert_eq!(chunks.concat(), data);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= 256 && chunk.len() <= 4096);
+        }
+
+        // Normalized chunking keeps the average near the target
+        let avg = data.len() / chunks.len();
+        assert!((512..=2048).contains(&avg), "average chunk size {}", avg);
+
+        assert!(chunker.chunks(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_boundaries_survive_an_insertion() {
+        let chunker = small_chunker();
+        let original = random_data(100_000, 2);
+
+        let mut edited = original.clone();
+        edited.splice(50_000..50_000, b"a few inserted bytes".iter().copied());
+
+        let before = chunker.chunks(&original);
+        let after = chunker.chunks(&edited);
+
+        // Only the chunks around the edit change
+        let unchanged = after.iter().filter(|c| before.contains(c)).count();
+        assert!(unchanged + 3 >= before.len(), "{} of {} chunks kept", unchanged, before.len());
+    }
+
+    #[test]
+    fn test_repetitive_data_cut_at_max_size() {
+        let chunker = small_chunker();
+        let data = vec![0u8; 10_000];
+
+        let sizes: Vec<usize> = chunker.chunks(&data).iter().map(|c| c.len()).collect();
+        assert_eq!(sizes, vec![4096, 4096, 1808]);
+    }
+}
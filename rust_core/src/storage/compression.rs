@@ -0,0 +1,150 @@
+//! Compression stage applied before encryption
+//!
+//! Ciphertext does not compress, so files are compressed before they are
+//! encrypted. Already-compressed formats are skipped by MIME type, and
+//! anything else has to pass a trial compression of its first block.
+
+use super::StorageError;
+
+use serde::{Deserialize, Serialize};
+
+/// Bytes compressed in the trial run
+const TRIAL_SIZE: usize = 64 * 1024;
+
+/// Trial output must be at most this fraction of the input to be worth it
+const MAX_TRIAL_RATIO: f64 = 0.9;
+
+/// Compression codec applied to a file before encryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Compression {
+    /// Stored as is
+    #[default]
+    None,
+
+    /// LZ4 block format (fast, moderate ratio)
+    Lz4,
+
+    /// Zstandard at the given level (1-22)
+    Zstd(i32),
+}
+
+impl Compression {
+    /// Pick the codec for a file: `self` if the data is worth compressing, else `None`
+    pub fn select(self, mime_type: &str, data: &[u8]) -> Compression {
+        if self == Compression::None || data.is_empty() || is_precompressed(mime_type) {
+            return Compression::None;
+        }
+
+        let sample = &data[..data.len().min(TRIAL_SIZE)];
+        match self.compress(sample) {
+            Ok(compressed) if (compressed.len() as f64) <= sample.len() as f64 * MAX_TRIAL_RATIO => self,
+            _ => Compression::None,
+        }
+    }
+
+    /// Compress data
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, StorageError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => Ok(lz4_flex::block::compress(data)),
+            Compression::Zstd(level) => {
+                zstd::bulk::compress(data, level).map_err(|e| StorageError::Compression(e.to_string()))
+            }
+        }
+    }
+
+    /// Decompress data of a known original size
+    /// Output larger than `original_size` is rejected rather than allocated
+    pub fn decompress(self, data: &[u8], original_size: usize) -> Result<Vec<u8>, StorageError> {
+        let decompressed = match self {
+            Compression::None => data.to_vec(),
+            Compression::Lz4 => lz4_flex::block::decompress(data, original_size)
+                .map_err(|e| StorageError::Compression(e.to_string()))?,
+            Compression::Zstd(_) => zstd::bulk::decompress(data, original_size)
+                .map_err(|e| StorageError::Compression(e.to_string()))?,
+        };
+
+        if decompressed.len() != original_size {
+            return Err(StorageError::Compression(format!(
+                "Expected {} bytes, got {}",
+                original_size,
+                decompressed.len()
+            )));
+        }
+        Ok(decompressed)
+    }
+}
+
+/// MIME types whose content is already compressed
+fn is_precompressed(mime_type: &str) -> bool {
+    let (kind, subtype) = mime_type.split_once('/').unwrap_or((mime_type, ""));
+    match kind {
+        "image" => !matches!(subtype, "bmp" | "svg+xml" | "tiff" | "x-icon"),
+        "video" | "audio" => !matches!(subtype, "wav" | "x-wav"),
+        "application" => matches!(
+            subtype,
+            "zip"
+                | "gzip"
+                | "x-gzip"
+                | "x-bzip2"
+                | "x-xz"
+                | "x-7z-compressed"
+                | "vnd.rar"
+                | "x-rar-compressed"
+                | "zstd"
+                | "java-archive"
+                | "epub+zip"
+                | "vnd.openxmlformats-officedocument.wordprocessingml.document"
+                | "vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+                | "vnd.openxmlformats-officedocument.presentationml.presentation"
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    fn csv_data() -> Vec<u8> {
+        (0..5000)
+            .map(|i| format!("{},user{},2024-01-{:02},ok\n", i, i % 50, i % 28 + 1))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn test_roundtrip_all_codecs() {
+        let data = csv_data();
+        for codec in [Compression::None, Compression::Lz4, Compression::Zstd(3), Compression::Zstd(19)] {
+            let compressed = codec.compress(&data).unwrap();
+            if codec != Compression::None {
+                assert!(compressed.len() * 2 < data.len(), "{:?} compressed to {}", codec, compressed.len());
+            }
+            assert_eq!(codec.decompress(&compressed, data.len()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_select_skips_incompressible() {
+        let text = csv_data();
+        let mut random = vec![0u8; 100_000];
+        rand::thread_rng().fill_bytes(&mut random);
+
+        assert_eq!(Compression::Zstd(3).select("text/csv", &text), Compression::Zstd(3));
+        assert_eq!(Compression::Lz4.select("application/octet-stream", &random), Compression::None);
+        assert_eq!(Compression::Lz4.select("image/jpeg", &text), Compression::None);
+        assert_eq!(Compression::Lz4.select("application/zip", &text), Compression::None);
+        assert_eq!(Compression::None.select("text/csv", &text), Compression::None);
+    }
+
+    #[test]
+    fn test_decompress_rejects_wrong_size() {
+        let data = csv_data();
+        let compressed = Compression::Zstd(3).compress(&data).unwrap();
+
+        assert!(Compression::Zstd(3).decompress(&compressed, data.len() / 2).is_err());
+        assert!(Compression::Lz4.decompress(&compressed, data.len()).is_err());
+    }
+}
@@ -235,9 +235,11 @@ pub struct Shard {
 }
 
 impl Shard {
-    /// Get a unique ID for this shard (based on content hash + index)
-    pub fn id(&self, file_hash: &str) -> String {
-        format!("{}-shard-{:02}", file_hash, self.index)
+    /// Get a unique ID for this shard (based on a file ID + index)
+    /// Shard IDs are public: pass a network ID such as `FileMetadata::locator`,
+    /// never the content hash of the plaintext
+    pub fn id(&self, file_id: &str) -> String {
+        format!("{}-shard-{:02}", file_id, self.index)
     }
 }
 
@@ -3,39 +3,82 @@
 //! Handles the complete lifecycle of files: encryption, erasure coding,
 //! distribution to peers, and retrieval.
 
+use super::chunker::{Chunker, ChunkerConfig};
+use super::compression::Compression;
+use super::inheritance::InheritedFile;
+use super::metadata::{PrivateMetadata, PublishedMetadata};
+use super::padding::Padding;
 use super::{ErasureConfig, ErasureDecoder, ErasureEncoder, StorageError};
-use crate::crypto::{ContentHash, EncryptionKey, FileEncryptor};
-use crate::identity::UserIdentity;
+use crate::crypto::encryption::{derive_convergent_key, derive_file_key};
+use crate::crypto::{CipherSuite, ContentHash, EncryptionKey, FileEncryptor, KdfParams, Signed, SignedPayload};
+use crate::identity::{BackupContents, EscrowRecord, IdentityBackup, IdentityDocument, UserIdentity};
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use tokio::sync::mpsc;
+use zeroize::Zeroizing;
 
-/// File metadata stored locally and in DHT
+/// File metadata as kept locally by the owner
+/// Kept as `Signed<FileMetadata>`; any change must be followed by
+/// `FileManager::sign_metadata`. Only leaves the device as
+/// `PublishedMetadata` (see `FileManager::publish_metadata`)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileMetadata {
-    /// Unique file ID (content hash of original file)
+    /// Unique file ID (content hash of original file, never published)
     pub file_id: String,
 
+    /// File ID used on the network: random, or keyed under the convergence
+    /// scope for convergent files, so it reveals nothing about the content
+    #[serde(default)]
+    pub locator: String,
+
     /// Original filename
     pub filename: String,
 
-    /// Original file size (bytes)
+    /// Original file size (bytes), or the padded size for padded files
     pub size: u64,
 
+    /// Original file size sealed under the owner's key (padded files only)
+    #[serde(default)]
+    pub sealed_size: Vec<u8>,
+
     /// MIME type
     pub mime_type: String,
 
     /// Content hash of encrypted data
     pub encrypted_hash: String,
 
+    /// Cipher suite the file was encrypted with
+    #[serde(default)]
+    pub cipher_suite: CipherSuite,
+
+    /// Size of the encrypted envelope before erasure coding
+    #[serde(default)]
+    pub encrypted_size: u64,
+
+    /// How the file key was chosen
+    #[serde(default)]
+    pub encryption_mode: EncryptionMode,
+
+    /// Codec applied before encryption
+    #[serde(default)]
+    pub compression: Compression,
+
+    /// Size-hiding padding applied after compression
+    #[serde(default)]
+    pub padding: Padding,
+
     /// Erasure coding config used
     pub erasure_config: ErasureConfig,
 
     /// Shard IDs and their storage locations
     pub shards: Vec<ShardLocation>,
 
+    /// Content-defined chunks, in file order (empty for files uploaded whole)
+    #[serde(default)]
+    pub chunks: Vec<ChunkRef>,
+
     /// Creation timestamp
     pub created_at: i64,
 
@@ -54,11 +97,35 @@ pub struct FileMetadata {
     /// File encryption key (encrypted with owner's master key)
     pub encrypted_file_key: Vec<u8>,
 
+    /// Key epoch of the master key wrapping the file and chunk keys
+    #[serde(default)]
+    pub key_epoch: u32,
+
     /// Parent folder ID (for organization)
     pub folder_id: Option<String>,
 
     /// Custom tags
     pub tags: Vec<String>,
+
+    /// Owner's Ed25519 public key (must match `owner_id`)
+    #[serde(default)]
+    pub owner_key: Vec<u8>,
+}
+
+impl SignedPayload for FileMetadata {
+    const CONTEXT: &'static str = "file manifest";
+}
+
+impl Signed<FileMetadata> {
+    /// Check that the owner named in the manifest signed it
+    pub fn verify_manifest(&self) -> Result<&FileMetadata, StorageError> {
+        let metadata = &self.payload;
+        if self.signature.is_empty() {
+            return Err(StorageError::UnsignedManifest(metadata.file_id.clone()));
+        }
+        self.verify_by_owner(&metadata.owner_key, &metadata.owner_id)
+            .map_err(|_| StorageError::InvalidManifestSignature(metadata.file_id.clone()))
+    }
 }
 
 /// Location of a shard in the network
@@ -86,15 +153,81 @@ pub struct UploadProgress {
     pub file_id: String,
     pub filename: String,
     pub total_bytes: u64,
+    /// Size after compression (equal to `total_bytes` when stored as is)
+    pub compressed_bytes: u64,
+    /// Size after padding (equal to `compressed_bytes` when not padded)
+    pub padded_bytes: u64,
     pub uploaded_bytes: u64,
     pub shards_total: usize,
     pub shards_uploaded: usize,
     pub stage: UploadStage,
 }
 
+impl UploadProgress {
+    /// Bytes saved by compression
+    pub fn saved_bytes(&self) -> u64 {
+        self.total_bytes.saturating_sub(self.compressed_bytes)
+    }
+
+    /// Bytes added by padding
+    pub fn padding_overhead(&self) -> u64 {
+        self.padded_bytes.saturating_sub(self.compressed_bytes)
+    }
+}
+
+/// A content-defined chunk of a file, encrypted and erasure coded on its own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkRef {
+    /// Keyed hash of the chunk plaintext (base58)
+    pub chunk_id: String,
+
+    /// Plaintext size, or the padded size for padded chunks
+    pub size: u64,
+
+    /// Plaintext size sealed under the owner's key (padded chunks only)
+    #[serde(default)]
+    pub sealed_size: Vec<u8>,
+
+    /// Codec applied before encryption
+    #[serde(default)]
+    pub compression: Compression,
+
+    /// Size-hiding padding applied after compression
+    #[serde(default)]
+    pub padding: Padding,
+
+    /// Size of the encrypted envelope before erasure coding
+    pub encrypted_size: u64,
+
+    /// Chunk key (encrypted with owner's master key)
+    pub encrypted_key: Vec<u8>,
+
+    /// Key epoch of the master key wrapping the chunk key
+    #[serde(default)]
+    pub key_epoch: u32,
+
+    /// Shard IDs and their storage locations
+    pub shards: Vec<ShardLocation>,
+}
+
+/// How file keys are chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum EncryptionMode {
+    /// Fresh random key per upload (nothing is shared between uploads)
+    #[default]
+    Random,
+
+    /// Key derived from the content under a user or team secret, so identical
+    /// files produce identical shards that hosts can deduplicate. Trades some
+    /// privacy for storage: anyone holding the secret can tell whether a given
+    /// file is stored, and hosts can see which shards are shared.
+    Convergent,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UploadStage {
     Reading,
+    Compressing,
     Encrypting,
     Encoding,
     Distributing,
@@ -103,6 +236,25 @@ pub enum UploadStage {
     Failed,
 }
 
+/// Key re-wrapping progress tracking
+#[derive(Debug, Clone)]
+pub struct RewrapProgress {
+    pub file_id: String,
+    pub files_total: usize,
+    pub files_done: usize,
+    pub target_epoch: u32,
+}
+
+/// Outcome of a `rewrap_file_keys` call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewrapSummary {
+    /// Files re-wrapped by this call
+    pub rewrapped: usize,
+
+    /// Files still wrapped under an older epoch
+    pub remaining: usize,
+}
+
 /// Download progress tracking
 #[derive(Debug, Clone)]
 pub struct DownloadProgress {
@@ -131,19 +283,37 @@ pub struct FileManager {
     identity: UserIdentity,
 
     /// Local file index
-    file_index: HashMap<String, FileMetadata>,
+    file_index: HashMap<String, Signed<FileMetadata>>,
 
     /// Erasure coding config
     erasure_config: ErasureConfig,
 
+    /// Cipher suite for new uploads
+    cipher_suite: CipherSuite,
+
+    /// Scope secret for convergent encryption (random keys if unset)
+    convergence_secret: Option<EncryptionKey>,
+
+    /// Content-defined chunker for chunked uploads
+    chunker: Chunker,
+
     /// Local cache path
     cache_path: PathBuf,
 
+    /// Preferred compression codec (skipped for incompressible files)
+    compression: Compression,
+
+    /// Size-hiding padding
+    padding: Padding,
+
     /// Progress sender for uploads
     upload_progress_tx: Option<mpsc::UnboundedSender<UploadProgress>>,
 
     /// Progress sender for downloads
     download_progress_tx: Option<mpsc::UnboundedSender<DownloadProgress>>,
+
+    /// Progress sender for key re-wrapping
+    rewrap_progress_tx: Option<mpsc::UnboundedSender<RewrapProgress>>,
 }
 
 impl FileManager {
@@ -153,9 +323,15 @@ impl FileManager {
             identity,
             file_index: HashMap::new(),
             erasure_config: ErasureConfig::default(),
+            cipher_suite: CipherSuite::default(),
+            convergence_secret: None,
+            chunker: Chunker::default(),
             cache_path,
+            compression: Compression::None,
+            padding: Padding::None,
             upload_progress_tx: None,
             download_progress_tx: None,
+            rewrap_progress_tx: None,
         }
     }
 
@@ -165,6 +341,45 @@ impl FileManager {
         self
     }
 
+    /// Set cipher suite for new uploads
+    /// `CipherSuite::preferred()` picks XChaCha20-Poly1305 on CPUs without AES instructions
+    pub fn with_cipher_suite(mut self, suite: CipherSuite) -> Self {
+        self.cipher_suite = suite;
+        self
+    }
+
+    /// Use convergent encryption scoped to this user: the same user uploading
+    /// the same file twice produces identical shards (see `EncryptionMode::Convergent`)
+    pub fn with_convergent_encryption(mut self) -> Self {
+        self.convergence_secret = Some(self.convergence_scope());
+        self
+    }
+
+    /// Use convergent encryption scoped to a secret shared by a team, so
+    /// identical files uploaded by any member produce identical shards
+    pub fn with_team_convergence(mut self, team_secret: EncryptionKey) -> Self {
+        self.convergence_secret = Some(team_secret);
+        self
+    }
+
+    /// Set the chunk sizes used by `prepare_chunked_upload`
+    pub fn with_chunker_config(mut self, config: ChunkerConfig) -> Self {
+        self.chunker = Chunker::new(config);
+        self
+    }
+
+    /// Compress files before encryption when it pays off
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Pad files before encryption so hosts only see a size bucket
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
+    }
+
     /// Set upload progress channel
     pub fn with_upload_progress(
         mut self,
@@ -183,6 +398,15 @@ impl FileManager {
         self
     }
 
+    /// Set key re-wrapping progress channel
+    pub fn with_rewrap_progress(
+        mut self,
+        tx: mpsc::UnboundedSender<RewrapProgress>,
+    ) -> Self {
+        self.rewrap_progress_tx = Some(tx);
+        self
+    }
+
     /// Prepare a file for upload (encrypt and encode)
     pub async fn prepare_upload(
         &self,
@@ -197,23 +421,38 @@ impl FileManager {
         let original_size = data.len();
         let original_hash = ContentHash::hash(&data);
 
-        // Generate per-file encryption key
-        let file_key = EncryptionKey::generate();
-
-        // Encrypt file
-        let encryptor = FileEncryptor::new(file_key.clone());
-        let encrypted = encryptor
-            .encrypt_file(&data)
-            .map_err(|e| StorageError::Encryption(e.to_string()))?;
+        // Generate per-file encryption key (derived from the content in convergent mode)
+        let (file_key, encryption_mode) = match &self.convergence_secret {
+            Some(secret) => (derive_convergent_key(secret, &data), EncryptionMode::Convergent),
+            None => (EncryptionKey::generate(), EncryptionMode::Random),
+        };
 
-        let encrypted_data = encrypted.to_bytes()
-            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+        // Compress before encrypting, if it pays off
+        let base_metadata = self.new_metadata(&original_hash, filename, original_size);
+        let compression = self.compression.select(&base_metadata.mime_type, &data);
+        let compressed = compression.compress(&data)?;
+
+        // Pad so the encrypted length only reveals a size bucket
+        let padded = self.padding.pad(&compressed);
+
+        if let Some(tx) = &self.upload_progress_tx {
+            let _ = tx.send(UploadProgress {
+                file_id: base_metadata.file_id.clone(),
+                filename: filename.to_string(),
+                total_bytes: original_size as u64,
+                compressed_bytes: compressed.len() as u64,
+                padded_bytes: padded.len() as u64,
+                uploaded_bytes: 0,
+                shards_total: self.erasure_config.total_shards(),
+                shards_uploaded: 0,
+                stage: UploadStage::Compressing,
+            });
+        }
 
-        let encrypted_hash = ContentHash::hash(&encrypted_data);
+        // Encrypt and erasure encode
+        let unit = self.seal_unit(&padded, &file_key, encryption_mode, &base_metadata.locator)?;
 
-        // Erasure encode
-        let encoder = ErasureEncoder::new(self.erasure_config)?;
-        let shards = encoder.encode(&encrypted_data)?;
+        let (size, sealed_size) = self.recorded_size(original_size, padded.len())?;
 
         // Encrypt the file key with user's master key
         let encrypted_file_key = self
@@ -221,57 +460,315 @@ impl FileManager {
             .encrypt(file_key.as_bytes())
             .map_err(|e| StorageError::Encryption(e.to_string()))?;
 
-        // Determine MIME type
-        let mime_type = mime_guess::from_path(filename)
-            .first_or_octet_stream()
-            .to_string();
+        let metadata = FileMetadata {
+            encrypted_hash: unit.encrypted_hash.to_base58(),
+            encrypted_size: unit.encrypted_size,
+            encryption_mode,
+            compression,
+            padding: self.padding,
+            size,
+            sealed_size,
+            shards: unit.locations,
+            encrypted_file_key,
+            ..base_metadata
+        };
+        let metadata = self.sign_metadata(metadata)?;
 
-        let now = chrono::Utc::now().timestamp();
+        Ok(PreparedFile { metadata, shards: unit.shards })
+    }
+
+    /// Prepare a file for upload as content-defined chunks
+    /// Each chunk is compressed, padded, encrypted (with the configured
+    /// encryption mode) and erasure coded on its own. Chunks already present in
+    /// the local index (unchanged parts of an earlier version, or duplicates
+    /// from other files) are reused instead of being re-encoded, so only new
+    /// chunks need to be distributed.
+    pub async fn prepare_chunked_upload(
+        &self,
+        file_path: &str,
+        filename: &str,
+    ) -> Result<PreparedChunkedFile, StorageError> {
+        let data = tokio::fs::read(file_path)
+            .await
+            .map_err(StorageError::Io)?;
+        let original_hash = ContentHash::hash(&data);
+        let base_metadata = self.new_metadata(&original_hash, filename, data.len());
+
+        let chunk_id_key = derive_file_key(&self.convergence_scope(), b"cloudp2p-chunk-id");
+        let encryption_mode = match self.convergence_secret {
+            Some(_) => EncryptionMode::Convergent,
+            None => EncryptionMode::Random,
+        };
+
+        // Chunks we already hold, from the index or earlier in this file
+        let mut known: HashMap<String, ChunkRef> = self
+            .file_index
+            .values()
+            .flat_map(|f| f.payload.chunks.iter())
+            .map(|c| (c.chunk_id.clone(), c.clone()))
+            .collect();
+
+        let mut chunks = Vec::new();
+        let mut new_chunks = Vec::new();
+        let mut reused_chunks = 0;
+
+        // Sizes of the new chunks before and after compression and padding
+        let (mut new_bytes, mut compressed_bytes, mut padded_bytes) = (0, 0, 0);
+
+        for chunk in self.chunker.chunks(&data) {
+            let chunk_id = ContentHash::keyed_hash(chunk_id_key.as_bytes(), chunk).to_base58();
+
+            if let Some(existing) = known.get(&chunk_id) {
+                reused_chunks += 1;
+                chunks.push(existing.clone());
+                continue;
+            }
+
+            let chunk_key = match &self.convergence_secret {
+                Some(secret) => derive_convergent_key(secret, chunk),
+                None => EncryptionKey::generate(),
+            };
+
+            let compression = self.compression.select(&base_metadata.mime_type, chunk);
+            let compressed = compression.compress(chunk)?;
+            let padded = self.padding.pad(&compressed);
+            new_bytes += chunk.len() as u64;
+            compressed_bytes += compressed.len() as u64;
+            padded_bytes += padded.len() as u64;
+            let unit = self.seal_unit(&padded, &chunk_key, encryption_mode, &chunk_id)?;
+            let (size, sealed_size) = self.recorded_size(chunk.len(), padded.len())?;
+            let encrypted_key = self
+                .identity
+                .encrypt(chunk_key.as_bytes())
+                .map_err(|e| StorageError::Encryption(e.to_string()))?;
+
+            let chunk_ref = ChunkRef {
+                chunk_id: chunk_id.clone(),
+                size,
+                sealed_size,
+                compression,
+                padding: self.padding,
+                encrypted_size: unit.encrypted_size,
+                encrypted_key,
+                key_epoch: self.identity.key_epoch(),
+                shards: unit.locations,
+            };
+            known.insert(chunk_id.clone(), chunk_ref.clone());
+            chunks.push(chunk_ref);
+            new_chunks.push(PreparedChunk {
+                chunk_id,
+                shards: unit.shards,
+            });
+        }
+
+        // Reused chunks are not uploaded again, so they count towards neither size
+        if let Some(tx) = &self.upload_progress_tx {
+            let _ = tx.send(UploadProgress {
+                file_id: base_metadata.file_id.clone(),
+                filename: filename.to_string(),
+                total_bytes: new_bytes,
+                compressed_bytes,
+                padded_bytes,
+                uploaded_bytes: 0,
+                shards_total: new_chunks.len() * self.erasure_config.total_shards(),
+                shards_uploaded: 0,
+                stage: UploadStage::Compressing,
+            });
+        }
+
+        // The chunk list stands in for the encrypted file
+        let manifest_hash = ContentHash::hash(
+            chunks
+                .iter()
+                .map(|c| c.chunk_id.as_str())
+                .collect::<Vec<_>>()
+                .join(":")
+                .as_bytes(),
+        );
 
-        let shard_locations: Vec<ShardLocation> = shards
+        let padded_size = chunks.iter().map(|c| c.size as usize).sum();
+        let (size, sealed_size) = self.recorded_size(data.len(), padded_size)?;
+
+        let metadata = FileMetadata {
+            encrypted_hash: manifest_hash.to_base58(),
+            encryption_mode,
+            padding: self.padding,
+            size,
+            sealed_size,
+            chunks,
+            ..base_metadata
+        };
+        let metadata = self.sign_metadata(metadata)?;
+
+        Ok(PreparedChunkedFile {
+            metadata,
+            new_chunks,
+            reused_chunks,
+        })
+    }
+
+    /// Reconstruct a file from shards
+    pub async fn reconstruct_file(
+        &self,
+        metadata: &Signed<FileMetadata>,
+        shard_data: Vec<Option<Vec<u8>>>,
+    ) -> Result<Vec<u8>, StorageError> {
+        let metadata = metadata.verify_manifest()?;
+
+        let decrypted = self.open_unit(
+            metadata.erasure_config,
+            &metadata.shards,
+            metadata.encrypted_size,
+            &metadata.encrypted_file_key,
+            metadata.key_epoch,
+            shard_data,
+        )?;
+        let compressed = metadata.padding.unpad(decrypted)?;
+        let original_size = self.original_size(metadata)?;
+        let plaintext = metadata.compression.decompress(&compressed, original_size as usize)?;
+
+        // Verify content hash
+        let hash = ContentHash::hash(&plaintext);
+        if hash.to_base58() != metadata.file_id {
+            return Err(StorageError::IntegrityCheckFailed);
+        }
+
+        Ok(plaintext)
+    }
+
+    /// Reconstruct a chunked file from the shards of each of its chunks (in manifest order)
+    pub async fn reconstruct_chunked_file(
+        &self,
+        metadata: &Signed<FileMetadata>,
+        chunk_shards: Vec<Vec<Option<Vec<u8>>>>,
+    ) -> Result<Vec<u8>, StorageError> {
+        let metadata = metadata.verify_manifest()?;
+
+        if chunk_shards.len() != metadata.chunks.len() {
+            return Err(StorageError::InsufficientFragments {
+                have: chunk_shards.len(),
+                need: metadata.chunks.len(),
+            });
+        }
+
+        let mut plaintext = Vec::with_capacity(metadata.size as usize);
+        for (chunk, shard_data) in metadata.chunks.iter().zip(chunk_shards) {
+            let decrypted = self.open_unit(
+                metadata.erasure_config,
+                &chunk.shards,
+                chunk.encrypted_size,
+                &chunk.encrypted_key,
+                chunk.key_epoch,
+                shard_data,
+            )?;
+            let compressed = chunk.padding.unpad(decrypted)?;
+            let chunk_size = self.unseal_size(chunk.size, &chunk.sealed_size, chunk.key_epoch)?;
+            let data = chunk.compression.decompress(&compressed, chunk_size as usize)?;
+            plaintext.extend_from_slice(&data);
+        }
+
+        // Verify content hash
+        let hash = ContentHash::hash(&plaintext);
+        if hash.to_base58() != metadata.file_id {
+            return Err(StorageError::IntegrityCheckFailed);
+        }
+
+        Ok(plaintext)
+    }
+
+    /// Original size of a file (unsealed for padded files)
+    pub fn original_size(&self, metadata: &FileMetadata) -> Result<u64, StorageError> {
+        self.unseal_size(metadata.size, &metadata.sealed_size, metadata.key_epoch)
+    }
+
+    /// Size to record for a file or chunk, and its true size sealed under the
+    /// owner's key (the true size is only kept sealed when padding)
+    fn recorded_size(&self, original_size: usize, padded_size: usize) -> Result<(u64, Vec<u8>), StorageError> {
+        match self.padding {
+            Padding::None => Ok((original_size as u64, vec![])),
+            _ => Ok((padded_size as u64, self.wrap_key(&(original_size as u64).to_le_bytes())?)),
+        }
+    }
+
+    /// True size of a file or chunk from its recorded size
+    fn unseal_size(&self, size: u64, sealed_size: &[u8], key_epoch: u32) -> Result<u64, StorageError> {
+        if sealed_size.is_empty() {
+            return Ok(size);
+        }
+
+        let bytes = self.unwrap_key(sealed_size, key_epoch)?;
+        let bytes: [u8; 8] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| StorageError::Encryption("Invalid sealed size".into()))?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Secret that scopes convergent keys and chunk IDs (team secret, or one derived from the user)
+    fn convergence_scope(&self) -> EncryptionKey {
+        self.convergence_secret
+            .clone()
+            .unwrap_or_else(|| self.identity.convergence_secret())
+    }
+
+    /// Encrypt and erasure encode one unit (a whole file or a chunk)
+    fn seal_unit(
+        &self,
+        data: &[u8],
+        key: &EncryptionKey,
+        mode: EncryptionMode,
+        shard_prefix: &str,
+    ) -> Result<SealedUnit, StorageError> {
+        let mut encryptor = FileEncryptor::new(key.clone()).with_suite(self.cipher_suite);
+        if mode == EncryptionMode::Convergent {
+            encryptor = encryptor.with_deterministic_nonces();
+        }
+        let encrypted = encryptor
+            .encrypt_file(data)
+            .map_err(|e| StorageError::Encryption(e.to_string()))?;
+
+        let encrypted_data = encrypted.to_bytes()
+            .map_err(|e| StorageError::Encryption(e.to_string()))?;
+
+        // Erasure encode
+        let encoder = ErasureEncoder::new(self.erasure_config)?;
+        let shards = encoder.encode(&encrypted_data)?;
+
+        let locations = shards
             .iter()
             .map(|s| ShardLocation {
                 index: s.index,
-                shard_id: s.id(&original_hash.to_base58()),
+                shard_id: s.id(shard_prefix),
                 peers: vec![], // Will be filled during distribution
                 size: s.data.len() as u64,
                 hash: ContentHash::hash(&s.data).to_base58(),
             })
             .collect();
 
-        let metadata = FileMetadata {
-            file_id: original_hash.to_base58(),
-            filename: filename.to_string(),
-            size: original_size as u64,
-            mime_type,
-            encrypted_hash: encrypted_hash.to_base58(),
-            erasure_config: self.erasure_config,
-            shards: shard_locations,
-            created_at: now,
-            modified_at: now,
-            owner_id: self.identity.public_id(),
-            is_shared: false,
-            shared_with: vec![],
-            encrypted_file_key,
-            folder_id: None,
-            tags: vec![],
-        };
-
-        Ok(PreparedFile { metadata, shards })
+        Ok(SealedUnit {
+            encrypted_hash: ContentHash::hash(&encrypted_data),
+            encrypted_size: encrypted_data.len() as u64,
+            shards,
+            locations,
+        })
     }
 
-    /// Reconstruct a file from shards
-    pub async fn reconstruct_file(
+    /// Erasure decode and decrypt one unit (a whole file or a chunk)
+    fn open_unit(
         &self,
-        metadata: &FileMetadata,
+        erasure_config: ErasureConfig,
+        locations: &[ShardLocation],
+        encrypted_size: u64,
+        encrypted_key: &[u8],
+        key_epoch: u32,
         shard_data: Vec<Option<Vec<u8>>>,
     ) -> Result<Vec<u8>, StorageError> {
         // Verify we have enough shards
         let available = shard_data.iter().filter(|s| s.is_some()).count();
-        if available < metadata.erasure_config.data_shards {
+        if available < erasure_config.data_shards {
             return Err(StorageError::InsufficientFragments {
                 have: available,
-                need: metadata.erasure_config.data_shards,
+                need: erasure_config.data_shards,
             });
         }
 
@@ -283,97 +780,365 @@ impl FileManager {
                 opt.map(|data| super::erasure::Shard {
                     index: i,
                     data,
-                    is_parity: i >= metadata.erasure_config.data_shards,
+                    is_parity: i >= erasure_config.data_shards,
                     original_size: 0, // Not needed for decoding
                 })
             })
             .collect();
 
         // Decode erasure coding
-        let decoder = ErasureDecoder::new(metadata.erasure_config)?;
-
-        // We need to know the encrypted size for proper reconstruction
-        let encrypted_size: usize = metadata.shards.iter().map(|s| s.size as usize).sum();
-        let estimated_size = encrypted_size / metadata.erasure_config.total_shards()
-            * metadata.erasure_config.data_shards;
+        let decoder = ErasureDecoder::new(erasure_config)?;
+
+        // We need to know the encrypted size for proper reconstruction:
+        // the envelope derives its chunk layout from its exact length
+        let encrypted_size = if encrypted_size > 0 {
+            encrypted_size as usize
+        } else {
+            let shard_total: usize = locations.iter().map(|s| s.size as usize).sum();
+            shard_total / erasure_config.total_shards() * erasure_config.data_shards
+        };
 
-        let encrypted_data = decoder.decode(shards, estimated_size)?;
+        let encrypted_data = decoder.decode(shards, encrypted_size)?;
 
-        // Decrypt file key
-        let file_key_bytes = self
-            .identity
-            .decrypt(&metadata.encrypted_file_key)
-            .map_err(|e| StorageError::Encryption(e.to_string()))?;
+        // Decrypt the unit key
+        let key_bytes = Zeroizing::new(
+            self.identity
+                .decrypt_with_epoch(encrypted_key, key_epoch)
+                .map_err(|e| StorageError::Encryption(e.to_string()))?,
+        );
 
-        let file_key_arr: [u8; 32] = file_key_bytes
+        let key_arr: [u8; 32] = key_bytes
+            .as_slice()
             .try_into()
             .map_err(|_| StorageError::Encryption("Invalid file key length".into()))?;
 
-        let file_key = EncryptionKey::new(file_key_arr);
+        let key = EncryptionKey::new(key_arr);
 
         // Parse encrypted file structure
         let encrypted_file = crate::crypto::EncryptedFile::from_bytes(&encrypted_data)
             .map_err(|e| StorageError::Encryption(e.to_string()))?;
 
-        // Decrypt file
-        let encryptor = FileEncryptor::new(file_key);
-        let plaintext = encryptor
+        // Decrypt (the suite is taken from the encrypted file itself)
+        FileEncryptor::new(key)
             .decrypt_file(&encrypted_file)
+            .map_err(|e| StorageError::Encryption(e.to_string()))
+    }
+
+    /// Metadata for a freshly prepared file (storage fields left empty)
+    fn new_metadata(&self, original_hash: &ContentHash, filename: &str, original_size: usize) -> FileMetadata {
+        // Determine MIME type
+        let mime_type = mime_guess::from_path(filename)
+            .first_or_octet_stream()
+            .to_string();
+
+        let now = chrono::Utc::now().timestamp();
+
+        FileMetadata {
+            file_id: original_hash.to_base58(),
+            locator: self.file_locator(original_hash),
+            filename: filename.to_string(),
+            size: original_size as u64,
+            sealed_size: vec![],
+            mime_type,
+            encrypted_hash: String::new(),
+            cipher_suite: self.cipher_suite,
+            encrypted_size: 0,
+            encryption_mode: EncryptionMode::Random,
+            compression: Compression::None,
+            padding: Padding::None,
+            erasure_config: self.erasure_config,
+            shards: vec![],
+            chunks: vec![],
+            created_at: now,
+            modified_at: now,
+            owner_id: self.identity.public_id(),
+            is_shared: false,
+            shared_with: vec![],
+            encrypted_file_key: vec![],
+            key_epoch: self.identity.key_epoch(),
+            folder_id: None,
+            tags: vec![],
+            owner_key: vec![],
+        }
+    }
+
+    /// Network ID of a new upload: a keyed hash of the content in convergent
+    /// mode (so identical files still share it), random otherwise
+    fn file_locator(&self, original_hash: &ContentHash) -> String {
+        match &self.convergence_secret {
+            Some(secret) => {
+                let locator_key = derive_file_key(secret, b"cloudp2p-file-locator");
+                ContentHash::keyed_hash(locator_key.as_bytes(), original_hash.as_bytes()).to_base58()
+            }
+            None => ContentHash::from_bytes(crate::crypto::random_32_bytes()).to_base58(),
+        }
+    }
+
+    /// Sign metadata as its owner (after filling in peers, tags, etc.)
+    pub fn sign_metadata(&self, mut metadata: FileMetadata) -> Result<Signed<FileMetadata>, StorageError> {
+        let keys = self.identity.signing_keys();
+        metadata.owner_key = keys.verifying_key.as_bytes().to_vec();
+        Signed::sign(metadata, keys).map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+
+    /// Prepare metadata for the DHT: routing part in the clear, the rest sealed
+    pub fn publish_metadata(&self, metadata: &Signed<FileMetadata>) -> Result<Signed<PublishedMetadata>, StorageError> {
+        let metadata = metadata.verify_manifest()?;
+        if metadata.owner_id != self.identity.public_id() {
+            return Err(StorageError::InvalidManifestSignature(metadata.file_id.clone()));
+        }
+
+        let (routing, private) = metadata.split();
+        let sealed_private = self
+            .identity
+            .encrypt(&private.to_bytes()?)
             .map_err(|e| StorageError::Encryption(e.to_string()))?;
 
-        // Verify content hash
-        let hash = ContentHash::hash(&plaintext);
-        if hash.to_base58() != metadata.file_id {
-            return Err(StorageError::IntegrityCheckFailed);
+        PublishedMetadata::sign(routing, sealed_private, self.identity.signing_keys())
+    }
+
+    /// Unseal published metadata back into the full local copy
+    /// The owner signature on the published copy covers the sealed part too,
+    /// so the rejoined manifest is simply signed again
+    pub fn open_metadata(&self, published: &Signed<PublishedMetadata>) -> Result<Signed<FileMetadata>, StorageError> {
+        let published = published.verify_published()?;
+        if published.routing.owner_id != self.identity.public_id() {
+            return Err(StorageError::InvalidManifestSignature(published.routing.locator.clone()));
         }
 
-        Ok(plaintext)
+        let private = self
+            .identity
+            .decrypt_with_epoch(&published.sealed_private, published.routing.key_epoch)
+            .map_err(|e| StorageError::Encryption(e.to_string()))?;
+        let private = PrivateMetadata::from_bytes(&private)?;
+
+        let metadata = FileMetadata::join(published.routing.clone(), private, published.owner_key.clone());
+        self.sign_metadata(metadata)
+    }
+
+    /// Rotate the master encryption key to a new epoch
+    /// New uploads are wrapped under the new key right away; existing files
+    /// stay readable and move over with `rewrap_file_keys`. The new key is
+    /// random: save a new keystore or `export_backup` to keep it
+    pub fn rotate_master_key(&mut self) -> Result<u32, StorageError> {
+        self.identity
+            .rotate_encryption_key()
+            .map_err(|e| StorageError::Encryption(e.to_string()))
+    }
+
+    /// Re-wrap file and chunk keys from older epochs under the current one
+    /// Nothing is re-uploaded: only the wrapped keys in the index change.
+    /// Files are handled in a fixed order and each records its new epoch as soon
+    /// as it is done, so an interrupted rotation resumes where it stopped
+    /// (persist the index with `export_index` between calls). `limit` caps the
+    /// number of files handled by this call.
+    pub fn rewrap_file_keys(&mut self, limit: Option<usize>) -> Result<RewrapSummary, StorageError> {
+        let target_epoch = self.identity.key_epoch();
+
+        let mut pending: Vec<String> = self
+            .file_index
+            .values()
+            .map(|f| &f.payload)
+            .filter(|f| f.key_epoch != target_epoch || f.chunks.iter().any(|c| c.key_epoch != target_epoch))
+            .map(|f| f.file_id.clone())
+            .collect();
+        pending.sort();
+
+        let files_total = pending.len();
+        let batch = limit.unwrap_or(files_total).min(files_total);
+
+        for (done, file_id) in pending.iter().take(batch).enumerate() {
+            let mut metadata = match self.file_index.get(file_id) {
+                Some(metadata) => metadata.payload.clone(),
+                None => continue,
+            };
+
+            if metadata.key_epoch != target_epoch {
+                if !metadata.encrypted_file_key.is_empty() {
+                    metadata.encrypted_file_key = self.rewrap_key(&metadata.encrypted_file_key, metadata.key_epoch)?;
+                }
+                if !metadata.sealed_size.is_empty() {
+                    metadata.sealed_size = self.rewrap_key(&metadata.sealed_size, metadata.key_epoch)?;
+                }
+            }
+            metadata.key_epoch = target_epoch;
+
+            for chunk in metadata.chunks.iter_mut().filter(|c| c.key_epoch != target_epoch) {
+                chunk.encrypted_key = self.rewrap_key(&chunk.encrypted_key, chunk.key_epoch)?;
+                if !chunk.sealed_size.is_empty() {
+                    chunk.sealed_size = self.rewrap_key(&chunk.sealed_size, chunk.key_epoch)?;
+                }
+                chunk.key_epoch = target_epoch;
+            }
+            let metadata = self.sign_metadata(metadata)?;
+
+            self.file_index.insert(file_id.clone(), metadata);
+
+            if let Some(tx) = &self.rewrap_progress_tx {
+                let _ = tx.send(RewrapProgress {
+                    file_id: file_id.clone(),
+                    files_total,
+                    files_done: done + 1,
+                    target_epoch,
+                });
+            }
+        }
+
+        Ok(RewrapSummary {
+            rewrapped: batch,
+            remaining: files_total - batch,
+        })
+    }
+
+    /// Decrypt a value sealed under an older epoch and re-seal it under the current one
+    fn rewrap_key(&self, wrapped: &[u8], epoch: u32) -> Result<Vec<u8>, StorageError> {
+        let key = self.unwrap_key(wrapped, epoch)?;
+        self.identity
+            .encrypt(&key)
+            .map_err(|e| StorageError::Encryption(e.to_string()))
+    }
+
+    /// Decrypt a value sealed under the master key of `epoch` (empty stays empty)
+    fn unwrap_key(&self, wrapped: &[u8], epoch: u32) -> Result<Zeroizing<Vec<u8>>, StorageError> {
+        if wrapped.is_empty() {
+            return Ok(Zeroizing::new(vec![]));
+        }
+        self.identity
+            .decrypt_with_epoch(wrapped, epoch)
+            .map(Zeroizing::new)
+            .map_err(|e| StorageError::Encryption(e.to_string()))
+    }
+
+    /// Seal a value under the current master key (empty stays empty)
+    fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>, StorageError> {
+        if key.is_empty() {
+            return Ok(vec![]);
+        }
+        self.identity
+            .encrypt(key)
+            .map_err(|e| StorageError::Encryption(e.to_string()))
+    }
+
+    /// Seal the keys of every indexed file to an heir as an escrow record
+    /// Hand the record to storage hosts: they release it to the heir only
+    /// after this identity sent no heartbeats for `inactivity_days`
+    pub fn create_inheritance(
+        &self,
+        heir: &IdentityDocument,
+        inactivity_days: u32,
+    ) -> Result<Signed<EscrowRecord>, StorageError> {
+        let mut files = Vec::with_capacity(self.file_index.len());
+        for signed in self.file_index.values() {
+            let metadata = &signed.payload;
+            files.push(InheritedFile {
+                metadata: signed.clone(),
+                file_key: self.unwrap_key(&metadata.encrypted_file_key, metadata.key_epoch)?.to_vec(),
+                sealed_size: self.unwrap_key(&metadata.sealed_size, metadata.key_epoch)?.to_vec(),
+                chunk_keys: metadata
+                    .chunks
+                    .iter()
+                    .map(|c| self.unwrap_key(&c.encrypted_key, c.key_epoch).map(|key| key.to_vec()))
+                    .collect::<Result<_, _>>()?,
+                chunk_sealed_sizes: metadata
+                    .chunks
+                    .iter()
+                    .map(|c| self.unwrap_key(&c.sealed_size, c.key_epoch).map(|size| size.to_vec()))
+                    .collect::<Result<_, _>>()?,
+            });
+        }
+
+        let secret = InheritedFile::to_bytes(&files)?;
+        self.identity
+            .create_escrow(heir, inactivity_days, &secret)
+            .map_err(|e| StorageError::Encryption(e.to_string()))
+    }
+
+    /// Take over the files of a released escrow record addressed to this identity
+    /// Keys are re-wrapped under this identity and the manifests re-signed,
+    /// so the files download like any other. Returns the number of files added
+    pub fn claim_inheritance(&mut self, record: &Signed<EscrowRecord>) -> Result<usize, StorageError> {
+        let secret = Zeroizing::new(
+            self.identity
+                .open_escrow(record)
+                .map_err(|e| StorageError::Encryption(e.to_string()))?,
+        );
+        let files = InheritedFile::from_bytes(&secret)?;
+
+        // Only manifests the deceased owner signed are taken over
+        let mut inherited = Vec::with_capacity(files.len());
+        for file in &files {
+            let original = file.metadata.verify_manifest()?;
+            if original.owner_id != record.payload.owner_id
+                || file.chunk_keys.len() != original.chunks.len()
+                || file.chunk_sealed_sizes.len() != original.chunks.len()
+            {
+                return Err(StorageError::InvalidManifestSignature(original.file_id.clone()));
+            }
+
+            let mut metadata = original.clone();
+            metadata.owner_id = self.identity.public_id();
+            metadata.key_epoch = self.identity.key_epoch();
+            metadata.encrypted_file_key = self.wrap_key(&file.file_key)?;
+            metadata.sealed_size = self.wrap_key(&file.sealed_size)?;
+            for ((chunk, key), sealed_size) in metadata.chunks.iter_mut().zip(&file.chunk_keys).zip(&file.chunk_sealed_sizes) {
+                chunk.encrypted_key = self.wrap_key(key)?;
+                chunk.sealed_size = self.wrap_key(sealed_size)?;
+                chunk.key_epoch = metadata.key_epoch;
+            }
+            inherited.push(self.sign_metadata(metadata)?);
+        }
+
+        let count = inherited.len();
+        for metadata in inherited {
+            self.add_to_index(metadata);
+        }
+        Ok(count)
     }
 
     /// Add file metadata to local index
-    pub fn add_to_index(&mut self, metadata: FileMetadata) {
+    pub fn add_to_index(&mut self, metadata: Signed<FileMetadata>) {
         self.file_index
-            .insert(metadata.file_id.clone(), metadata);
+            .insert(metadata.payload.file_id.clone(), metadata);
     }
 
     /// Get file metadata by ID
-    pub fn get_metadata(&self, file_id: &str) -> Option<&FileMetadata> {
+    pub fn get_metadata(&self, file_id: &str) -> Option<&Signed<FileMetadata>> {
         self.file_index.get(file_id)
     }
 
     /// List all files
-    pub fn list_files(&self) -> Vec<&FileMetadata> {
+    pub fn list_files(&self) -> Vec<&Signed<FileMetadata>> {
         self.file_index.values().collect()
     }
 
     /// List files in a folder
-    pub fn list_folder(&self, folder_id: Option<&str>) -> Vec<&FileMetadata> {
+    pub fn list_folder(&self, folder_id: Option<&str>) -> Vec<&Signed<FileMetadata>> {
         self.file_index
             .values()
-            .filter(|f| f.folder_id.as_deref() == folder_id)
+            .filter(|f| f.payload.folder_id.as_deref() == folder_id)
             .collect()
     }
 
     /// Search files by name or tags
-    pub fn search(&self, query: &str) -> Vec<&FileMetadata> {
+    pub fn search(&self, query: &str) -> Vec<&Signed<FileMetadata>> {
         let query_lower = query.to_lowercase();
         self.file_index
             .values()
             .filter(|f| {
-                f.filename.to_lowercase().contains(&query_lower)
-                    || f.tags.iter().any(|t| t.to_lowercase().contains(&query_lower))
+                f.payload.filename.to_lowercase().contains(&query_lower)
+                    || f.payload.tags.iter().any(|t| t.to_lowercase().contains(&query_lower))
             })
             .collect()
     }
 
     /// Delete file from index
-    pub fn remove_from_index(&mut self, file_id: &str) -> Option<FileMetadata> {
+    pub fn remove_from_index(&mut self, file_id: &str) -> Option<Signed<FileMetadata>> {
         self.file_index.remove(file_id)
     }
 
     /// Get total storage used
     pub fn total_storage_used(&self) -> u64 {
-        self.file_index.values().map(|f| f.size).sum()
+        self.file_index.values().map(|f| f.payload.size).sum()
     }
 
     /// Get file count
@@ -388,20 +1153,90 @@ impl FileManager {
     }
 
     /// Import index from JSON
+    /// Nothing is imported unless every entry carries a valid owner signature
     pub fn import_index(&mut self, json: &str) -> Result<usize, StorageError> {
-        let index: HashMap<String, FileMetadata> = serde_json::from_str(json)
+        let index: HashMap<String, Signed<FileMetadata>> = serde_json::from_str(json)
             .map_err(|e| StorageError::Serialization(e.to_string()))?;
 
+        for (file_id, metadata) in &index {
+            if *file_id != metadata.verify_manifest()?.file_id {
+                return Err(StorageError::InvalidManifestSignature(file_id.clone()));
+            }
+        }
+
         let count = index.len();
         self.file_index.extend(index);
         Ok(count)
     }
+
+    /// Seal the identity and the file index into a password-protected backup
+    /// `contents` supplies the devices and contacts; its file index is replaced
+    pub fn export_backup(
+        &self,
+        contents: BackupContents,
+        password: &str,
+        kdf: KdfParams,
+    ) -> Result<IdentityBackup, StorageError> {
+        let contents = BackupContents {
+            file_index: Some(self.export_index()?),
+            ..contents
+        };
+        IdentityBackup::create(&self.identity, &contents, password, kdf)
+            .map_err(|e| StorageError::Encryption(e.to_string()))
+    }
+
+    /// Restore a file manager (identity and index) from a backup
+    /// Also returns the backed up devices and contacts
+    pub fn from_backup(
+        backup: &IdentityBackup,
+        password: &str,
+        cache_path: PathBuf,
+    ) -> Result<(Self, BackupContents), StorageError> {
+        let (identity, contents) = backup
+            .restore(password)
+            .map_err(|e| StorageError::Encryption(e.to_string()))?;
+
+        let mut manager = Self::new(identity, cache_path);
+        if let Some(index) = &contents.file_index {
+            manager.import_index(index)?;
+        }
+        Ok((manager, contents))
+    }
+}
+
+/// Chunked file ready for distribution
+pub struct PreparedChunkedFile {
+    /// File metadata (with the chunk manifest)
+    pub metadata: Signed<FileMetadata>,
+
+    /// Chunks not seen before, which still need distributing
+    pub new_chunks: Vec<PreparedChunk>,
+
+    /// Number of chunks reused from already stored files
+    pub reused_chunks: usize,
+}
+
+/// New chunk ready for distribution
+pub struct PreparedChunk {
+    /// Chunk ID
+    pub chunk_id: String,
+
+    /// Encoded shards
+    pub shards: Vec<super::erasure::Shard>,
+}
+
+/// Encrypted and erasure coded unit (a whole file or a chunk)
+struct SealedUnit {
+    encrypted_hash: ContentHash,
+    encrypted_size: u64,
+    shards: Vec<super::erasure::Shard>,
+    locations: Vec<ShardLocation>,
 }
 
 /// Prepared file ready for distribution
 pub struct PreparedFile {
     /// File metadata
-    pub metadata: FileMetadata,
+    pub metadata: Signed<FileMetadata>,
 
     /// Encoded shards
     pub shards: Vec<super::erasure::Shard>,
@@ -416,6 +1251,7 @@ impl PreparedFile {
     /// Get all shard data for distribution
     pub fn shard_data(&self) -> Vec<(&str, &[u8])> {
         self.metadata
+            .payload
             .shards
             .iter()
             .zip(self.shards.iter())
@@ -427,6 +1263,7 @@ impl PreparedFile {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::ChunkerConfig;
     use tempfile::TempDir;
 
     fn create_test_identity() -> UserIdentity {
@@ -451,9 +1288,9 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(prepared.metadata.filename, "test.txt");
+        assert_eq!(prepared.metadata.payload.filename, "test.txt");
         assert_eq!(prepared.shards.len(), 14); // 10 data + 4 parity
-        assert!(prepared.metadata.size > 0);
+        assert!(prepared.metadata.payload.size > 0);
     }
 
     #[tokio::test]
@@ -490,6 +1327,38 @@ mod tests {
         assert_eq!(reconstructed, original_data.to_vec());
     }
 
+    #[tokio::test]
+    async fn test_full_cycle_xchacha() {
+        let temp_dir = TempDir::new().unwrap();
+        let identity = create_test_identity();
+
+        let original_data: Vec<u8> = (0..100_000).map(|i| (i % 256) as u8).collect();
+        let test_file = temp_dir.path().join("test.bin");
+        tokio::fs::write(&test_file, &original_data).await.unwrap();
+
+        let manager = FileManager::new(identity, temp_dir.path().to_path_buf())
+            .with_cipher_suite(CipherSuite::XChaCha20Poly1305);
+
+        let prepared = manager
+            .prepare_upload(test_file.to_str().unwrap(), "test.bin")
+            .await
+            .unwrap();
+        assert_eq!(prepared.metadata.payload.cipher_suite, CipherSuite::XChaCha20Poly1305);
+
+        let shard_data: Vec<Option<Vec<u8>>> = prepared
+            .shards
+            .iter()
+            .map(|s| Some(s.data.clone()))
+            .collect();
+
+        let reconstructed = manager
+            .reconstruct_file(&prepared.metadata, shard_data)
+            .await
+            .unwrap();
+
+        assert_eq!(reconstructed, original_data);
+    }
+
     #[tokio::test]
     async fn test_reconstruct_with_missing_shards() {
         let temp_dir = TempDir::new().unwrap();
@@ -528,4 +1397,561 @@ mod tests {
 
         assert_eq!(reconstructed, original_data);
     }
+
+    #[tokio::test]
+    async fn test_convergent_uploads_deduplicate() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("artifact.bin");
+        tokio::fs::write(&test_file, vec![7u8; 10_000]).await.unwrap();
+        let path = test_file.to_str().unwrap();
+
+        let (identity, phrase) = UserIdentity::generate(None).unwrap();
+        let same_user = UserIdentity::from_seed_phrase(&phrase, None).unwrap();
+
+        let manager = FileManager::new(identity, temp_dir.path().to_path_buf()).with_convergent_encryption();
+        let first = manager.prepare_upload(path, "artifact.bin").await.unwrap();
+        let second = FileManager::new(same_user, temp_dir.path().to_path_buf())
+            .with_convergent_encryption()
+            .prepare_upload(path, "copy.bin")
+            .await
+            .unwrap();
+
+        assert_eq!(first.metadata.payload.encryption_mode, EncryptionMode::Convergent);
+        assert_eq!(first.metadata.payload.encrypted_hash, second.metadata.payload.encrypted_hash);
+        assert_eq!(first.metadata.payload.locator, second.metadata.payload.locator);
+        assert_ne!(first.metadata.payload.locator, first.metadata.payload.file_id);
+        for (a, b) in first.shards.iter().zip(&second.shards) {
+            assert_eq!(a.data, b.data);
+        }
+
+        // Random mode never repeats
+        let random = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf())
+            .prepare_upload(path, "artifact.bin")
+            .await
+            .unwrap();
+        assert_eq!(random.metadata.payload.encryption_mode, EncryptionMode::Random);
+        assert_ne!(random.metadata.payload.encrypted_hash, first.metadata.payload.encrypted_hash);
+
+        // Convergent files reconstruct like any other
+        let shard_data = first.shards.iter().map(|s| Some(s.data.clone())).collect();
+        let reconstructed = manager.reconstruct_file(&first.metadata, shard_data).await.unwrap();
+        assert_eq!(reconstructed, vec![7u8; 10_000]);
+    }
+
+    #[tokio::test]
+    async fn test_dedup_survives_key_rotation() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("artifact.bin");
+        tokio::fs::write(&test_file, vec![7u8; 10_000]).await.unwrap();
+        let path = test_file.to_str().unwrap();
+
+        let (identity, phrase) = UserIdentity::generate(None).unwrap();
+        let before = FileManager::new(identity, temp_dir.path().to_path_buf())
+            .with_convergent_encryption()
+            .prepare_upload(path, "artifact.bin")
+            .await
+            .unwrap();
+
+        let mut rotated = UserIdentity::from_seed_phrase(&phrase, None).unwrap();
+        rotated.rotate_encryption_key().unwrap();
+        let after = FileManager::new(rotated, temp_dir.path().to_path_buf())
+            .with_convergent_encryption()
+            .prepare_upload(path, "artifact.bin")
+            .await
+            .unwrap();
+        assert_eq!(before.metadata.payload.encrypted_hash, after.metadata.payload.encrypted_hash);
+
+        // Chunks indexed before a rotation are still recognised after it
+        let mut manager = chunked_test_manager(&temp_dir);
+        let first = manager.prepare_chunked_upload(path, "artifact.bin").await.unwrap();
+        manager.add_to_index(first.metadata.clone());
+        manager.rotate_master_key().unwrap();
+
+        let second = manager.prepare_chunked_upload(path, "artifact.bin").await.unwrap();
+        assert!(second.new_chunks.is_empty());
+        assert_eq!(second.reused_chunks, first.metadata.payload.chunks.len());
+    }
+
+    #[tokio::test]
+    async fn test_team_convergence_across_users() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("dataset.csv");
+        tokio::fs::write(&test_file, b"id,value\n1,42\n").await.unwrap();
+        let path = test_file.to_str().unwrap();
+        let team_secret = EncryptionKey::new([9u8; 32]);
+
+        let alice = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf())
+            .with_team_convergence(team_secret.clone());
+        let bob = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf())
+            .with_team_convergence(team_secret);
+
+        let a = alice.prepare_upload(path, "dataset.csv").await.unwrap();
+        let b = bob.prepare_upload(path, "dataset.csv").await.unwrap();
+        assert_eq!(a.metadata.payload.encrypted_hash, b.metadata.payload.encrypted_hash);
+    }
+
+    fn chunked_test_manager(temp_dir: &TempDir) -> FileManager {
+        FileManager::new(create_test_identity(), temp_dir.path().to_path_buf()).with_chunker_config(
+            ChunkerConfig {
+                min_size: 256,
+                avg_size: 1024,
+                max_size: 4096,
+            },
+        )
+    }
+
+    fn all_chunk_shards(prepared: &PreparedChunkedFile, stored: &HashMap<String, Vec<Vec<u8>>>) -> Vec<Vec<Option<Vec<u8>>>> {
+        prepared
+            .metadata
+            .payload
+            .chunks
+            .iter()
+            .map(|c| stored[&c.chunk_id].iter().cloned().map(Some).collect())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_chunked_upload_reuses_unchanged_chunks() {
+        use rand::{RngCore, SeedableRng};
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = chunked_test_manager(&temp_dir);
+        let mut stored: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+
+        let mut original = vec![0u8; 50_000];
+        rand_chacha::ChaCha8Rng::seed_from_u64(7).fill_bytes(&mut original);
+        let test_file = temp_dir.path().join("big.bin");
+        tokio::fs::write(&test_file, &original).await.unwrap();
+
+        let first = manager
+            .prepare_chunked_upload(test_file.to_str().unwrap(), "big.bin")
+            .await
+            .unwrap();
+        assert_eq!(first.reused_chunks, 0);
+        assert_eq!(first.new_chunks.len(), first.metadata.payload.chunks.len());
+        for chunk in &first.new_chunks {
+            stored.insert(chunk.chunk_id.clone(), chunk.shards.iter().map(|s| s.data.clone()).collect());
+        }
+
+        let shards = all_chunk_shards(&first, &stored);
+        let reconstructed = manager.reconstruct_chunked_file(&first.metadata, shards).await.unwrap();
+        assert_eq!(reconstructed, original);
+        manager.add_to_index(first.metadata.clone());
+
+        // Edit one byte in the middle: only the chunk around it is new
+        let mut edited = original.clone();
+        edited[25_000] ^= 0xFF;
+        tokio::fs::write(&test_file, &edited).await.unwrap();
+
+        let second = manager
+            .prepare_chunked_upload(test_file.to_str().unwrap(), "big.bin")
+            .await
+            .unwrap();
+        assert!(second.new_chunks.len() <= 2, "{} new chunks", second.new_chunks.len());
+        assert_eq!(second.reused_chunks + second.new_chunks.len(), second.metadata.payload.chunks.len());
+        for chunk in &second.new_chunks {
+            stored.insert(chunk.chunk_id.clone(), chunk.shards.iter().map(|s| s.data.clone()).collect());
+        }
+
+        let shards = all_chunk_shards(&second, &stored);
+        let reconstructed = manager.reconstruct_chunked_file(&second.metadata, shards).await.unwrap();
+        assert_eq!(reconstructed, edited);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_upload_dedups_within_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = chunked_test_manager(&temp_dir);
+
+        // The same 4 KB block repeated is cut at max size into identical chunks
+        let test_file = temp_dir.path().join("repeated.bin");
+        tokio::fs::write(&test_file, vec![42u8; 4096 * 5]).await.unwrap();
+
+        let prepared = manager
+            .prepare_chunked_upload(test_file.to_str().unwrap(), "repeated.bin")
+            .await
+            .unwrap();
+        assert_eq!(prepared.metadata.payload.chunks.len(), 5);
+        assert_eq!(prepared.new_chunks.len(), 1);
+        assert_eq!(prepared.reused_chunks, 4);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_upload_respects_mode_compression_and_padding() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("log.txt");
+        let original: Vec<u8> = (0..2000)
+            .map(|i| format!("{} request served in {} ms\n", i, i % 97))
+            .collect::<String>()
+            .into_bytes();
+        tokio::fs::write(&test_file, &original).await.unwrap();
+        let path = test_file.to_str().unwrap();
+
+        // Random keys by default: the same file seals differently each time
+        let manager = chunked_test_manager(&temp_dir);
+        let a = manager.prepare_chunked_upload(path, "log.txt").await.unwrap();
+        let b = manager.prepare_chunked_upload(path, "log.txt").await.unwrap();
+        assert_eq!(a.metadata.payload.encryption_mode, EncryptionMode::Random);
+        assert_ne!(a.new_chunks[0].shards[0].data, b.new_chunks[0].shards[0].data);
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut manager = chunked_test_manager(&temp_dir)
+            .with_convergent_encryption()
+            .with_compression(Compression::Zstd(3))
+            .with_padding(Padding::Padme)
+            .with_upload_progress(tx);
+        let prepared = manager.prepare_chunked_upload(path, "log.txt").await.unwrap();
+
+        // Upload stats cover the new chunks
+        let progress = rx.try_recv().unwrap();
+        assert_eq!(progress.stage, UploadStage::Compressing);
+        assert_eq!(progress.total_bytes, original.len() as u64);
+        assert!(progress.saved_bytes() > 0);
+        assert!(progress.padded_bytes >= progress.compressed_bytes);
+
+        let metadata = prepared.metadata.clone();
+        assert_eq!(metadata.payload.encryption_mode, EncryptionMode::Convergent);
+        assert!(metadata.payload.chunks.iter().all(|c| c.compression == Compression::Zstd(3)));
+        assert!(metadata.payload.chunks.iter().all(|c| c.padding == Padding::Padme));
+        assert!(metadata.payload.chunks.iter().all(|c| !c.sealed_size.is_empty()));
+        assert!(metadata.payload.size < original.len() as u64);
+        assert_eq!(manager.original_size(&metadata.payload).unwrap(), original.len() as u64);
+
+        let stored: HashMap<String, Vec<Vec<u8>>> = prepared
+            .new_chunks
+            .iter()
+            .map(|c| (c.chunk_id.clone(), c.shards.iter().map(|s| s.data.clone()).collect()))
+            .collect();
+        let shards = all_chunk_shards(&prepared, &stored);
+        assert_eq!(manager.reconstruct_chunked_file(&metadata, shards).await.unwrap(), original);
+
+        // Sealed chunk sizes follow the chunk keys through a rotation
+        manager.add_to_index(metadata);
+        manager.rotate_master_key().unwrap();
+        manager.rewrap_file_keys(None).unwrap();
+        let rewrapped = manager.get_metadata(&prepared.metadata.payload.file_id).unwrap().clone();
+        let shards = all_chunk_shards(&prepared, &stored);
+        assert_eq!(manager.reconstruct_chunked_file(&rewrapped, shards).await.unwrap(), original);
+    }
+
+    #[tokio::test]
+    async fn test_rotate_and_rewrap_file_keys() {
+        let temp_dir = TempDir::new().unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut manager = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf())
+            .with_rewrap_progress(tx);
+
+        let mut uploads = Vec::new();
+        for i in 0..3 {
+            let test_file = temp_dir.path().join(format!("file{}.txt", i));
+            tokio::fs::write(&test_file, format!("file number {}", i)).await.unwrap();
+            let prepared = manager
+                .prepare_upload(test_file.to_str().unwrap(), "file.txt")
+                .await
+                .unwrap();
+            manager.add_to_index(prepared.metadata.clone());
+            uploads.push(prepared);
+        }
+
+        assert_eq!(manager.rotate_master_key().unwrap(), 1);
+
+        // Interrupted after one file, then resumed
+        let first = manager.rewrap_file_keys(Some(1)).unwrap();
+        assert_eq!(first, RewrapSummary { rewrapped: 1, remaining: 2 });
+        let rest = manager.rewrap_file_keys(None).unwrap();
+        assert_eq!(rest, RewrapSummary { rewrapped: 2, remaining: 0 });
+        assert_eq!(manager.rewrap_file_keys(None).unwrap().rewrapped, 0);
+
+        let progress: Vec<RewrapProgress> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert_eq!(progress.len(), 3);
+        assert!(progress.iter().all(|p| p.target_epoch == 1));
+
+        // Every file is now wrapped under epoch 1 and still downloads
+        for (i, prepared) in uploads.iter().enumerate() {
+            let metadata = manager.get_metadata(&prepared.metadata.payload.file_id).unwrap().clone();
+            assert_eq!(metadata.payload.key_epoch, 1);
+            assert_ne!(metadata.payload.encrypted_file_key, prepared.metadata.payload.encrypted_file_key);
+
+            let shard_data = prepared.shards.iter().map(|s| Some(s.data.clone())).collect();
+            let data = manager.reconstruct_file(&metadata, shard_data).await.unwrap();
+            assert_eq!(data, format!("file number {}", i).into_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signed_manifest_survives_index_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("test.txt");
+        tokio::fs::write(&test_file, b"signed manifest").await.unwrap();
+
+        let mut manager = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf());
+        let prepared = manager
+            .prepare_upload(test_file.to_str().unwrap(), "test.txt")
+            .await
+            .unwrap();
+        prepared.metadata.verify_manifest().unwrap();
+
+        // Filling in peers requires re-signing
+        let mut metadata = prepared.metadata.clone();
+        metadata.payload.shards[0].peers.push("peer-a".into());
+        assert!(matches!(
+            metadata.verify_manifest(),
+            Err(StorageError::InvalidManifestSignature(_))
+        ));
+        let metadata = manager.sign_metadata(metadata.payload).unwrap();
+        manager.add_to_index(metadata);
+
+        let json = manager.export_index().unwrap();
+        let mut other = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf());
+        assert_eq!(other.import_index(&json).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_backup_restores_identity_and_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("test.txt");
+        tokio::fs::write(&test_file, b"backed up").await.unwrap();
+
+        let mut manager = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf());
+        let prepared = manager
+            .prepare_upload(test_file.to_str().unwrap(), "test.txt")
+            .await
+            .unwrap();
+        manager.add_to_index(prepared.metadata.clone());
+
+        let kdf = KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let backup = manager
+            .export_backup(BackupContents::default(), "backup password", kdf)
+            .unwrap();
+        assert!(!backup.to_json().unwrap().contains("test.txt"));
+
+        let (restored, _) =
+            FileManager::from_backup(&backup, "backup password", temp_dir.path().to_path_buf()).unwrap();
+        assert_eq!(restored.file_count(), 1);
+
+        let data = restored
+            .reconstruct_file(&prepared.metadata, prepared.shards.iter().map(|s| Some(s.data.clone())).collect())
+            .await
+            .unwrap();
+        assert_eq!(data, b"backed up");
+    }
+
+    #[tokio::test]
+    async fn test_tampered_or_unsigned_manifest_rejected() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("test.txt");
+        tokio::fs::write(&test_file, b"signed manifest").await.unwrap();
+
+        let mut manager = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf());
+        let prepared = manager
+            .prepare_upload(test_file.to_str().unwrap(), "test.txt")
+            .await
+            .unwrap();
+        let shard_data = || prepared.shards.iter().map(|s| Some(s.data.clone())).collect();
+
+        // Shards pointed at an attacker peer
+        let mut redirected = prepared.metadata.clone();
+        redirected.payload.shards[0].peers = vec!["attacker".into()];
+        assert!(matches!(
+            manager.reconstruct_file(&redirected, shard_data()).await,
+            Err(StorageError::InvalidManifestSignature(_))
+        ));
+
+        // Re-signed by someone who is not the owner
+        let attacker = create_test_identity();
+        let forged = Signed::sign(
+            FileMetadata {
+                owner_key: attacker.signing_keys().verifying_key.as_bytes().to_vec(),
+                ..redirected.payload
+            },
+            attacker.signing_keys(),
+        )
+        .unwrap();
+        assert!(matches!(
+            forged.verify_manifest(),
+            Err(StorageError::InvalidManifestSignature(_))
+        ));
+
+        let mut unsigned = prepared.metadata.clone();
+        unsigned.signature.clear();
+        assert!(matches!(
+            manager.reconstruct_file(&unsigned, shard_data()).await,
+            Err(StorageError::UnsignedManifest(_))
+        ));
+
+        let index = HashMap::from([(unsigned.payload.file_id.clone(), unsigned)]);
+        let json = serde_json::to_string(&index).unwrap();
+        assert!(manager.import_index(&json).is_err());
+        assert_eq!(manager.file_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_upload_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let manager = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf())
+            .with_compression(Compression::Zstd(3))
+            .with_upload_progress(tx);
+
+        let original_data: Vec<u8> = (0..5000)
+            .map(|i| format!("{},2024-01-01T00:00:{:02},INFO request served\n", i, i % 60))
+            .collect::<String>()
+            .into_bytes();
+        let test_file = temp_dir.path().join("export.csv");
+        tokio::fs::write(&test_file, &original_data).await.unwrap();
+
+        let prepared = manager
+            .prepare_upload(test_file.to_str().unwrap(), "export.csv")
+            .await
+            .unwrap();
+        assert_eq!(prepared.metadata.payload.compression, Compression::Zstd(3));
+        assert!(prepared.metadata.payload.encrypted_size * 5 < original_data.len() as u64);
+
+        let progress = rx.try_recv().unwrap();
+        assert_eq!(progress.stage, UploadStage::Compressing);
+        assert_eq!(progress.total_bytes, original_data.len() as u64);
+        assert!(progress.saved_bytes() > 0);
+
+        let shard_data = prepared.shards.iter().map(|s| Some(s.data.clone())).collect();
+        let reconstructed = manager.reconstruct_file(&prepared.metadata, shard_data).await.unwrap();
+        assert_eq!(reconstructed, original_data);
+
+        // Already-compressed formats are stored as is
+        let image_file = temp_dir.path().join("photo.jpg");
+        tokio::fs::write(&image_file, &original_data).await.unwrap();
+        let prepared = manager
+            .prepare_upload(image_file.to_str().unwrap(), "photo.jpg")
+            .await
+            .unwrap();
+        assert_eq!(prepared.metadata.payload.compression, Compression::None);
+    }
+
+    #[tokio::test]
+    async fn test_padded_upload_hides_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut manager = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf())
+            .with_padding(Padding::Padme)
+            .with_upload_progress(tx);
+
+        let original_data = vec![7u8; 1000];
+        let test_file = temp_dir.path().join("test.bin");
+        tokio::fs::write(&test_file, &original_data).await.unwrap();
+
+        let prepared = manager
+            .prepare_upload(test_file.to_str().unwrap(), "test.bin")
+            .await
+            .unwrap();
+        let metadata = prepared.metadata.clone();
+        assert_eq!(metadata.payload.padding, Padding::Padme);
+        assert_eq!(metadata.payload.size, 1024);
+        assert!(!metadata.payload.sealed_size.is_empty());
+        assert_eq!(manager.original_size(&metadata.payload).unwrap(), 1000);
+
+        let progress = rx.try_recv().unwrap();
+        assert_eq!(progress.padding_overhead(), 24);
+
+        let shard_data = || prepared.shards.iter().map(|s| Some(s.data.clone())).collect();
+        assert_eq!(manager.reconstruct_file(&metadata, shard_data()).await.unwrap(), original_data);
+
+        // The sealed size follows the file key through a rotation
+        manager.add_to_index(metadata);
+        manager.rotate_master_key().unwrap();
+        manager.rewrap_file_keys(None).unwrap();
+        let rewrapped = manager.get_metadata(&prepared.metadata.payload.file_id).unwrap().clone();
+        assert_eq!(manager.original_size(&rewrapped.payload).unwrap(), 1000);
+        assert_eq!(manager.reconstruct_file(&rewrapped, shard_data()).await.unwrap(), original_data);
+    }
+
+    #[tokio::test]
+    async fn test_published_metadata_hides_private_fields() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("test.txt");
+        tokio::fs::write(&test_file, b"quarterly numbers").await.unwrap();
+
+        let mut manager = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf());
+        let other_manager = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf());
+
+        let prepared = manager
+            .prepare_upload(test_file.to_str().unwrap(), "secret-plans.txt")
+            .await
+            .unwrap();
+        let metadata = manager
+            .sign_metadata(FileMetadata {
+                folder_id: Some("acquisitions".into()),
+                tags: vec!["confidential".into()],
+                ..prepared.metadata.payload.clone()
+            })
+            .unwrap();
+
+        let published = manager.publish_metadata(&metadata).unwrap();
+        let json = serde_json::to_string(&published).unwrap();
+        let metadata = &metadata.payload;
+        assert_eq!(published.payload.routing.locator, metadata.locator);
+        assert_ne!(published.payload.routing.locator, metadata.file_id);
+        for private in ["secret-plans", "text/plain", "acquisitions", "confidential", &metadata.file_id] {
+            assert!(!json.contains(private), "{} leaked", private);
+        }
+
+        // Anyone can check the routing part; only the owner can open it
+        published.verify_published().unwrap();
+        assert!(other_manager.open_metadata(&published).is_err());
+
+        let opened = manager.open_metadata(&published).unwrap();
+        opened.verify_manifest().unwrap();
+        assert_eq!(opened.payload.filename, "secret-plans.txt");
+        assert_eq!(opened.payload.tags, metadata.tags);
+
+        // Search works on the opened local copy
+        manager.add_to_index(opened);
+        assert_eq!(manager.search("secret").len(), 1);
+        assert_eq!(manager.search("confidential").len(), 1);
+
+        // Routing changes by a host are caught
+        let mut tampered = published;
+        tampered.payload.routing.shards[0].peers = vec!["attacker".into()];
+        assert!(matches!(
+            tampered.verify_published(),
+            Err(StorageError::InvalidManifestSignature(_))
+        ));
+        assert!(manager.open_metadata(&tampered).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_heir_claims_inherited_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("will.txt");
+        tokio::fs::write(&test_file, b"my last will").await.unwrap();
+
+        let mut owner = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf())
+            .with_padding(Padding::Padme);
+        let prepared = owner
+            .prepare_upload(test_file.to_str().unwrap(), "will.txt")
+            .await
+            .unwrap();
+        owner.add_to_index(prepared.metadata.clone());
+        owner.rotate_master_key().unwrap();
+
+        let (heir_identity, _) = UserIdentity::generate(None).unwrap();
+        let heir_document = heir_identity.identity_document(None, vec![], vec![]).unwrap();
+        let record = owner.create_inheritance(&heir_document, 365).unwrap();
+
+        // A stranger cannot claim the record
+        let mut stranger = FileManager::new(create_test_identity(), temp_dir.path().to_path_buf());
+        assert!(stranger.claim_inheritance(&record).is_err());
+
+        let mut heir = FileManager::new(heir_identity, temp_dir.path().to_path_buf());
+        assert_eq!(heir.claim_inheritance(&record).unwrap(), 1);
+
+        let metadata = heir.get_metadata(&prepared.metadata.payload.file_id).unwrap().clone();
+        assert_eq!(metadata.payload.owner_id, heir_document.payload.owner_id);
+        assert_eq!(heir.original_size(&metadata.payload).unwrap(), 12);
+
+        let shard_data = prepared.shards.iter().map(|s| Some(s.data.clone())).collect();
+        assert_eq!(heir.reconstruct_file(&metadata, shard_data).await.unwrap(), b"my last will");
+    }
 }
@@ -0,0 +1,56 @@
+//! Handing files on to heirs
+//!
+//! `FileManager::create_inheritance` unwraps the keys of every indexed file
+//! and seals them, together with the manifests, into an escrow record for an
+//! heir (see `identity::EscrowRecord`). Once hosts release the record,
+//! `FileManager::claim_inheritance` re-wraps the keys under the heir's own
+//! identity.
+
+use super::file_manager::FileMetadata;
+use super::StorageError;
+use crate::crypto::Signed;
+
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+/// One file as handed to an heir: the owner's manifest and its unwrapped secrets
+#[derive(Serialize, Deserialize)]
+pub(super) struct InheritedFile {
+    /// Manifest as signed by the owner
+    pub(super) metadata: Signed<FileMetadata>,
+
+    /// File key (empty for chunked files)
+    pub(super) file_key: Vec<u8>,
+
+    /// Plaintext of `metadata.sealed_size` (empty for unpadded files)
+    pub(super) sealed_size: Vec<u8>,
+
+    /// Chunk keys, in manifest order
+    pub(super) chunk_keys: Vec<Vec<u8>>,
+
+    /// Plaintext of each chunk's `sealed_size`, in manifest order
+    pub(super) chunk_sealed_sizes: Vec<Vec<u8>>,
+}
+
+impl Drop for InheritedFile {
+    fn drop(&mut self) {
+        self.file_key.zeroize();
+        self.sealed_size.zeroize();
+        self.chunk_keys.iter_mut().for_each(|key| key.zeroize());
+        self.chunk_sealed_sizes.iter_mut().for_each(|size| size.zeroize());
+    }
+}
+
+impl InheritedFile {
+    /// Serialize a set of files for sealing
+    pub(super) fn to_bytes(files: &[InheritedFile]) -> Result<Zeroizing<Vec<u8>>, StorageError> {
+        bincode::serialize(files)
+            .map(Zeroizing::new)
+            .map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+
+    /// Parse a set of files from an opened escrow
+    pub(super) fn from_bytes(bytes: &[u8]) -> Result<Vec<InheritedFile>, StorageError> {
+        bincode::deserialize(bytes).map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+}
@@ -0,0 +1,224 @@
+//! Public/private split of file metadata
+//!
+//! Only the routing part of `FileMetadata` (locator, shard locations, erasure
+//! config, sizes) is readable by the network. The content hash, file names,
+//! types, tags, folders and sharing details travel sealed under the owner's
+//! key, and the owner keeps the decrypted `FileMetadata` locally. Both are
+//! signed as `Signed<T>` envelopes that name the owner's key in the payload,
+//! so anyone can check them without looking the owner up.
+
+use super::compression::Compression;
+use super::file_manager::{ChunkRef, EncryptionMode, FileMetadata, ShardLocation};
+use super::padding::Padding;
+use super::{ErasureConfig, StorageError};
+use crate::crypto::{CipherSuite, Signed, SignedPayload, SigningKeyPair};
+
+use serde::{Deserialize, Serialize};
+
+/// Metadata as published to the DHT, signed by the owner as `Signed<PublishedMetadata>`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishedMetadata {
+    /// Public routing part
+    pub routing: RoutingMetadata,
+
+    /// Private part, sealed under the owner's key
+    pub sealed_private: Vec<u8>,
+
+    /// Owner's Ed25519 public key (must match `routing.owner_id`)
+    pub owner_key: Vec<u8>,
+}
+
+impl SignedPayload for PublishedMetadata {
+    const CONTEXT: &'static str = "published metadata";
+}
+
+/// What storage hosts and DHT observers need to locate a file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingMetadata {
+    /// Network file ID (see `FileMetadata::locator`)
+    pub locator: String,
+    pub size: u64,
+    pub encrypted_hash: String,
+    pub cipher_suite: CipherSuite,
+    pub encrypted_size: u64,
+    pub erasure_config: ErasureConfig,
+    pub shards: Vec<ShardLocation>,
+    pub chunks: Vec<ChunkRef>,
+    pub owner_id: String,
+    pub key_epoch: u32,
+}
+
+/// Everything in `FileMetadata` that is not needed for routing
+#[derive(Serialize, Deserialize)]
+pub(super) struct PrivateMetadata {
+    file_id: String,
+    filename: String,
+    sealed_size: Vec<u8>,
+    mime_type: String,
+    encryption_mode: EncryptionMode,
+    compression: Compression,
+    padding: Padding,
+    created_at: i64,
+    modified_at: i64,
+    is_shared: bool,
+    shared_with: Vec<String>,
+    encrypted_file_key: Vec<u8>,
+    folder_id: Option<String>,
+    tags: Vec<String>,
+}
+
+impl PrivateMetadata {
+    /// Encode for sealing
+    pub(super) fn to_bytes(&self) -> Result<Vec<u8>, StorageError> {
+        bincode::serialize(self).map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+
+    /// Decode after unsealing
+    pub(super) fn from_bytes(bytes: &[u8]) -> Result<Self, StorageError> {
+        bincode::deserialize(bytes).map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+}
+
+impl FileMetadata {
+    /// Split into the routing part and the private part
+    pub(super) fn split(&self) -> (RoutingMetadata, PrivateMetadata) {
+        let metadata = self.clone();
+        let routing = RoutingMetadata {
+            locator: metadata.locator,
+            size: metadata.size,
+            encrypted_hash: metadata.encrypted_hash,
+            cipher_suite: metadata.cipher_suite,
+            encrypted_size: metadata.encrypted_size,
+            erasure_config: metadata.erasure_config,
+            shards: metadata.shards,
+            chunks: metadata.chunks,
+            owner_id: metadata.owner_id,
+            key_epoch: metadata.key_epoch,
+        };
+        let private = PrivateMetadata {
+            file_id: metadata.file_id,
+            filename: metadata.filename,
+            sealed_size: metadata.sealed_size,
+            mime_type: metadata.mime_type,
+            encryption_mode: metadata.encryption_mode,
+            compression: metadata.compression,
+            padding: metadata.padding,
+            created_at: metadata.created_at,
+            modified_at: metadata.modified_at,
+            is_shared: metadata.is_shared,
+            shared_with: metadata.shared_with,
+            encrypted_file_key: metadata.encrypted_file_key,
+            folder_id: metadata.folder_id,
+            tags: metadata.tags,
+        };
+        (routing, private)
+    }
+
+    /// Reassemble from the routing part and the private part
+    pub(super) fn join(routing: RoutingMetadata, private: PrivateMetadata, owner_key: Vec<u8>) -> Self {
+        FileMetadata {
+            file_id: private.file_id,
+            locator: routing.locator,
+            filename: private.filename,
+            size: routing.size,
+            sealed_size: private.sealed_size,
+            mime_type: private.mime_type,
+            encrypted_hash: routing.encrypted_hash,
+            cipher_suite: routing.cipher_suite,
+            encrypted_size: routing.encrypted_size,
+            encryption_mode: private.encryption_mode,
+            compression: private.compression,
+            padding: private.padding,
+            erasure_config: routing.erasure_config,
+            shards: routing.shards,
+            chunks: routing.chunks,
+            created_at: private.created_at,
+            modified_at: private.modified_at,
+            owner_id: routing.owner_id,
+            is_shared: private.is_shared,
+            shared_with: private.shared_with,
+            encrypted_file_key: private.encrypted_file_key,
+            key_epoch: routing.key_epoch,
+            folder_id: private.folder_id,
+            tags: private.tags,
+            owner_key,
+        }
+    }
+}
+
+impl PublishedMetadata {
+    /// Sign the routing part and sealed private part as the owner
+    pub(super) fn sign(
+        routing: RoutingMetadata,
+        sealed_private: Vec<u8>,
+        keys: &SigningKeyPair,
+    ) -> Result<Signed<Self>, StorageError> {
+        let published = Self {
+            routing,
+            sealed_private,
+            owner_key: keys.verifying_key.as_bytes().to_vec(),
+        };
+        Signed::sign(published, keys).map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+}
+
+impl Signed<PublishedMetadata> {
+    /// Check the owner signature (anyone can do this without the owner's key)
+    pub fn verify_published(&self) -> Result<&PublishedMetadata, StorageError> {
+        let published = &self.payload;
+        let locator = &published.routing.locator;
+        if self.signature.is_empty() {
+            return Err(StorageError::UnsignedManifest(locator.clone()));
+        }
+        self.verify_by_owner(&published.owner_key, &published.routing.owner_id)
+            .map_err(|_| StorageError::InvalidManifestSignature(locator.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::UserIdentity;
+
+    #[test]
+    fn test_split_join_roundtrip() {
+        let (identity, _) = UserIdentity::generate(None).unwrap();
+        let metadata: FileMetadata = serde_json::from_value(serde_json::json!({
+            "file_id": "file",
+            "locator": "locator",
+            "filename": "taxes 2024.pdf",
+            "size": 42,
+            "mime_type": "application/pdf",
+            "encrypted_hash": "hash",
+            "erasure_config": ErasureConfig::default(),
+            "shards": [],
+            "created_at": 1,
+            "modified_at": 2,
+            "owner_id": identity.public_id(),
+            "is_shared": true,
+            "shared_with": ["friend"],
+            "encrypted_file_key": [1, 2, 3],
+            "folder_id": "finance",
+            "tags": ["private"],
+        }))
+        .unwrap();
+        let metadata = Signed::sign(
+            FileMetadata {
+                owner_key: identity.signing_keys().verifying_key.as_bytes().to_vec(),
+                ..metadata
+            },
+            identity.signing_keys(),
+        )
+        .unwrap();
+
+        let (routing, private) = metadata.payload.split();
+        let private = PrivateMetadata::from_bytes(&private.to_bytes().unwrap()).unwrap();
+        let joined = Signed {
+            payload: FileMetadata::join(routing, private, metadata.payload.owner_key.clone()),
+            ..metadata.clone()
+        };
+
+        assert_eq!(joined.signing_bytes().unwrap(), metadata.signing_bytes().unwrap());
+        joined.verify_manifest().unwrap();
+    }
+}
@@ -2,12 +2,21 @@
 //!
 //! Handles file encryption, fragmentation, and erasure coding for redundancy.
 
+mod chunker;
+mod compression;
 mod erasure;
 mod file_manager;
+mod inheritance;
+mod metadata;
+mod padding;
 mod quota;
 
+pub use chunker::{Chunker, ChunkerConfig};
+pub use compression::Compression;
 pub use erasure::{ErasureEncoder, ErasureDecoder, ErasureConfig};
-pub use file_manager::{FileManager, FileMetadata, UploadProgress, DownloadProgress};
+pub use file_manager::{EncryptionMode, FileManager, FileMetadata, UploadProgress, DownloadProgress};
+pub use metadata::{PublishedMetadata, RoutingMetadata};
+pub use padding::Padding;
 pub use quota::{QuotaManager, QuotaConfig, UserQuota, QuotaCheckResult, QuotaSummary, NetworkStats};
 
 use thiserror::Error;
@@ -37,4 +46,16 @@ pub enum StorageError {
 
     #[error("Quota exceeded: {0}")]
     QuotaExceeded(String),
+
+    #[error("Compression error: {0}")]
+    Compression(String),
+
+    #[error("Padding error: {0}")]
+    Padding(String),
+
+    #[error("Unsigned file manifest: {0}")]
+    UnsignedManifest(String),
+
+    #[error("Invalid file manifest signature: {0}")]
+    InvalidManifestSignature(String),
 }
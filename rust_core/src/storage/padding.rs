@@ -0,0 +1,118 @@
+//! Size-hiding padding
+//!
+//! Pads file contents before encryption so ciphertext and shard sizes only
+//! reveal a size bucket, not the exact plaintext length. The padding is an
+//! 0x80 marker followed by zeros, so it can be stripped without knowing the
+//! original length.
+
+use super::StorageError;
+
+use serde::{Deserialize, Serialize};
+
+/// Marker byte that starts the padding
+const PADDING_MARKER: u8 = 0x80;
+
+/// Padding scheme applied before encryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Padding {
+    /// No padding (exact size visible)
+    #[default]
+    None,
+
+    /// Padmé: at most ~12% overhead, leaks O(log log n) bits of the size
+    Padme,
+
+    /// Next power of two: at most 100% overhead, leaks O(log n) bits
+    PowerOfTwo,
+}
+
+impl Padding {
+    /// Padded length for `len` bytes of content (including the marker)
+    pub fn padded_len(self, len: usize) -> usize {
+        match self {
+            Padding::None => len,
+            Padding::Padme => padme(len + 1),
+            Padding::PowerOfTwo => (len + 1).next_power_of_two(),
+        }
+    }
+
+    /// Pad data
+    pub fn pad(self, data: &[u8]) -> Vec<u8> {
+        if self == Padding::None {
+            return data.to_vec();
+        }
+
+        let mut padded = Vec::with_capacity(self.padded_len(data.len()));
+        padded.extend_from_slice(data);
+        padded.push(PADDING_MARKER);
+        padded.resize(self.padded_len(data.len()), 0);
+        padded
+    }
+
+    /// Strip the padding added by `pad`
+    pub fn unpad(self, mut padded: Vec<u8>) -> Result<Vec<u8>, StorageError> {
+        if self == Padding::None {
+            return Ok(padded);
+        }
+
+        let marker = padded
+            .iter()
+            .rposition(|&b| b != 0)
+            .filter(|&i| padded[i] == PADDING_MARKER)
+            .ok_or_else(|| StorageError::Padding("Padding marker not found".into()))?;
+        padded.truncate(marker);
+        Ok(padded)
+    }
+}
+
+/// Padmé length: keep only the top bits of `len`, rounding up
+fn padme(len: usize) -> usize {
+    if len < 2 {
+        return len;
+    }
+    let exponent = usize::BITS - 1 - len.leading_zeros();
+    let exponent_bits = u32::BITS - exponent.leading_zeros();
+    let low_bits = exponent - exponent_bits;
+    let mask = (1usize << low_bits) - 1;
+    (len + mask) & !mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padme_lengths() {
+        let lengths: Vec<usize> = [0, 1, 9, 100, 1000, 1_000_000]
+            .iter()
+            .map(|&len| Padding::Padme.padded_len(len))
+            .collect();
+        assert_eq!(lengths, vec![1, 2, 10, 104, 1024, 1_015_808]);
+
+        // Overhead stays within 12%
+        for len in (1000..200_000).step_by(997) {
+            let padded = Padding::Padme.padded_len(len);
+            assert!(padded > len && padded as f64 <= len as f64 * 1.12 + 1.0);
+        }
+
+        assert_eq!(Padding::PowerOfTwo.padded_len(1000), 1024);
+        assert_eq!(Padding::PowerOfTwo.padded_len(1024), 2048);
+    }
+
+    #[test]
+    fn test_pad_unpad_roundtrip() {
+        for padding in [Padding::None, Padding::Padme, Padding::PowerOfTwo] {
+            for data in [vec![], vec![0u8; 100], vec![PADDING_MARKER; 37], b"file ends in zeros\0\0".to_vec()] {
+                let padded = padding.pad(&data);
+                assert_eq!(padded.len(), padding.padded_len(data.len()));
+                assert_eq!(padding.unpad(padded).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unpad_rejects_missing_marker() {
+        assert!(Padding::Padme.unpad(vec![1, 2, 3, 0, 0]).is_err());
+        assert!(Padding::Padme.unpad(vec![0; 8]).is_err());
+    }
+}
@@ -149,12 +149,14 @@ impl QuotaManager {
 
     /// Check if user can upload
     pub fn can_upload(&mut self, user_id: &str, size: u64) -> QuotaCheckResult {
+        let config = self.config.clone();
         let quota = self.get_user_quota(user_id);
-        quota.check_grace_period(&self.config);
+        quota.check_grace_period(&config);
 
-        if quota.can_upload(size, &self.config) {
+        if quota.can_upload(size, &config) {
             QuotaCheckResult::Allowed
         } else {
+            let quota = quota.clone();
             let needed = self.calculate_needed_contribution(user_id, size);
             QuotaCheckResult::InsufficientQuota {
                 current_contribution: quota.bytes_contributed,
@@ -251,18 +253,20 @@ impl QuotaManager {
 
     /// Get user's quota summary
     pub fn get_quota_summary(&mut self, user_id: &str) -> QuotaSummary {
+        let config = self.config.clone();
         let quota = self.get_user_quota(user_id);
-        quota.check_grace_period(&self.config);
+        quota.check_grace_period(&config);
+        let config = &config;
 
         QuotaSummary {
             bytes_used: quota.bytes_used,
             bytes_contributed: quota.bytes_contributed,
-            bytes_available: quota.available_storage(&self.config),
-            usage_percentage: quota.usage_percentage(&self.config),
+            bytes_available: quota.available_storage(config),
+            usage_percentage: quota.usage_percentage(config),
             files_count: quota.files_count,
             shards_hosted: quota.shards_hosted,
             in_grace_period: quota.in_grace_period,
-            contribution_ratio: self.config.contribution_ratio,
+            contribution_ratio: config.contribution_ratio,
         }
     }
 }